@@ -0,0 +1,14 @@
+-- Migración de los importes INT del esquema anterior a BIGINT + moneda. Las
+-- columnas INT ya estaban en céntimos (120000 = 1200.00) así que basta con
+-- ensanchar el tipo; en tablas ya migradas las sentencias no cambian nada.
+-- No se ejecuta al arrancar: se lanza una vez contra la base de datos antigua.
+BEGIN;
+
+ALTER TABLE articulos ALTER COLUMN precio TYPE BIGINT;
+ALTER TABLE articulos ADD COLUMN IF NOT EXISTS moneda VARCHAR(3) NOT NULL DEFAULT 'EUR';
+ALTER TABLE pedidos ALTER COLUMN total TYPE BIGINT;
+ALTER TABLE pedidos ADD COLUMN IF NOT EXISTS moneda VARCHAR(3) NOT NULL DEFAULT 'EUR';
+ALTER TABLE pedidos_detalles ALTER COLUMN precio_unitario TYPE BIGINT;
+ALTER TABLE pedidos_detalles ALTER COLUMN subtotal TYPE BIGINT;
+
+COMMIT;
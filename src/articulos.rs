@@ -1,34 +1,70 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
-use sqlx::{Decode, FromRow};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::Validate;
+
+use crate::money::{Money, money_from_row};
 
-#[derive(Serialize, Deserialize, Clone, FromRow, Decode, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
 pub struct ArticuloRequest {
     pub id: i32,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
     pub nombre: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
     pub descripcion: Option<String>,
-    pub precio: i32,
+    #[validate(custom(function = "crate::validacion::validate_non_negative_money"))]
+    pub precio: Money,
+    #[validate(range(min = 0, message = "no puede ser negativo"))]
     pub stock: i32,
+    #[serde(default = "crate::iva::tipo_iva_por_defecto")]
+    #[validate(custom(function = "crate::iva::validate_tipo_iva"))]
+    pub tipo_iva: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, FromRow, Decode)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Articulo {
     pub id: i32,
     pub nombre: String,
     pub descripcion: Option<String>,
-    pub precio: i32,
+    pub precio: Money,
     pub stock: i32,
+    pub tipo_iva: String,
     pub fecha_creacion: chrono::NaiveDateTime,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+}
+
+// precio y moneda son dos columnas que se leen como un único Money
+impl<'r> FromRow<'r, PgRow> for Articulo {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Articulo {
+            id: row.try_get("id")?,
+            nombre: row.try_get("nombre")?,
+            descripcion: row.try_get("descripcion")?,
+            precio: money_from_row(row, "precio", "moneda")?,
+            stock: row.try_get("stock")?,
+            tipo_iva: row.try_get("tipo_iva")?,
+            fecha_creacion: row.try_get("fecha_creacion")?,
+            deleted_at: row.try_get("deleted_at")?,
+        })
+    }
 }
 
 pub async fn postgres_get_articulos(
     pool: &sqlx::Pool<sqlx::Postgres>,
+    include_deleted: bool,
 ) -> Result<Vec<Articulo>, sqlx::Error> {
     let articulos = sqlx::query_as::<_, Articulo>(
         "
         SELECT
-            id, nombre, descripcion, precio, stock, fecha_creacion
-        FROM articulos",
+            id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at
+        FROM articulos
+        WHERE $1 OR deleted_at IS NULL
+        ORDER BY id",
     )
+    .bind(include_deleted)
     .fetch_all(pool)
     .await?;
 
@@ -42,7 +78,7 @@ pub async fn postgres_get_articulo_by_id(
     let articulo = sqlx::query_as::<_, Articulo>(
         "
         SELECT
-            id, nombre, descripcion, precio, stock, fecha_creacion
+            id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at
         FROM articulos
         WHERE id = $1",
     )
@@ -59,15 +95,17 @@ pub async fn postgres_create_articulo(
 ) -> Result<Articulo, sqlx::Error> {
     let new_articulo = sqlx::query_as::<_, Articulo>(
         r#"
-        INSERT INTO articulos (nombre, descripcion, precio, stock, fecha_creacion)
-        VALUES ($1, $2, $3, $4, $5)
-        RETURNING id, nombre, descripcion, precio, stock, fecha_creacion
+        INSERT INTO articulos (nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at
         "#,
     )
     .bind(articulo.nombre)
     .bind(articulo.descripcion)
-    .bind(articulo.precio)
+    .bind(articulo.precio.amount_minor())
+    .bind(articulo.precio.currency().code())
     .bind(articulo.stock)
+    .bind(articulo.tipo_iva)
     .bind(chrono::Utc::now().naive_utc())
     .fetch_one(pool)
     .await?;
@@ -83,18 +121,202 @@ pub async fn postgres_update_articulo(
     let updated_articulo = sqlx::query_as::<_, Articulo>(
         r#"
         UPDATE articulos
-        SET nombre = $1, descripcion = $2, precio = $3, stock = $4
-        WHERE id = $5
-        RETURNING id, nombre, descripcion, precio, stock, fecha_creacion
+        SET nombre = $1, descripcion = $2, precio = $3, moneda = $4, stock = $5, tipo_iva = $6
+        WHERE id = $7
+        RETURNING id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at
         "#,
     )
     .bind(articulo.nombre)
     .bind(articulo.descripcion)
-    .bind(articulo.precio)
+    .bind(articulo.precio.amount_minor())
+    .bind(articulo.precio.currency().code())
     .bind(articulo.stock)
+    .bind(articulo.tipo_iva)
     .bind(id)
     .fetch_one(pool)
     .await?;
 
     Ok(updated_articulo)
 }
+
+#[derive(Deserialize, Debug, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct ArticuloPatch {
+    #[serde(default, deserialize_with = "crate::mergepatch::required_trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: Option<String>,
+    #[serde(default, deserialize_with = "crate::mergepatch::nullable_trimmed")]
+    pub descripcion: Option<Option<String>>,
+    #[serde(default, deserialize_with = "crate::mergepatch::required")]
+    #[validate(custom(function = "crate::validacion::validate_non_negative_money"))]
+    pub precio: Option<Money>,
+    #[serde(default, deserialize_with = "crate::mergepatch::required")]
+    #[validate(range(min = 0, message = "no puede ser negativo"))]
+    pub stock: Option<i32>,
+    #[serde(default, deserialize_with = "crate::mergepatch::required_trimmed")]
+    #[validate(custom(function = "crate::iva::validate_tipo_iva"))]
+    pub tipo_iva: Option<String>,
+}
+
+impl ArticuloPatch {
+    pub fn is_empty(&self) -> bool {
+        self.nombre.is_none()
+            && self.descripcion.is_none()
+            && self.precio.is_none()
+            && self.stock.is_none()
+            && self.tipo_iva.is_none()
+    }
+}
+
+// solo actualiza las columnas presentes en el patch, None si el artículo no existe
+pub async fn postgres_patch_articulo(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    patch: ArticuloPatch,
+    id: i32,
+) -> Result<Option<Articulo>, sqlx::Error> {
+    if patch.is_empty() {
+        return sqlx::query_as::<_, Articulo>(
+            "
+            SELECT
+                id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at
+            FROM articulos
+            WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await;
+    }
+
+    let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new("UPDATE articulos SET ");
+    let mut set = query.separated(", ");
+    if let Some(nombre) = patch.nombre {
+        set.push("nombre = ").push_bind_unseparated(nombre);
+    }
+    if let Some(descripcion) = patch.descripcion {
+        set.push("descripcion = ")
+            .push_bind_unseparated(descripcion);
+    }
+    if let Some(precio) = patch.precio {
+        set.push("precio = ")
+            .push_bind_unseparated(precio.amount_minor());
+        set.push("moneda = ")
+            .push_bind_unseparated(precio.currency().code());
+    }
+    if let Some(stock) = patch.stock {
+        set.push("stock = ").push_bind_unseparated(stock);
+    }
+    if let Some(tipo_iva) = patch.tipo_iva {
+        set.push("tipo_iva = ").push_bind_unseparated(tipo_iva);
+    }
+    query.push(" WHERE id = ").push_bind(id).push(
+        " RETURNING id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at",
+    );
+
+    let updated_articulo = query
+        .build_query_as::<Articulo>()
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(updated_articulo)
+}
+
+#[derive(Debug)]
+pub enum BorradoArticuloError {
+    Sqlx(sqlx::Error),
+    ArticuloNoEncontrado(i32),
+    EnPedidosAbiertos(i32),
+}
+
+impl fmt::Display for BorradoArticuloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BorradoArticuloError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            BorradoArticuloError::ArticuloNoEncontrado(id) => {
+                write!(f, "artículo {} no encontrado", id)
+            }
+            BorradoArticuloError::EnPedidosAbiertos(id) => {
+                write!(f, "el artículo {} forma parte de pedidos abiertos", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BorradoArticuloError {}
+
+impl From<sqlx::Error> for BorradoArticuloError {
+    fn from(e: sqlx::Error) -> Self {
+        BorradoArticuloError::Sqlx(e)
+    }
+}
+
+// Borrado lógico; no se borra un artículo que forma parte de pedidos abiertos.
+// El bloqueo del artículo es el mismo que toma la creación de pedidos, así que
+// un pedido nuevo no puede colarse entre la comprobación y el borrado.
+pub async fn postgres_soft_delete_articulo(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Articulo, BorradoArticuloError> {
+    let mut tx = pool.begin().await?;
+
+    let existe: Option<i32> = sqlx::query_scalar(
+        "SELECT id FROM articulos WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if existe.is_none() {
+        return Err(BorradoArticuloError::ArticuloNoEncontrado(id));
+    }
+
+    // true si el artículo aparece en algún pedido que no esté entregado ni cancelado
+    let in_open_pedidos: bool = sqlx::query_scalar(
+        "
+        SELECT EXISTS (
+            SELECT 1
+            FROM pedidos_detalles pd
+            JOIN pedidos p ON p.id = pd.pedido_id
+            WHERE pd.articulo_id = $1
+            AND p.estado NOT IN ('Entregado', 'Cancelado')
+        )",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if in_open_pedidos {
+        return Err(BorradoArticuloError::EnPedidosAbiertos(id));
+    }
+
+    let deleted_articulo = sqlx::query_as::<_, Articulo>(
+        "
+        UPDATE articulos
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        RETURNING id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(deleted_articulo)
+}
+
+// deshace el borrado lógico, None si el artículo no existe o no estaba borrado
+pub async fn postgres_restore_articulo(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Articulo>, sqlx::Error> {
+    let restored_articulo = sqlx::query_as::<_, Articulo>(
+        "
+        UPDATE articulos
+        SET deleted_at = NULL
+        WHERE id = $1 AND deleted_at IS NOT NULL
+        RETURNING id, nombre, descripcion, precio, moneda, stock, tipo_iva, fecha_creacion, deleted_at",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(restored_articulo)
+}
@@ -0,0 +1,114 @@
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+
+use crate::articulos::{Articulo, postgres_get_articulo_by_id, postgres_get_articulos};
+
+// Las claves llevan la versión del catálogo: cualquier cambio incrementa
+// catalogo-version y todas las réplicas dejan de leer las entradas anteriores,
+// que caducan solas. Un lector lento que guarde datos viejos los guarda con
+// una versión que ya no se consulta.
+const CATALOGO_VERSION_KEY: &str = "catalogo-version";
+const CATALOGO_KEY: &str = "catalogo:";
+
+#[derive(Clone)]
+pub struct CatalogoCache {
+    pub redis: ConnectionManager,
+    pub ttl: u64,
+}
+
+impl CatalogoCache {
+    async fn key(&self, recurso: &str) -> redis::RedisResult<String> {
+        let mut con = self.redis.clone();
+        let version: Option<i64> = con.get(CATALOGO_VERSION_KEY).await?;
+        Ok(format!(
+            "{}{}:{}",
+            CATALOGO_KEY,
+            version.unwrap_or(0),
+            recurso
+        ))
+    }
+
+    // (clave versionada, valor cacheado si lo hay)
+    async fn leer<T: serde::de::DeserializeOwned>(
+        &self,
+        recurso: &str,
+    ) -> redis::RedisResult<(String, Option<T>)> {
+        let key = self.key(recurso).await?;
+        let mut con = self.redis.clone();
+        let json: Option<String> = con.get(&key).await?;
+
+        // una entrada que no se puede leer cuenta como fallo de caché
+        let valor = json.and_then(|json| {
+            serde_json::from_str(&json)
+                .inspect_err(|e| eprintln!("Corrupt catalog cache entry {}: {:?}", key, e))
+                .ok()
+        });
+        Ok((key, valor))
+    }
+
+    async fn guardar<T: serde::Serialize>(&self, key: &str, valor: &T) {
+        let Ok(json) = serde_json::to_string(valor) else {
+            return;
+        };
+        let mut con = self.redis.clone();
+        let guardado: redis::RedisResult<()> = con.set_ex(key, json, self.ttl).await;
+        if let Err(e) = guardado {
+            eprintln!("Error caching catalog: {:?}", e);
+        }
+    }
+
+    // Lectura a través de la caché; si Redis falla se lee de Postgres
+    async fn leer_o_consultar<T, F>(&self, recurso: &str, consulta: F) -> Result<T, sqlx::Error>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        F: std::future::Future<Output = Result<T, sqlx::Error>>,
+    {
+        let key = match self.leer::<T>(recurso).await {
+            Ok((_, Some(valor))) => return Ok(valor),
+            Ok((key, None)) => Some(key),
+            Err(e) => {
+                eprintln!("Error reading catalog cache: {:?}", e);
+                None
+            }
+        };
+
+        let valor = consulta.await?;
+        if let Some(key) = key {
+            self.guardar(&key, &valor).await;
+        }
+        Ok(valor)
+    }
+
+    pub async fn articulos(
+        &self,
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        include_deleted: bool,
+    ) -> Result<Vec<Articulo>, sqlx::Error> {
+        self.leer_o_consultar(
+            &format!("articulos:{}", include_deleted),
+            postgres_get_articulos(pool, include_deleted),
+        )
+        .await
+    }
+
+    pub async fn articulo(
+        &self,
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        id: i32,
+    ) -> Result<Articulo, sqlx::Error> {
+        self.leer_o_consultar(
+            &format!("articulo:{}", id),
+            postgres_get_articulo_by_id(pool, id),
+        )
+        .await
+    }
+
+    // se llama después de confirmar cualquier cambio de artículos o de stock
+    pub async fn invalidar(&self) {
+        let mut con = self.redis.clone();
+        let version: redis::RedisResult<i64> = con.incr(CATALOGO_VERSION_KEY, 1).await;
+        if let Err(e) = version {
+            eprintln!("Error invalidating catalog cache: {:?}", e);
+        }
+    }
+}
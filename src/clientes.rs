@@ -1,34 +1,71 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use validator::Validate;
 
-#[derive(Serialize, Deserialize, Clone, FromRow)]
+// user_id es la cuenta de auth del cliente; un cliente de empresa puede no tenerla
+#[derive(Serialize, Deserialize, Clone, FromRow, Validate)]
 pub struct ClienteRequest {
-    pub user_id: i32,
+    #[serde(default)]
+    pub user_id: Option<i32>,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
     pub nombre: String,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(
+        email(message = "formato de email no válido"),
+        length(max = 100, message = "máximo 100 caracteres")
+    )]
     pub email: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(
+        length(max = 20, message = "máximo 20 caracteres"),
+        custom(function = "crate::validacion::validate_telefono")
+    )]
     pub telefono: Option<String>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
     pub direccion: Option<String>,
 }
 
+// vincula (o desvincula con None) la cuenta de auth del cliente
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct ClienteUsuarioRequest {
+    pub user_id: Option<i32>,
+}
+
+// tarifa_id a null devuelve al cliente al precio de catálogo
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct ClienteTarifaRequest {
+    pub tarifa_id: Option<i32>,
+}
+
 #[derive(Serialize, Deserialize, Clone, FromRow)]
 pub struct Cliente {
-    id: i32,
-    user_id: i32,
-    nombre: String,
-    email: String,
-    telefono: Option<String>,
-    direccion: Option<String>,
-    fecha_registro: chrono::NaiveDateTime,
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub nombre: String,
+    pub email: String,
+    pub telefono: Option<String>,
+    pub direccion: Option<String>,
+    pub fecha_registro: chrono::NaiveDateTime,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub tarifa_id: Option<i32>,
 }
 
+pub const CLIENTE_COLUMNS: &str =
+    "id, user_id, nombre, email, telefono, direccion, fecha_registro, deleted_at, tarifa_id";
+
 pub async fn postgres_get_clientes(
     pool: &sqlx::Pool<sqlx::Postgres>,
+    include_deleted: bool,
 ) -> Result<Vec<Cliente>, sqlx::Error> {
-    let clientes = sqlx::query_as::<_, Cliente>(
-        "SELECT
-            id, user_id, nombre, email, telefono, direccion, fecha_registro
-        FROM clientes",
-    )
+    let clientes = sqlx::query_as::<_, Cliente>(&format!(
+        "SELECT {}
+        FROM clientes
+        WHERE $1 OR deleted_at IS NULL
+        ORDER BY id",
+        CLIENTE_COLUMNS
+    ))
+    .bind(include_deleted)
     .fetch_all(pool)
     .await?;
 
@@ -39,12 +76,12 @@ pub async fn postgres_get_cliente_by_user_id(
     pool: &sqlx::Pool<sqlx::Postgres>,
     user_id: i32,
 ) -> Result<Option<Cliente>, sqlx::Error> {
-    let cliente: Option<Cliente> = sqlx::query_as::<_, Cliente>(
-        "SELECT
-            id, user_id, nombre, email, telefono, direccion, fecha_registro
+    let cliente: Option<Cliente> = sqlx::query_as::<_, Cliente>(&format!(
+        "SELECT {}
         FROM clientes
         WHERE user_id = $1",
-    )
+        CLIENTE_COLUMNS
+    ))
     .bind(user_id)
     .fetch_optional(pool)
     .await?;
@@ -52,34 +89,33 @@ pub async fn postgres_get_cliente_by_user_id(
     Ok(cliente)
 }
 
-/*
 pub async fn postgres_get_cliente_by_id(
     pool: &sqlx::Pool<sqlx::Postgres>,
     id: i32,
-) -> Result<Cliente, sqlx::Error> {
-    let cliente: Cliente = sqlx::query_as::<_, Cliente>(
-        "SELECT
-            id, user_id, nombre, email, telefono, direccion, fecha_registro
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let cliente: Option<Cliente> = sqlx::query_as::<_, Cliente>(&format!(
+        "SELECT {}
         FROM clientes
         WHERE id = $1",
-    )
+        CLIENTE_COLUMNS
+    ))
     .bind(id)
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await?;
 
     Ok(cliente)
 }
-*/
 
 pub async fn postgres_create_cliente(
     pool: &sqlx::Pool<sqlx::Postgres>,
     cliente: ClienteRequest,
 ) -> Result<Cliente, sqlx::Error> {
-    let new_cliente = sqlx::query_as::<_, Cliente>(
+    let new_cliente = sqlx::query_as::<_, Cliente>(&format!(
         "INSERT INTO clientes (user_id, nombre, email, telefono, direccion)
         VALUES ($1, $2, $3, $4, $5)
-        RETURNING id, user_id, nombre, email, telefono, direccion, fecha_registro",
-    )
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
     .bind(cliente.user_id)
     .bind(cliente.nombre)
     .bind(cliente.email)
@@ -91,23 +127,175 @@ pub async fn postgres_create_cliente(
     Ok(new_cliente)
 }
 
+// el user_id del request se ignora, se vincula con postgres_set_cliente_user_id
 pub async fn postgres_update_cliente(
     pool: &sqlx::Pool<sqlx::Postgres>,
     cliente: ClienteRequest,
-    user_id: i32,
-) -> Result<Cliente, sqlx::Error> {
-    let updated_cliente = sqlx::query_as::<_, Cliente>(
+    id: i32,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let updated_cliente = sqlx::query_as::<_, Cliente>(&format!(
         "UPDATE clientes
         SET nombre = $1, email = $2, telefono = $3, direccion = $4
-        WHERE user_id = $5
-        RETURNING id, user_id, nombre, email, telefono, direccion, fecha_registro",
-    )
+        WHERE id = $5
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
     .bind(cliente.nombre)
     .bind(cliente.email)
     .bind(cliente.telefono)
     .bind(cliente.direccion)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_cliente)
+}
+
+#[derive(Deserialize, Debug, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct ClientePatch {
+    #[serde(default, deserialize_with = "crate::mergepatch::required_trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: Option<String>,
+    #[serde(default, deserialize_with = "crate::mergepatch::required_trimmed")]
+    #[validate(
+        email(message = "formato de email no válido"),
+        length(max = 100, message = "máximo 100 caracteres")
+    )]
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "crate::mergepatch::nullable_trimmed")]
+    #[validate(
+        length(max = 20, message = "máximo 20 caracteres"),
+        custom(function = "crate::validacion::validate_telefono")
+    )]
+    pub telefono: Option<Option<String>>,
+    #[serde(default, deserialize_with = "crate::mergepatch::nullable_trimmed")]
+    pub direccion: Option<Option<String>>,
+}
+
+impl ClientePatch {
+    pub fn is_empty(&self) -> bool {
+        self.nombre.is_none()
+            && self.email.is_none()
+            && self.telefono.is_none()
+            && self.direccion.is_none()
+    }
+}
+
+// solo actualiza las columnas presentes en el patch, None si el cliente no existe
+pub async fn postgres_patch_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    patch: ClientePatch,
+    id: i32,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    if patch.is_empty() {
+        return postgres_get_cliente_by_id(pool, id).await;
+    }
+
+    let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new("UPDATE clientes SET ");
+    let mut set = query.separated(", ");
+    if let Some(nombre) = patch.nombre {
+        set.push("nombre = ").push_bind_unseparated(nombre);
+    }
+    if let Some(email) = patch.email {
+        set.push("email = ").push_bind_unseparated(email);
+    }
+    if let Some(telefono) = patch.telefono {
+        set.push("telefono = ").push_bind_unseparated(telefono);
+    }
+    if let Some(direccion) = patch.direccion {
+        set.push("direccion = ").push_bind_unseparated(direccion);
+    }
+    query
+        .push(" WHERE id = ")
+        .push_bind(id)
+        .push(" RETURNING ")
+        .push(CLIENTE_COLUMNS);
+
+    let updated_cliente = query
+        .build_query_as::<Cliente>()
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(updated_cliente)
+}
+
+// borrado lógico, None si el cliente no existe o ya estaba borrado
+pub async fn postgres_soft_delete_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let deleted_cliente = sqlx::query_as::<_, Cliente>(&format!(
+        "UPDATE clientes
+        SET deleted_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(deleted_cliente)
+}
+
+// deshace el borrado lógico, None si el cliente no existe o no estaba borrado
+pub async fn postgres_restore_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let restored_cliente = sqlx::query_as::<_, Cliente>(&format!(
+        "UPDATE clientes
+        SET deleted_at = NULL
+        WHERE id = $1 AND deleted_at IS NOT NULL
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(restored_cliente)
+}
+
+// asigna (o quita con None) la tarifa del cliente; None si el cliente o la tarifa no existen
+pub async fn postgres_set_cliente_tarifa(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    tarifa_id: Option<i32>,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let updated_cliente = sqlx::query_as::<_, Cliente>(&format!(
+        "UPDATE clientes
+        SET tarifa_id = $1
+        WHERE id = $2
+        AND ($1 IS NULL OR EXISTS (SELECT 1 FROM tarifas WHERE id = $1))
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
+    .bind(tarifa_id)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_cliente)
+}
+
+// None si el cliente no existe; otro cliente con el mismo user_id falla con unique violation
+pub async fn postgres_set_cliente_user_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    user_id: Option<i32>,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let updated_cliente = sqlx::query_as::<_, Cliente>(&format!(
+        "UPDATE clientes
+        SET user_id = $1
+        WHERE id = $2
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
     .bind(user_id)
-    .fetch_one(pool)
+    .bind(id)
+    .fetch_optional(pool)
     .await?;
 
     Ok(updated_cliente)
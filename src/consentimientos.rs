@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+use crate::duplicados::sql_ids_fusionados;
+
+pub const CANAL_EMAIL: &str = "email";
+pub const CANAL_TELEFONO: &str = "telefono";
+pub const CANAL_SMS: &str = "sms";
+
+pub const CANALES: [&str; 3] = [CANAL_EMAIL, CANAL_TELEFONO, CANAL_SMS];
+
+pub const ORIGENES_CONSENTIMIENTO: [&str; 5] =
+    ["web", "formulario", "llamada", "email", "importacion"];
+
+pub fn validate_canal(canal: &str) -> Result<(), ValidationError> {
+    if !CANALES.contains(&canal) {
+        return Err(
+            ValidationError::new("canal").with_message("debe ser email, telefono o sms".into())
+        );
+    }
+    Ok(())
+}
+
+pub fn validate_origen_consentimiento(origen: &str) -> Result<(), ValidationError> {
+    if !ORIGENES_CONSENTIMIENTO.contains(&origen) {
+        return Err(ValidationError::new("origen")
+            .with_message("debe ser web, formulario, llamada, email o importacion".into()));
+    }
+    Ok(())
+}
+
+// cada cambio es una fila nueva; version_texto es la versión del texto legal aceptado
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct ConsentimientoRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_canal"))]
+    pub canal: String,
+    pub otorgado: bool,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_origen_consentimiento"))]
+    pub origen: String,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 20, message = "entre 1 y 20 caracteres"))]
+    pub version_texto: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Consentimiento {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub canal: String,
+    pub otorgado: bool,
+    pub origen: String,
+    pub version_texto: String,
+    pub user_id: i32,
+    pub fecha: chrono::NaiveDateTime,
+}
+
+const CONSENTIMIENTO_COLUMNS: &str =
+    "id, cliente_id, canal, otorgado, origen, version_texto, user_id, fecha";
+
+// último registro de cada canal hasta `fecha` (ahora si es None); un canal sin
+// registros no tiene consentimiento. Cuentan también los registros de los
+// clientes fusionados en este, que se quedaron en el duplicado.
+pub async fn postgres_get_consentimientos(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    fecha: Option<chrono::NaiveDateTime>,
+) -> Result<Vec<Consentimiento>, sqlx::Error> {
+    let consentimientos = sqlx::query_as::<_, Consentimiento>(&format!(
+        "SELECT DISTINCT ON (canal) {}
+        FROM consentimientos
+        WHERE cliente_id IN ({}) AND ($2::TIMESTAMP IS NULL OR fecha <= $2)
+        ORDER BY canal, fecha DESC, id DESC",
+        CONSENTIMIENTO_COLUMNS,
+        sql_ids_fusionados("$1")
+    ))
+    .bind(cliente_id)
+    .bind(fecha)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(consentimientos)
+}
+
+pub async fn postgres_get_historial_consentimientos(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Consentimiento>, sqlx::Error> {
+    let historial = sqlx::query_as::<_, Consentimiento>(&format!(
+        "SELECT {} FROM consentimientos WHERE cliente_id IN ({}) ORDER BY fecha DESC, id DESC",
+        CONSENTIMIENTO_COLUMNS,
+        sql_ids_fusionados("$1")
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(historial)
+}
+
+// None si el cliente no existe o está borrado
+pub async fn postgres_create_consentimiento(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    user_id: i32,
+    consentimiento: ConsentimientoRequest,
+) -> Result<Option<Consentimiento>, sqlx::Error> {
+    let new_consentimiento = sqlx::query_as::<_, Consentimiento>(&format!(
+        "
+        INSERT INTO consentimientos (cliente_id, canal, otorgado, origen, version_texto, user_id)
+        SELECT id, $2, $3, $4, $5, $6
+        FROM clientes
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING {}",
+        CONSENTIMIENTO_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(consentimiento.canal)
+    .bind(consentimiento.otorgado)
+    .bind(consentimiento.origen)
+    .bind(consentimiento.version_texto)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(new_consentimiento)
+}
@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::Validate;
+
+// persona de contacto de un cliente empresa
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct ContactoRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 100, message = "máximo 100 caracteres"))]
+    pub cargo: Option<String>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(
+        email(message = "formato de email no válido"),
+        length(max = 100, message = "máximo 100 caracteres")
+    )]
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(
+        length(max = 20, message = "máximo 20 caracteres"),
+        custom(function = "crate::validacion::validate_telefono")
+    )]
+    pub telefono: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Contacto {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub nombre: String,
+    pub cargo: Option<String>,
+    pub email: Option<String>,
+    pub telefono: Option<String>,
+    pub fecha_creacion: chrono::NaiveDateTime,
+}
+
+const CONTACTO_COLUMNS: &str = "id, cliente_id, nombre, cargo, email, telefono, fecha_creacion";
+
+pub async fn postgres_get_contactos_by_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Contacto>, sqlx::Error> {
+    let contactos = sqlx::query_as::<_, Contacto>(&format!(
+        "SELECT {} FROM contactos WHERE cliente_id = $1 ORDER BY nombre, id",
+        CONTACTO_COLUMNS
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(contactos)
+}
+
+pub async fn postgres_get_contacto_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Contacto>, sqlx::Error> {
+    let contacto = sqlx::query_as::<_, Contacto>(&format!(
+        "SELECT {} FROM contactos WHERE id = $1",
+        CONTACTO_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(contacto)
+}
+
+// None si el cliente no existe o está borrado
+pub async fn postgres_create_contacto(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    contacto: ContactoRequest,
+) -> Result<Option<Contacto>, sqlx::Error> {
+    let new_contacto = sqlx::query_as::<_, Contacto>(&format!(
+        "
+        INSERT INTO contactos (cliente_id, nombre, cargo, email, telefono)
+        SELECT id, $2, $3, $4, $5
+        FROM clientes
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING {}",
+        CONTACTO_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(contacto.nombre)
+    .bind(contacto.cargo)
+    .bind(contacto.email)
+    .bind(contacto.telefono)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(new_contacto)
+}
+
+pub async fn postgres_update_contacto(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    contacto: ContactoRequest,
+) -> Result<Option<Contacto>, sqlx::Error> {
+    let updated_contacto = sqlx::query_as::<_, Contacto>(&format!(
+        "
+        UPDATE contactos
+        SET nombre = $1, cargo = $2, email = $3, telefono = $4
+        WHERE id = $5
+        RETURNING {}",
+        CONTACTO_COLUMNS
+    ))
+    .bind(contacto.nombre)
+    .bind(contacto.cargo)
+    .bind(contacto.email)
+    .bind(contacto.telefono)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_contacto)
+}
+
+pub async fn postgres_delete_contacto(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM contactos WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
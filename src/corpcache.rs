@@ -0,0 +1,153 @@
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::Deserialize;
+
+use crate::corpservice::{UserData, corp_service_userdata_by_id};
+
+const CORP_USERDATA_KEY: &str = "corp-userdata:";
+const CORP_REFRESH_KEY: &str = "corp-userdata-refresh:";
+
+// segundos que un proceso se reserva la revalidación de un usuario
+const REFRESH_LOCK_TTL: u64 = 30;
+
+// ttl y ttl_negativo: segundos en que la entrada está vigente (con datos o 404);
+// stale: segundos más en que se sigue sirviendo mientras se revalida
+#[derive(Clone)]
+pub struct CorpCache {
+    pub redis: ConnectionManager,
+    pub ttl: i64,
+    pub ttl_negativo: i64,
+    pub stale: i64,
+}
+
+// datos None es que el directorio respondió 404
+#[derive(Deserialize)]
+struct Entrada {
+    datos: Option<UserData>,
+    guardado: i64,
+}
+
+fn key(prefijo: &str, user_id: i32) -> String {
+    format!("{}{}", prefijo, user_id)
+}
+
+impl CorpCache {
+    fn vigente(&self, entrada: &Entrada) -> bool {
+        let ttl = if entrada.datos.is_some() {
+            self.ttl
+        } else {
+            self.ttl_negativo
+        };
+        chrono::Utc::now().timestamp() - entrada.guardado < ttl
+    }
+
+    async fn leer(&self, user_id: i32) -> redis::RedisResult<Option<Entrada>> {
+        let mut con = self.redis.clone();
+        let json: Option<String> = con.get(key(CORP_USERDATA_KEY, user_id)).await?;
+
+        // una entrada que no se puede leer cuenta como fallo de caché
+        Ok(json.and_then(|json| {
+            serde_json::from_str(&json)
+                .inspect_err(|e| eprintln!("Corrupt corp cache entry for {}: {:?}", user_id, e))
+                .ok()
+        }))
+    }
+
+    async fn guardar(&self, user_id: i32, datos: &Option<UserData>) -> redis::RedisResult<()> {
+        let json = serde_json::json!({
+            "datos": datos,
+            "guardado": chrono::Utc::now().timestamp(),
+        });
+
+        let ttl = if datos.is_some() {
+            self.ttl
+        } else {
+            self.ttl_negativo
+        };
+        let mut con = self.redis.clone();
+        let _: () = con
+            .set_ex(
+                key(CORP_USERDATA_KEY, user_id),
+                json.to_string(),
+                (ttl + self.stale).max(1) as u64,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn invalidar(&self, user_id: i32) -> redis::RedisResult<()> {
+        let mut con = self.redis.clone();
+        let _: () = con.del(key(CORP_USERDATA_KEY, user_id)).await?;
+        Ok(())
+    }
+
+    async fn consultar(&self, user_id: i32) -> Result<Option<UserData>, String> {
+        let datos = corp_service_userdata_by_id(user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = self.guardar(user_id, &datos).await {
+            eprintln!("Error caching corp user data: {:?}", e);
+        }
+        Ok(datos)
+    }
+
+    // Revalida en segundo plano; solo un proceso a la vez por usuario. Si el
+    // servicio corporativo falla se sigue sirviendo la entrada caducada hasta
+    // que Redis la expire.
+    fn revalidar(&self, user_id: i32) {
+        let cache = self.clone();
+        rocket::tokio::spawn(async move {
+            let mut con = cache.redis.clone();
+            let reservado: redis::RedisResult<Option<String>> = con
+                .set_options(
+                    key(CORP_REFRESH_KEY, user_id),
+                    1,
+                    redis::SetOptions::default()
+                        .conditional_set(redis::ExistenceCheck::NX)
+                        .with_expiration(redis::SetExpiry::EX(REFRESH_LOCK_TTL)),
+                )
+                .await;
+            match reservado {
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("Error locking corp cache refresh: {:?}", e);
+                    return;
+                }
+            }
+
+            if let Err(e) = cache.consultar(user_id).await {
+                eprintln!("Error refreshing corp user data for {}: {}", user_id, e);
+            }
+        });
+    }
+
+    // consulta el directorio sin mirar la caché y guarda la respuesta, para
+    // cuando se pide expresamente el dato actual
+    pub async fn refrescar(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<UserData>, Box<dyn std::error::Error>> {
+        Ok(self.consultar(user_id).await?)
+    }
+
+    // corp_service_userdata_by_id a través de la caché; si Redis no responde
+    // se consulta directamente el servicio corporativo
+    pub async fn userdata_by_id(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<UserData>, Box<dyn std::error::Error>> {
+        match self.leer(user_id).await {
+            Ok(Some(entrada)) => {
+                if !self.vigente(&entrada) {
+                    self.revalidar(user_id);
+                }
+                return Ok(entrada.datos);
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Error reading corp cache: {:?}", e),
+        }
+
+        Ok(self.consultar(user_id).await?)
+    }
+}
@@ -1,6 +1,5 @@
 use std::env;
 
-use log;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
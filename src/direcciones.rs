@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+pub const TIPO_FACTURACION: &str = "facturacion";
+pub const TIPO_ENVIO: &str = "envio";
+
+pub fn validate_tipo_direccion(tipo: &str) -> Result<(), ValidationError> {
+    if tipo != TIPO_FACTURACION && tipo != TIPO_ENVIO {
+        return Err(
+            ValidationError::new("tipo").with_message("debe ser facturacion o envio".into())
+        );
+    }
+    Ok(())
+}
+
+// código ISO 3166-1 alfa-2 en mayúsculas
+pub fn validate_pais(pais: &str) -> Result<(), ValidationError> {
+    if pais.len() != 2 || !pais.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(ValidationError::new("pais")
+            .with_message("código de país de 2 letras en mayúsculas (ISO 3166-1)".into()));
+    }
+    Ok(())
+}
+
+fn pais_por_defecto() -> String {
+    "ES".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DireccionRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_tipo_direccion"))]
+    pub tipo: String,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 200, message = "entre 1 y 200 caracteres"))]
+    pub calle: String,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 10, message = "entre 1 y 10 caracteres"))]
+    pub codigo_postal: String,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub ciudad: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 100, message = "máximo 100 caracteres"))]
+    pub provincia: Option<String>,
+    #[serde(
+        default = "pais_por_defecto",
+        deserialize_with = "crate::validacion::trimmed"
+    )]
+    #[validate(custom(function = "validate_pais"))]
+    pub pais: String,
+    #[serde(default)]
+    pub predeterminada: bool,
+}
+
+// como mucho una predeterminada por cliente y tipo
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Direccion {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub tipo: String,
+    pub calle: String,
+    pub codigo_postal: String,
+    pub ciudad: String,
+    pub provincia: Option<String>,
+    pub pais: String,
+    pub predeterminada: bool,
+    pub fecha_creacion: chrono::NaiveDateTime,
+}
+
+impl Direccion {
+    // una línea para facturas y PDFs: "calle, CP ciudad, provincia, país"
+    pub fn formatear(&self) -> String {
+        let mut partes = vec![
+            self.calle.clone(),
+            format!("{} {}", self.codigo_postal, self.ciudad),
+        ];
+        if let Some(provincia) = self.provincia.as_deref().filter(|p| !p.is_empty()) {
+            partes.push(provincia.to_string());
+        }
+        partes.push(self.pais.clone());
+        partes.join(", ")
+    }
+}
+
+const DIRECCION_COLUMNS: &str = "id, cliente_id, tipo, calle, codigo_postal, ciudad, provincia, pais, predeterminada, fecha_creacion";
+
+pub async fn postgres_get_direcciones_by_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Direccion>, sqlx::Error> {
+    let direcciones = sqlx::query_as::<_, Direccion>(&format!(
+        "SELECT {}
+        FROM direcciones
+        WHERE cliente_id = $1
+        ORDER BY tipo, predeterminada DESC, id",
+        DIRECCION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(direcciones)
+}
+
+pub async fn postgres_get_direccion_by_id<'e, E>(
+    executor: E,
+    id: i32,
+) -> Result<Option<Direccion>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let direccion = sqlx::query_as::<_, Direccion>(&format!(
+        "SELECT {} FROM direcciones WHERE id = $1",
+        DIRECCION_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(direccion)
+}
+
+pub async fn postgres_get_direccion_predeterminada<'e, E>(
+    executor: E,
+    cliente_id: i32,
+    tipo: &str,
+) -> Result<Option<Direccion>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let direccion = sqlx::query_as::<_, Direccion>(&format!(
+        "SELECT {} FROM direcciones WHERE cliente_id = $1 AND tipo = $2 AND predeterminada",
+        DIRECCION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(tipo)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(direccion)
+}
+
+// quita la marca a las demás del mismo tipo antes de marcar una nueva
+async fn quitar_predeterminada(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    cliente_id: i32,
+    tipo: &str,
+    excepto_id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        UPDATE direcciones
+        SET predeterminada = FALSE
+        WHERE cliente_id = $1 AND tipo = $2 AND predeterminada
+        AND ($3::INT IS NULL OR id <> $3)",
+    )
+    .bind(cliente_id)
+    .bind(tipo)
+    .bind(excepto_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// La primera dirección de cada tipo queda como predeterminada aunque no se pida.
+// None si el cliente no existe o está borrado.
+pub async fn postgres_create_direccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    direccion: DireccionRequest,
+) -> Result<Option<Direccion>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if direccion.predeterminada {
+        quitar_predeterminada(&mut tx, cliente_id, &direccion.tipo, None).await?;
+    }
+
+    let new_direccion = sqlx::query_as::<_, Direccion>(&format!(
+        "
+        INSERT INTO direcciones (
+            cliente_id, tipo, calle, codigo_postal, ciudad, provincia, pais, predeterminada
+        )
+        SELECT c.id, $2, $3, $4, $5, $6, $7, $8 OR NOT EXISTS (
+            SELECT 1 FROM direcciones d WHERE d.cliente_id = c.id AND d.tipo = $2
+        )
+        FROM clientes c
+        WHERE c.id = $1 AND c.deleted_at IS NULL
+        RETURNING {}",
+        DIRECCION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(&direccion.tipo)
+    .bind(direccion.calle)
+    .bind(direccion.codigo_postal)
+    .bind(direccion.ciudad)
+    .bind(direccion.provincia)
+    .bind(direccion.pais)
+    .bind(direccion.predeterminada)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(new_direccion)
+}
+
+// Al cambiar de tipo o quitar la marca el cliente puede quedarse sin
+// predeterminada de ese tipo; pedidos y facturas lo toleran.
+pub async fn postgres_update_direccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    direccion: DireccionRequest,
+) -> Result<Option<Direccion>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let Some(actual) = postgres_get_direccion_by_id(&mut *tx, id).await? else {
+        return Ok(None);
+    };
+    if direccion.predeterminada {
+        quitar_predeterminada(&mut tx, actual.cliente_id, &direccion.tipo, Some(id)).await?;
+    }
+
+    let updated_direccion = sqlx::query_as::<_, Direccion>(&format!(
+        "
+        UPDATE direcciones
+        SET tipo = $1, calle = $2, codigo_postal = $3, ciudad = $4, provincia = $5, pais = $6,
+            predeterminada = $7
+        WHERE id = $8
+        RETURNING {}",
+        DIRECCION_COLUMNS
+    ))
+    .bind(direccion.tipo)
+    .bind(direccion.calle)
+    .bind(direccion.codigo_postal)
+    .bind(direccion.ciudad)
+    .bind(direccion.provincia)
+    .bind(direccion.pais)
+    .bind(direccion.predeterminada)
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(updated_direccion)
+}
+
+// los pedidos que la usaban conservan el pedido sin dirección de envío
+pub async fn postgres_delete_direccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM direcciones WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
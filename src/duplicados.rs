@@ -0,0 +1,597 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::Validate;
+
+use crate::clientes::{CLIENTE_COLUMNS, Cliente};
+
+pub const UMBRAL_POR_DEFECTO: i32 = 50;
+
+// peso de cada coincidencia en la puntuación (0-100) de un par
+const PESO_EMAIL: i32 = 50;
+const PESO_TELEFONO: i32 = 30;
+const PESO_NOMBRE: f64 = 40.0;
+// por debajo de esta similitud el nombre no suma
+const SIMILITUD_NOMBRE_MINIMA: f64 = 0.5;
+
+pub fn normalizar_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// solo dígitos y sin prefijo internacional: se comparan los 9 últimos
+pub fn normalizar_telefono(telefono: &str) -> Option<String> {
+    let digitos: String = telefono.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digitos.len() < 6 {
+        return None;
+    }
+    Some(digitos[digitos.len().saturating_sub(9)..].to_string())
+}
+
+// minúsculas, sin tildes y con las palabras separadas por un solo espacio
+pub fn normalizar_nombre(nombre: &str) -> String {
+    let nombre: String = nombre
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'ä' | 'â' => 'a',
+            'é' | 'è' | 'ë' | 'ê' => 'e',
+            'í' | 'ì' | 'ï' | 'î' => 'i',
+            'ó' | 'ò' | 'ö' | 'ô' => 'o',
+            'ú' | 'ù' | 'ü' | 'û' => 'u',
+            'ñ' => 'n',
+            'ç' => 'c',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect();
+    nombre.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// trigramas de cada palabra con el mismo relleno que pg_trgm ("  pe", " pe", ...)
+fn trigramas(nombre: &str) -> HashSet<String> {
+    let mut trigramas = HashSet::new();
+    for palabra in nombre.split_whitespace() {
+        let chars: Vec<char> = format!("  {} ", palabra).chars().collect();
+        for ventana in chars.windows(3) {
+            trigramas.insert(ventana.iter().collect());
+        }
+    }
+    trigramas
+}
+
+// similitud de trigramas entre 0 y 1, como similarity() de pg_trgm
+pub fn similitud_nombre(a: &str, b: &str) -> f64 {
+    let a = trigramas(&normalizar_nombre(a));
+    let b = trigramas(&normalizar_nombre(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+// motivos: email, telefono y/o nombre
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ParDuplicado {
+    pub cliente_a: Cliente,
+    pub cliente_b: Cliente,
+    pub puntuacion: i32,
+    pub similitud_nombre: f64,
+    pub motivos: Vec<String>,
+}
+
+impl ParDuplicado {
+    // None si el par no llega al umbral
+    fn puntuar(a: &Cliente, b: &Cliente, umbral: i32) -> Option<ParDuplicado> {
+        let mut puntuacion = 0;
+        let mut motivos = Vec::new();
+
+        if normalizar_email(&a.email) == normalizar_email(&b.email) {
+            puntuacion += PESO_EMAIL;
+            motivos.push("email".to_string());
+        }
+        let telefono_a = a.telefono.as_deref().and_then(normalizar_telefono);
+        let telefono_b = b.telefono.as_deref().and_then(normalizar_telefono);
+        if telefono_a.is_some() && telefono_a == telefono_b {
+            puntuacion += PESO_TELEFONO;
+            motivos.push("telefono".to_string());
+        }
+        let similitud = similitud_nombre(&a.nombre, &b.nombre);
+        if similitud >= SIMILITUD_NOMBRE_MINIMA {
+            puntuacion += (similitud * PESO_NOMBRE).round() as i32;
+            motivos.push("nombre".to_string());
+        }
+
+        let puntuacion = puntuacion.min(100);
+        (puntuacion >= umbral).then(|| ParDuplicado {
+            cliente_a: a.clone(),
+            cliente_b: b.clone(),
+            puntuacion,
+            similitud_nombre: similitud,
+            motivos,
+        })
+    }
+
+    // Compara todos los pares; de mayor a menor puntuación. Es cuadrático, pero
+    // el nombre aproximado no permite agrupar antes por una clave exacta.
+    pub fn buscar(clientes: &[Cliente], umbral: i32) -> Vec<ParDuplicado> {
+        let mut pares: Vec<ParDuplicado> = clientes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| {
+                clientes[i + 1..]
+                    .iter()
+                    .filter_map(move |b| ParDuplicado::puntuar(a, b, umbral))
+            })
+            .collect();
+        pares.sort_by(|x, y| {
+            y.puntuacion
+                .cmp(&x.puntuacion)
+                .then(x.cliente_a.id.cmp(&y.cliente_a.id))
+                .then(x.cliente_b.id.cmp(&y.cliente_b.id))
+        });
+        pares
+    }
+}
+
+// el cliente de la ruta sobrevive; duplicado_id queda borrado tras la fusión
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct FusionRequest {
+    pub duplicado_id: i32,
+}
+
+// fusionado guarda la fila del duplicado tal como estaba y movidos cuántas
+// filas de cada tabla pasaron al superviviente (y qué facturas se quedaron)
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Fusion {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub fusionado_id: i32,
+    pub fusionado: serde_json::Value,
+    pub movidos: serde_json::Value,
+    pub user_id: i32,
+    pub fecha: chrono::NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub enum FusionError {
+    Sqlx(sqlx::Error),
+    ClienteNoEncontrado(i32),
+    MismoCliente,
+    UsuariosDistintos,
+}
+
+impl fmt::Display for FusionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FusionError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            FusionError::ClienteNoEncontrado(id) => write!(f, "cliente {} no encontrado", id),
+            FusionError::MismoCliente => write!(f, "no se puede fusionar un cliente consigo mismo"),
+            FusionError::UsuariosDistintos => {
+                write!(f, "los dos clientes tienen cuentas de auth distintas")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FusionError {}
+
+impl From<sqlx::Error> for FusionError {
+    fn from(e: sqlx::Error) -> Self {
+        FusionError::Sqlx(e)
+    }
+}
+
+const FUSION_COLUMNS: &str = "id, cliente_id, fusionado_id, fusionado, movidos, user_id, fecha";
+
+// Tablas cuyo cliente_id pasa tal cual al superviviente. Los consentimientos no
+// se mueven: su historial es de solo inserción y el del duplicado queda como prueba.
+// Las facturas tampoco: una factura emitida no cambia de destinatario (y el
+// trigger facturas_inmutables no lo permitiría), se quedan en el duplicado.
+const TABLAS_MOVIDAS: [&str; 8] = [
+    "pedidos",
+    "presupuestos",
+    "pagos",
+    "interacciones",
+    "contactos",
+    "leads",
+    "oportunidades",
+    "tareas_clientes",
+];
+
+// tablas de vínculos con clave (x, cliente_id): se omiten los que ya tiene el superviviente
+const VINCULOS_MOVIDOS: [(&str, &str); 2] = [
+    ("issue_request_clientes", "issue_request_id"),
+    ("clientes_etiquetas", "etiqueta"),
+];
+
+// Subconsulta con el id del cliente `cliente` (una columna o un parámetro) y los
+// de los clientes fusionados en él, también en fusiones encadenadas. Lo que se
+// quedó en el duplicado (consentimientos, facturas) sigue siendo del cliente.
+pub fn sql_ids_fusionados(cliente: &str) -> String {
+    format!(
+        "
+        WITH RECURSIVE ids (id) AS (
+            SELECT {}::INT
+            UNION
+            SELECT f.fusionado_id FROM clientes_fusiones f JOIN ids ON f.cliente_id = ids.id
+        )
+        SELECT id FROM ids",
+        cliente
+    )
+}
+
+// true si fusionado_id acabó fusionado en un cliente del usuario de auth
+pub async fn postgres_fusionado_en_usuario(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    fusionado_id: i32,
+    user_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let fusionado: bool = sqlx::query_scalar(&format!(
+        "SELECT EXISTS (
+            SELECT 1 FROM clientes c WHERE c.user_id = $2 AND $1 IN ({})
+        )",
+        sql_ids_fusionados("c.id")
+    ))
+    .bind(fusionado_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(fusionado)
+}
+
+pub async fn postgres_get_duplicados(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    umbral: i32,
+) -> Result<Vec<ParDuplicado>, sqlx::Error> {
+    let clientes = crate::clientes::postgres_get_clientes(pool, false).await?;
+
+    Ok(ParDuplicado::buscar(&clientes, umbral))
+}
+
+pub async fn postgres_get_fusiones_by_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Fusion>, sqlx::Error> {
+    let fusiones = sqlx::query_as::<_, Fusion>(&format!(
+        "SELECT {} FROM clientes_fusiones WHERE cliente_id = $1 ORDER BY fecha DESC, id DESC",
+        FUSION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(fusiones)
+}
+
+// Pasa todo lo del duplicado al superviviente salvo sus facturas, completa los
+// datos que a este le falten, borra (lógicamente) el duplicado y deja constancia
+// en clientes_fusiones; movidos.facturas_conservadas lista las que se quedan.
+pub async fn postgres_fusionar_clientes(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    duplicado_id: i32,
+    user_id: i32,
+) -> Result<Fusion, FusionError> {
+    if cliente_id == duplicado_id {
+        return Err(FusionError::MismoCliente);
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // se bloquean en orden de id para no cruzarse con otra fusión
+    let clientes = sqlx::query_as::<_, Cliente>(&format!(
+        "SELECT {}
+        FROM clientes
+        WHERE id IN ($1, $2) AND deleted_at IS NULL
+        ORDER BY id
+        FOR UPDATE",
+        CLIENTE_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(duplicado_id)
+    .fetch_all(&mut *tx)
+    .await?;
+    let superviviente = clientes
+        .iter()
+        .find(|c| c.id == cliente_id)
+        .ok_or(FusionError::ClienteNoEncontrado(cliente_id))?;
+    let duplicado = clientes
+        .iter()
+        .find(|c| c.id == duplicado_id)
+        .ok_or(FusionError::ClienteNoEncontrado(duplicado_id))?;
+    if let (Some(a), Some(b)) = (superviviente.user_id, duplicado.user_id)
+        && a != b
+    {
+        return Err(FusionError::UsuariosDistintos);
+    }
+
+    let fusionado: serde_json::Value =
+        sqlx::query_scalar("SELECT to_jsonb(c) FROM clientes c WHERE id = $1")
+            .bind(duplicado_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+    let mut movidos = serde_json::Map::new();
+    for tabla in TABLAS_MOVIDAS {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET cliente_id = $1 WHERE cliente_id = $2",
+            tabla
+        ))
+        .bind(cliente_id)
+        .bind(duplicado_id)
+        .execute(&mut *tx)
+        .await?;
+        movidos.insert(tabla.to_string(), result.rows_affected().into());
+    }
+
+    let facturas_conservadas: Vec<i32> =
+        sqlx::query_scalar("SELECT id FROM facturas WHERE cliente_id = $1 ORDER BY id")
+            .bind(duplicado_id)
+            .fetch_all(&mut *tx)
+            .await?;
+    movidos.insert(
+        "facturas_conservadas".to_string(),
+        facturas_conservadas.into(),
+    );
+
+    // las del duplicado dejan de ser predeterminadas si el superviviente ya tiene una
+    let result = sqlx::query(
+        "
+        UPDATE direcciones d
+        SET cliente_id = $1, predeterminada = d.predeterminada AND NOT EXISTS (
+            SELECT 1 FROM direcciones s
+            WHERE s.cliente_id = $1 AND s.tipo = d.tipo AND s.predeterminada
+        )
+        WHERE d.cliente_id = $2",
+    )
+    .bind(cliente_id)
+    .bind(duplicado_id)
+    .execute(&mut *tx)
+    .await?;
+    movidos.insert("direcciones".to_string(), result.rows_affected().into());
+
+    for (tabla, clave) in VINCULOS_MOVIDOS {
+        let result = sqlx::query(&format!(
+            "
+            UPDATE {tabla} v
+            SET cliente_id = $1
+            WHERE v.cliente_id = $2 AND NOT EXISTS (
+                SELECT 1 FROM {tabla} s WHERE s.cliente_id = $1 AND s.{clave} = v.{clave}
+            )",
+        ))
+        .bind(cliente_id)
+        .bind(duplicado_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!("DELETE FROM {} WHERE cliente_id = $1", tabla))
+            .bind(duplicado_id)
+            .execute(&mut *tx)
+            .await?;
+        movidos.insert(tabla.to_string(), result.rows_affected().into());
+    }
+
+    // user_id es único: se suelta del duplicado antes de pasarlo
+    sqlx::query("UPDATE clientes SET deleted_at = CURRENT_TIMESTAMP, user_id = NULL WHERE id = $1")
+        .bind(duplicado_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "
+        UPDATE clientes
+        SET user_id = COALESCE(user_id, $2), telefono = COALESCE(telefono, $3),
+            direccion = COALESCE(direccion, $4), tarifa_id = COALESCE(tarifa_id, $5)
+        WHERE id = $1",
+    )
+    .bind(cliente_id)
+    .bind(duplicado.user_id)
+    .bind(&duplicado.telefono)
+    .bind(&duplicado.direccion)
+    .bind(duplicado.tarifa_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let fusion = sqlx::query_as::<_, Fusion>(&format!(
+        "
+        INSERT INTO clientes_fusiones (cliente_id, fusionado_id, fusionado, movidos, user_id)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {}",
+        FUSION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(duplicado_id)
+    .bind(fusionado)
+    .bind(serde_json::Value::Object(movidos))
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(fusion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paginacion::Paginacion;
+    use crate::postgresini::pruebas::pool_de_pruebas;
+    use crate::{consentimientos, facturas, pedidos, segmentos, timeline};
+
+    #[test]
+    fn normaliza_telefonos() {
+        assert_eq!(
+            normalizar_telefono("+34 600 111 222"),
+            Some("600111222".to_string())
+        );
+        assert_eq!(
+            normalizar_telefono("600-111-222"),
+            Some("600111222".to_string())
+        );
+        assert_eq!(normalizar_telefono("12345"), None);
+        assert_eq!(normalizar_telefono(""), None);
+    }
+
+    #[test]
+    fn similitud_de_nombres() {
+        assert_eq!(similitud_nombre("José Pérez", "jose  PEREZ"), 1.0);
+        assert!(similitud_nombre("Juan Pérez", "Juan Perez Gómez") >= SIMILITUD_NOMBRE_MINIMA);
+        assert!(similitud_nombre("Juan Pérez", "María López") < SIMILITUD_NOMBRE_MINIMA);
+        assert_eq!(similitud_nombre("", ""), 0.0);
+    }
+
+    async fn crear_cliente(pool: &sqlx::Pool<sqlx::Postgres>, nombre: &str, email: &str) -> i32 {
+        sqlx::query_scalar("INSERT INTO clientes (nombre, email) VALUES ($1, $2) RETURNING id")
+            .bind(nombre)
+            .bind(email)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    #[ignore = "necesita TEST_DATABASE_URL"]
+    async fn fusion_conserva_las_facturas_del_duplicado() {
+        let (_guard, pool) = pool_de_pruebas().await;
+        let superviviente = crear_cliente(&pool, "Ana García", "ana@example.com").await;
+        let duplicado = crear_cliente(&pool, "Ana Garcia", "ana.garcia@example.com").await;
+
+        let pedido = pedidos::postgres_create_pedido(
+            &pool,
+            pedidos::PedidoRequest {
+                cliente_id: duplicado,
+                direccion_envio_id: None,
+                lineas: vec![pedidos::PedidoLineaRequest {
+                    articulo_id: 1,
+                    cantidad: 1,
+                }],
+            },
+        )
+        .await
+        .unwrap();
+        sqlx::query("UPDATE pedidos SET estado = $1 WHERE id = $2")
+            .bind(pedidos::ESTADO_ENTREGADO)
+            .bind(pedido.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let factura = facturas::postgres_create_factura_from_pedido(&pool, pedido.id)
+            .await
+            .unwrap();
+
+        let fusion = postgres_fusionar_clientes(&pool, superviviente, duplicado, 1)
+            .await
+            .unwrap();
+
+        assert_eq!(fusion.movidos["pedidos"], 1);
+        assert_eq!(
+            fusion.movidos["facturas_conservadas"],
+            serde_json::json!([factura.id])
+        );
+        let factura = facturas::postgres_get_factura_by_id(&pool, factura.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(factura.cliente_id, duplicado);
+        let pedido = pedidos::postgres_get_pedido_by_id(&pool, pedido.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pedido.cliente_id, superviviente);
+
+        // la factura sigue siendo del usuario del superviviente y sale en su timeline
+        sqlx::query("UPDATE clientes SET user_id = 77 WHERE id = $1")
+            .bind(superviviente)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            postgres_fusionado_en_usuario(&pool, duplicado, 77)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !postgres_fusionado_en_usuario(&pool, duplicado, 78)
+                .await
+                .unwrap()
+        );
+        let paginacion = Paginacion::new(None, None, 50, 200).unwrap();
+        let eventos = timeline::postgres_get_timeline(
+            &pool,
+            superviviente,
+            false,
+            Some(timeline::EVENTO_FACTURA_EMITIDA),
+            &paginacion,
+        )
+        .await
+        .unwrap()
+        .eventos;
+        assert_eq!(eventos.len(), 1);
+        assert_eq!(eventos[0].referencia_id, factura.id);
+    }
+
+    #[rocket::async_test]
+    #[ignore = "necesita TEST_DATABASE_URL"]
+    async fn fusion_mantiene_los_consentimientos_del_duplicado() {
+        let (_guard, pool) = pool_de_pruebas().await;
+        let superviviente = crear_cliente(&pool, "Luis Martín", "luis@example.com").await;
+        let duplicado = crear_cliente(&pool, "Luis Martin", "luis.martin@example.com").await;
+        consentimientos::postgres_create_consentimiento(
+            &pool,
+            duplicado,
+            1,
+            consentimientos::ConsentimientoRequest {
+                canal: consentimientos::CANAL_EMAIL.to_string(),
+                otorgado: true,
+                origen: "web".to_string(),
+                version_texto: "v1".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        postgres_fusionar_clientes(&pool, superviviente, duplicado, 1)
+            .await
+            .unwrap();
+
+        let vigentes = consentimientos::postgres_get_consentimientos(&pool, superviviente, None)
+            .await
+            .unwrap();
+        assert_eq!(vigentes.len(), 1);
+        assert!(vigentes[0].otorgado);
+
+        let segmento = segmentos::Segmento {
+            id: 0,
+            nombre: "todos".to_string(),
+            descripcion: None,
+            reglas: vec![],
+            creador_user_id: 1,
+            fecha_creacion: chrono::Utc::now().naive_utc(),
+            fecha_modificacion: None,
+        };
+        let paginacion = Paginacion::new(None, None, 50, 200).unwrap();
+        let email = segmentos::postgres_get_clientes_segmento(
+            &pool,
+            &segmento,
+            Some(consentimientos::CANAL_EMAIL),
+            &paginacion,
+        )
+        .await
+        .unwrap();
+        let ids: Vec<i32> = email.clientes.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![superviviente]);
+
+        let sms = segmentos::postgres_get_clientes_segmento(
+            &pool,
+            &segmento,
+            Some(consentimientos::CANAL_SMS),
+            &paginacion,
+        )
+        .await
+        .unwrap();
+        assert_eq!(sms.total, 0);
+    }
+}
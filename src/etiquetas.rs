@@ -0,0 +1,122 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+// las etiquetas se guardan sin espacios en los extremos y en minúsculas
+pub fn normalizar(etiqueta: &str) -> String {
+    etiqueta.trim().to_lowercase()
+}
+
+// #[serde(deserialize_with = "etiquetas::normalizada")]
+pub fn normalizada<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|e| normalizar(&e))
+}
+
+// normaliza y quita repetidas conservando el orden
+fn normalizadas<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut etiquetas: Vec<String> = Vec::new();
+    for etiqueta in Vec::<String>::deserialize(deserializer)? {
+        let etiqueta = normalizar(&etiqueta);
+        if !etiquetas.contains(&etiqueta) {
+            etiquetas.push(etiqueta);
+        }
+    }
+    Ok(etiquetas)
+}
+
+pub fn validate_etiqueta(etiqueta: &str) -> Result<(), ValidationError> {
+    if etiqueta.is_empty() || etiqueta.chars().count() > 50 {
+        return Err(ValidationError::new("etiqueta")
+            .with_message("cada etiqueta entre 1 y 50 caracteres".into()));
+    }
+    Ok(())
+}
+
+fn validate_etiquetas(etiquetas: &[String]) -> Result<(), ValidationError> {
+    etiquetas.iter().try_for_each(|e| validate_etiqueta(e))
+}
+
+// sustituye el conjunto completo de etiquetas del cliente
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct EtiquetasRequest {
+    #[serde(deserialize_with = "normalizadas")]
+    #[validate(
+        length(max = 50, message = "máximo 50 etiquetas"),
+        custom(function = "validate_etiquetas")
+    )]
+    pub etiquetas: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct EtiquetaUso {
+    pub etiqueta: String,
+    pub clientes: i64,
+}
+
+pub async fn postgres_get_etiquetas_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<String>, sqlx::Error> {
+    let etiquetas = sqlx::query_scalar(
+        "SELECT etiqueta FROM clientes_etiquetas WHERE cliente_id = $1 ORDER BY etiqueta",
+    )
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(etiquetas)
+}
+
+// todas las etiquetas en uso con cuántos clientes activos la llevan
+pub async fn postgres_get_etiquetas(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<EtiquetaUso>, sqlx::Error> {
+    let etiquetas = sqlx::query_as::<_, EtiquetaUso>(
+        "
+        SELECT ce.etiqueta, COUNT(*) AS clientes
+        FROM clientes_etiquetas ce
+        JOIN clientes c ON c.id = ce.cliente_id
+        WHERE c.deleted_at IS NULL
+        GROUP BY ce.etiqueta
+        ORDER BY ce.etiqueta",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(etiquetas)
+}
+
+pub async fn postgres_set_etiquetas_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    etiquetas: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM clientes_etiquetas WHERE cliente_id = $1")
+        .bind(cliente_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut etiquetas: Vec<String> = sqlx::query_scalar(
+        "
+        INSERT INTO clientes_etiquetas (cliente_id, etiqueta)
+        SELECT $1, UNNEST($2::VARCHAR[])
+        RETURNING etiqueta",
+    )
+    .bind(cliente_id)
+    .bind(etiquetas)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    etiquetas.sort();
+    Ok(etiquetas)
+}
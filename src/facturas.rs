@@ -0,0 +1,424 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::Validate;
+
+use crate::direcciones;
+use crate::iva::DesgloseIva;
+use crate::money::{Money, money_from_row};
+use crate::pedidos::ESTADO_ENTREGADO;
+
+// Serie de las facturas ordinarias y de las rectificativas. La numeración es
+// correlativa y sin huecos por serie y año: el contador se incrementa en la misma
+// transacción que inserta la factura, así un rollback no deja números perdidos.
+pub const SERIE_ORDINARIA: &str = "F";
+pub const SERIE_RECTIFICATIVA: &str = "R";
+
+pub const TIPO_ORDINARIA: &str = "ordinaria";
+pub const TIPO_RECTIFICATIVA: &str = "rectificativa";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct RectificativaRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 500, message = "entre 1 y 500 caracteres"))]
+    pub motivo: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Factura {
+    pub id: i32,
+    pub serie: String,
+    pub anio: i32,
+    pub numero: i32,
+    pub codigo: String,
+    pub tipo: String,
+    pub fecha_emision: chrono::NaiveDateTime,
+    pub pedido_id: i32,
+    pub factura_rectificada_id: Option<i32>,
+    pub motivo: Option<String>,
+    pub cliente_id: i32,
+    pub cliente_nombre: String,
+    pub cliente_email: Option<String>,
+    pub cliente_telefono: Option<String>,
+    pub cliente_direccion: Option<String>,
+    pub base_imponible: Money,
+    pub cuota_iva: Money,
+    pub total: Money,
+}
+
+impl<'r> FromRow<'r, PgRow> for Factura {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Factura {
+            id: row.try_get("id")?,
+            serie: row.try_get("serie")?,
+            anio: row.try_get("anio")?,
+            numero: row.try_get("numero")?,
+            codigo: row.try_get("codigo")?,
+            tipo: row.try_get("tipo")?,
+            fecha_emision: row.try_get("fecha_emision")?,
+            pedido_id: row.try_get("pedido_id")?,
+            factura_rectificada_id: row.try_get("factura_rectificada_id")?,
+            motivo: row.try_get("motivo")?,
+            cliente_id: row.try_get("cliente_id")?,
+            cliente_nombre: row.try_get("cliente_nombre")?,
+            cliente_email: row.try_get("cliente_email")?,
+            cliente_telefono: row.try_get("cliente_telefono")?,
+            cliente_direccion: row.try_get("cliente_direccion")?,
+            base_imponible: money_from_row(row, "base_imponible", "moneda")?,
+            cuota_iva: money_from_row(row, "cuota_iva", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FacturaLinea {
+    pub id: i32,
+    pub factura_id: i32,
+    pub articulo_id: i32,
+    pub descripcion: String,
+    pub cantidad: i32,
+    pub precio_unitario: Money,
+    pub descuento: i32,
+    pub subtotal: Money,
+    pub tipo_iva: String,
+    pub porcentaje_iva: i32,
+    pub cuota_iva: Money,
+    pub total: Money,
+}
+
+impl FacturaLinea {
+    pub fn desglose_iva(&self) -> DesgloseIva {
+        DesgloseIva {
+            tipo_iva: self.tipo_iva.clone(),
+            porcentaje_iva: self.porcentaje_iva,
+            base: self.subtotal,
+            cuota: self.cuota_iva,
+            total: self.total,
+        }
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for FacturaLinea {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(FacturaLinea {
+            id: row.try_get("id")?,
+            factura_id: row.try_get("factura_id")?,
+            articulo_id: row.try_get("articulo_id")?,
+            descripcion: row.try_get("descripcion")?,
+            cantidad: row.try_get("cantidad")?,
+            precio_unitario: money_from_row(row, "precio_unitario", "moneda")?,
+            descuento: row.try_get("descuento")?,
+            subtotal: money_from_row(row, "subtotal", "moneda")?,
+            tipo_iva: row.try_get("tipo_iva")?,
+            porcentaje_iva: row.try_get("porcentaje_iva")?,
+            cuota_iva: money_from_row(row, "cuota_iva", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum FacturaError {
+    Sqlx(sqlx::Error),
+    PedidoNoEncontrado(i32),
+    PedidoNoEntregado(i32),
+    PedidoYaFacturado(i32),
+    FacturaNoEncontrada(i32),
+    FacturaNoRectificable(i32),
+}
+
+impl fmt::Display for FacturaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FacturaError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            FacturaError::PedidoNoEncontrado(id) => write!(f, "pedido {} no encontrado", id),
+            FacturaError::PedidoNoEntregado(id) => write!(f, "el pedido {} no está entregado", id),
+            FacturaError::PedidoYaFacturado(id) => write!(f, "el pedido {} ya está facturado", id),
+            FacturaError::FacturaNoEncontrada(id) => write!(f, "factura {} no encontrada", id),
+            FacturaError::FacturaNoRectificable(id) => {
+                write!(f, "la factura {} no se puede rectificar", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FacturaError {}
+
+impl From<sqlx::Error> for FacturaError {
+    fn from(e: sqlx::Error) -> Self {
+        FacturaError::Sqlx(e)
+    }
+}
+
+const FACTURA_COLUMNS: &str = "
+    id, serie, anio, numero, codigo, tipo, fecha_emision, pedido_id,
+    factura_rectificada_id, motivo, cliente_id, cliente_nombre, cliente_email,
+    cliente_telefono, cliente_direccion, base_imponible, cuota_iva, total, moneda";
+
+const FACTURA_LINEA_COLUMNS: &str = "
+    id, factura_id, articulo_id, descripcion, cantidad, precio_unitario, descuento, subtotal,
+    tipo_iva, porcentaje_iva, cuota_iva, total, moneda";
+
+pub async fn postgres_get_factura_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Factura>, sqlx::Error> {
+    let factura = sqlx::query_as::<_, Factura>(&format!(
+        "SELECT {} FROM facturas WHERE id = $1",
+        FACTURA_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(factura)
+}
+
+pub async fn postgres_get_factura_lineas(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    factura_id: i32,
+) -> Result<Vec<FacturaLinea>, sqlx::Error> {
+    let lineas = sqlx::query_as::<_, FacturaLinea>(&format!(
+        "SELECT {} FROM facturas_lineas WHERE factura_id = $1 ORDER BY id",
+        FACTURA_LINEA_COLUMNS
+    ))
+    .bind(factura_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(lineas)
+}
+
+// siguiente número de la serie en el año, bloqueando la fila del contador
+async fn siguiente_numero(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    serie: &str,
+    anio: i32,
+) -> Result<i32, sqlx::Error> {
+    let numero: i32 = sqlx::query_scalar(
+        "
+        INSERT INTO facturas_contadores (serie, anio, ultimo_numero)
+        VALUES ($1, $2, 1)
+        ON CONFLICT (serie, anio)
+        DO UPDATE SET ultimo_numero = facturas_contadores.ultimo_numero + 1
+        RETURNING ultimo_numero",
+    )
+    .bind(serie)
+    .bind(anio)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(numero)
+}
+
+// Emite la factura de un pedido entregado copiando sus líneas y los datos del
+// cliente tal como están ahora; después ya no cambian aunque cambie el cliente.
+// Un pedido tiene como mucho una factura ordinaria sin rectificar.
+// La dirección es la de facturación predeterminada o, si no tiene, la antigua
+// dirección libre del cliente.
+pub async fn postgres_create_factura_from_pedido(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    pedido_id: i32,
+) -> Result<Factura, FacturaError> {
+    let mut tx = pool.begin().await?;
+
+    let pedido = sqlx::query(
+        "
+        SELECT
+            p.estado, p.base_imponible, p.cuota_iva, p.total, p.moneda,
+            c.id AS cliente_id, c.nombre, c.email, c.telefono, c.direccion
+        FROM pedidos p
+        JOIN clientes c ON c.id = p.cliente_id
+        WHERE p.id = $1
+        FOR UPDATE OF p",
+    )
+    .bind(pedido_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(FacturaError::PedidoNoEncontrado(pedido_id))?;
+
+    let estado: String = pedido.try_get("estado")?;
+    if estado != ESTADO_ENTREGADO {
+        return Err(FacturaError::PedidoNoEntregado(pedido_id));
+    }
+
+    // una factura anulada por su rectificativa ya no cuenta: el pedido se puede
+    // volver a facturar con los datos corregidos
+    let ya_facturado: bool = sqlx::query_scalar(
+        "
+        SELECT EXISTS (
+            SELECT 1
+            FROM facturas f
+            WHERE f.pedido_id = $1 AND f.tipo = $2
+            AND NOT EXISTS (SELECT 1 FROM facturas r WHERE r.factura_rectificada_id = f.id)
+        )",
+    )
+    .bind(pedido_id)
+    .bind(TIPO_ORDINARIA)
+    .fetch_one(&mut *tx)
+    .await?;
+    if ya_facturado {
+        return Err(FacturaError::PedidoYaFacturado(pedido_id));
+    }
+
+    let cliente_id: i32 = pedido.try_get("cliente_id")?;
+    let direccion = direcciones::postgres_get_direccion_predeterminada(
+        &mut *tx,
+        cliente_id,
+        direcciones::TIPO_FACTURACION,
+    )
+    .await?
+    .map(|d| d.formatear())
+    .or(pedido.try_get::<Option<String>, _>("direccion")?);
+
+    let fecha_emision = chrono::Utc::now().naive_utc();
+    let anio = chrono::Datelike::year(&fecha_emision);
+    let numero = siguiente_numero(&mut tx, SERIE_ORDINARIA, anio).await?;
+
+    let factura = sqlx::query_as::<_, Factura>(&format!(
+        "
+        INSERT INTO facturas (
+            serie, anio, numero, codigo, tipo, fecha_emision, pedido_id,
+            cliente_id, cliente_nombre, cliente_email, cliente_telefono, cliente_direccion,
+            base_imponible, cuota_iva, total, moneda
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        RETURNING {}",
+        FACTURA_COLUMNS
+    ))
+    .bind(SERIE_ORDINARIA)
+    .bind(anio)
+    .bind(numero)
+    .bind(codigo_factura(SERIE_ORDINARIA, anio, numero))
+    .bind(TIPO_ORDINARIA)
+    .bind(fecha_emision)
+    .bind(pedido_id)
+    .bind(cliente_id)
+    .bind(pedido.try_get::<String, _>("nombre")?)
+    .bind(pedido.try_get::<Option<String>, _>("email")?)
+    .bind(pedido.try_get::<Option<String>, _>("telefono")?)
+    .bind(direccion)
+    .bind(pedido.try_get::<i64, _>("base_imponible")?)
+    .bind(pedido.try_get::<i64, _>("cuota_iva")?)
+    .bind(pedido.try_get::<i64, _>("total")?)
+    .bind(pedido.try_get::<String, _>("moneda")?)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "
+        INSERT INTO facturas_lineas (
+            factura_id, articulo_id, descripcion, cantidad, precio_unitario, descuento,
+            subtotal, tipo_iva, porcentaje_iva, cuota_iva, total, moneda
+        )
+        SELECT
+            $1, pd.articulo_id, a.nombre, pd.cantidad, pd.precio_unitario, pd.descuento,
+            pd.subtotal,
+            pd.tipo_iva, pd.porcentaje_iva, pd.cuota_iva, pd.total, $2
+        FROM pedidos_detalles pd
+        JOIN articulos a ON a.id = pd.articulo_id
+        WHERE pd.pedido_id = $3
+        ORDER BY pd.id",
+    )
+    .bind(factura.id)
+    .bind(factura.total.currency().code())
+    .bind(pedido_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(factura)
+}
+
+// Factura rectificativa por anulación: copia la factura original con los
+// importes en negativo en la serie de rectificativas. Después el pedido se
+// puede facturar de nuevo con la factura corregida.
+pub async fn postgres_create_factura_rectificativa(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    factura_id: i32,
+    motivo: &str,
+) -> Result<Factura, FacturaError> {
+    let mut tx = pool.begin().await?;
+
+    let original = sqlx::query_as::<_, Factura>(&format!(
+        "SELECT {} FROM facturas WHERE id = $1 FOR UPDATE",
+        FACTURA_COLUMNS
+    ))
+    .bind(factura_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(FacturaError::FacturaNoEncontrada(factura_id))?;
+
+    let ya_rectificada: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM facturas WHERE factura_rectificada_id = $1)",
+    )
+    .bind(factura_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if original.tipo != TIPO_ORDINARIA || ya_rectificada {
+        return Err(FacturaError::FacturaNoRectificable(factura_id));
+    }
+
+    let fecha_emision = chrono::Utc::now().naive_utc();
+    let anio = chrono::Datelike::year(&fecha_emision);
+    let numero = siguiente_numero(&mut tx, SERIE_RECTIFICATIVA, anio).await?;
+
+    let rectificativa = sqlx::query_as::<_, Factura>(&format!(
+        "
+        INSERT INTO facturas (
+            serie, anio, numero, codigo, tipo, fecha_emision, pedido_id,
+            factura_rectificada_id, motivo,
+            cliente_id, cliente_nombre, cliente_email, cliente_telefono, cliente_direccion,
+            base_imponible, cuota_iva, total, moneda
+        )
+        SELECT
+            $1, $2, $3, $4, $5, $6, pedido_id, id, $7,
+            cliente_id, cliente_nombre, cliente_email, cliente_telefono, cliente_direccion,
+            -base_imponible, -cuota_iva, -total, moneda
+        FROM facturas
+        WHERE id = $8
+        RETURNING {}",
+        FACTURA_COLUMNS
+    ))
+    .bind(SERIE_RECTIFICATIVA)
+    .bind(anio)
+    .bind(numero)
+    .bind(codigo_factura(SERIE_RECTIFICATIVA, anio, numero))
+    .bind(TIPO_RECTIFICATIVA)
+    .bind(fecha_emision)
+    .bind(motivo)
+    .bind(factura_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "
+        INSERT INTO facturas_lineas (
+            factura_id, articulo_id, descripcion, cantidad, precio_unitario, descuento,
+            subtotal, tipo_iva, porcentaje_iva, cuota_iva, total, moneda
+        )
+        SELECT
+            $1, articulo_id, descripcion, -cantidad, precio_unitario, descuento, -subtotal,
+            tipo_iva, porcentaje_iva, -cuota_iva, -total, moneda
+        FROM facturas_lineas
+        WHERE factura_id = $2
+        ORDER BY id",
+    )
+    .bind(rectificativa.id)
+    .bind(factura_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(rectificativa)
+}
+
+// F2026-000001
+pub fn codigo_factura(serie: &str, anio: i32, numero: i32) -> String {
+    format!("{}{}-{:06}", serie, anio, numero)
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+pub const TIPOS_INTERACCION: [&str; 4] = ["llamada", "reunion", "email", "nota"];
+
+pub fn validate_tipo_interaccion(tipo: &str) -> Result<(), ValidationError> {
+    if !TIPOS_INTERACCION.contains(&tipo) {
+        return Err(ValidationError::new("tipo")
+            .with_message("debe ser llamada, reunion, email o nota".into()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct InteraccionRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_tipo_interaccion"))]
+    pub tipo: String,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 10000, message = "entre 1 y 10000 caracteres"))]
+    pub cuerpo: String,
+    // cuándo ocurrió; por defecto ahora al crear y sin cambios al editar
+    pub fecha: Option<chrono::NaiveDateTime>,
+    pub fecha_seguimiento: Option<chrono::NaiveDate>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Interaccion {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub autor_user_id: i32,
+    pub tipo: String,
+    pub cuerpo: String,
+    pub fecha: chrono::NaiveDateTime,
+    pub fecha_seguimiento: Option<chrono::NaiveDate>,
+    pub fecha_creacion: chrono::NaiveDateTime,
+    pub fecha_modificacion: Option<chrono::NaiveDateTime>,
+}
+
+const INTERACCION_COLUMNS: &str = "id, cliente_id, autor_user_id, tipo, cuerpo, fecha, fecha_seguimiento, fecha_creacion, fecha_modificacion";
+
+pub async fn postgres_get_interacciones_by_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Interaccion>, sqlx::Error> {
+    let interacciones = sqlx::query_as::<_, Interaccion>(&format!(
+        "SELECT {} FROM interacciones WHERE cliente_id = $1 ORDER BY fecha DESC, id DESC",
+        INTERACCION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(interacciones)
+}
+
+pub async fn postgres_get_interaccion_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Interaccion>, sqlx::Error> {
+    let interaccion = sqlx::query_as::<_, Interaccion>(&format!(
+        "SELECT {} FROM interacciones WHERE id = $1",
+        INTERACCION_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(interaccion)
+}
+
+// None si el cliente no existe o está borrado
+pub async fn postgres_create_interaccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    autor_user_id: i32,
+    interaccion: InteraccionRequest,
+) -> Result<Option<Interaccion>, sqlx::Error> {
+    let new_interaccion = sqlx::query_as::<_, Interaccion>(&format!(
+        "
+        INSERT INTO interacciones (
+            cliente_id, autor_user_id, tipo, cuerpo, fecha, fecha_seguimiento
+        )
+        SELECT id, $2, $3, $4, COALESCE($5, CURRENT_TIMESTAMP), $6
+        FROM clientes
+        WHERE id = $1 AND deleted_at IS NULL
+        RETURNING {}",
+        INTERACCION_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(autor_user_id)
+    .bind(interaccion.tipo)
+    .bind(interaccion.cuerpo)
+    .bind(interaccion.fecha)
+    .bind(interaccion.fecha_seguimiento)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(new_interaccion)
+}
+
+pub async fn postgres_update_interaccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    interaccion: InteraccionRequest,
+) -> Result<Option<Interaccion>, sqlx::Error> {
+    let updated_interaccion = sqlx::query_as::<_, Interaccion>(&format!(
+        "
+        UPDATE interacciones
+        SET tipo = $1, cuerpo = $2, fecha = COALESCE($3, fecha), fecha_seguimiento = $4,
+            fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $5
+        RETURNING {}",
+        INTERACCION_COLUMNS
+    ))
+    .bind(interaccion.tipo)
+    .bind(interaccion.cuerpo)
+    .bind(interaccion.fecha)
+    .bind(interaccion.fecha_seguimiento)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_interaccion)
+}
+
+pub async fn postgres_delete_interaccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM interacciones WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::ValidationError;
+
+use crate::money::{Money, MoneyError, money_from_row};
+
+// Catálogo de tipos de IVA. Los códigos son fijos, el porcentaje vive en la tabla
+// tipos_iva (en puntos básicos, 2100 = 21%) y se copia a cada línea de pedido.
+pub const TIPOS_IVA: [(&str, &str, i32); 4] = [
+    ("general", "IVA general", 2100),
+    ("reducido", "IVA reducido", 1000),
+    ("superreducido", "IVA superreducido", 400),
+    ("exento", "Exento de IVA", 0),
+];
+
+pub const TIPO_IVA_POR_DEFECTO: &str = "general";
+
+pub fn tipo_iva_por_defecto() -> String {
+    TIPO_IVA_POR_DEFECTO.to_string()
+}
+
+pub fn validate_tipo_iva(tipo_iva: &str) -> Result<(), ValidationError> {
+    if !TIPOS_IVA.iter().any(|(codigo, _, _)| *codigo == tipo_iva) {
+        return Err(ValidationError::new("tipo_iva")
+            .with_message("debe ser general, reducido, superreducido o exento".into()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, FromRow, Debug)]
+pub struct TipoIva {
+    pub codigo: String,
+    pub descripcion: String,
+    pub porcentaje: i32,
+}
+
+// base, cuota y total de una línea o de un grupo de líneas con el mismo tipo
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DesgloseIva {
+    pub tipo_iva: String,
+    pub porcentaje_iva: i32,
+    pub base: Money,
+    pub cuota: Money,
+    pub total: Money,
+}
+
+impl DesgloseIva {
+    pub fn calcular(tipo_iva: &str, porcentaje_iva: i32, base: Money) -> Result<Self, MoneyError> {
+        let cuota = base.percentage(porcentaje_iva)?;
+        Ok(DesgloseIva {
+            tipo_iva: tipo_iva.to_string(),
+            porcentaje_iva,
+            base,
+            cuota,
+            total: base.checked_add(cuota)?,
+        })
+    }
+
+    // agrupa por tipo y porcentaje sumando bases, cuotas y totales ya redondeados
+    pub fn agrupar<I>(lineas: I) -> Result<Vec<DesgloseIva>, MoneyError>
+    where
+        I: IntoIterator<Item = DesgloseIva>,
+    {
+        let mut grupos: Vec<DesgloseIva> = Vec::new();
+        for linea in lineas {
+            match grupos
+                .iter_mut()
+                .find(|g| g.tipo_iva == linea.tipo_iva && g.porcentaje_iva == linea.porcentaje_iva)
+            {
+                Some(grupo) => {
+                    grupo.base = grupo.base.checked_add(linea.base)?;
+                    grupo.cuota = grupo.cuota.checked_add(linea.cuota)?;
+                    grupo.total = grupo.total.checked_add(linea.total)?;
+                }
+                None => grupos.push(linea),
+            }
+        }
+        grupos.sort_by_key(|g| std::cmp::Reverse(g.porcentaje_iva));
+        Ok(grupos)
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for DesgloseIva {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(DesgloseIva {
+            tipo_iva: row.try_get("tipo_iva")?,
+            porcentaje_iva: row.try_get("porcentaje_iva")?,
+            base: money_from_row(row, "base", "moneda")?,
+            cuota: money_from_row(row, "cuota", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+        })
+    }
+}
+
+pub async fn postgres_get_tipos_iva(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<TipoIva>, sqlx::Error> {
+    let tipos_iva = sqlx::query_as::<_, TipoIva>(
+        "
+        SELECT
+            codigo, descripcion, porcentaje
+        FROM tipos_iva
+        ORDER BY porcentaje DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tipos_iva)
+}
+
+// informe de IVA repercutido por tipo entre dos fechas (ambas opcionales)
+pub async fn postgres_get_informe_iva(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    desde: Option<chrono::NaiveDate>,
+    hasta: Option<chrono::NaiveDate>,
+) -> Result<Vec<DesgloseIva>, sqlx::Error> {
+    let informe = sqlx::query_as::<_, DesgloseIva>(
+        "
+        SELECT
+            pd.tipo_iva, pd.porcentaje_iva, p.moneda,
+            SUM(pd.subtotal)::BIGINT AS base,
+            SUM(pd.cuota_iva)::BIGINT AS cuota,
+            SUM(pd.total)::BIGINT AS total
+        FROM pedidos_detalles pd
+        JOIN pedidos p ON p.id = pd.pedido_id
+        WHERE ($1::DATE IS NULL OR p.fecha_pedido >= $1::DATE)
+        AND ($2::DATE IS NULL OR p.fecha_pedido < $2::DATE + 1)
+        AND p.estado <> 'Cancelado'
+        GROUP BY pd.tipo_iva, pd.porcentaje_iva, p.moneda
+        ORDER BY p.moneda, pd.porcentaje_iva DESC",
+    )
+    .bind(desde)
+    .bind(hasta)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(informe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+
+    fn eur(amount: i64) -> Money {
+        Money::from_minor(amount, Currency::Eur)
+    }
+
+    #[test]
+    fn calcula_cuota_y_total() {
+        let desglose = DesgloseIva::calcular("general", 2100, eur(1_000)).unwrap();
+        assert_eq!(desglose.cuota, eur(210));
+        assert_eq!(desglose.total, eur(1_210));
+
+        // 0.05 * 21% = 0.0105 -> 0.01
+        let desglose = DesgloseIva::calcular("general", 2100, eur(5)).unwrap();
+        assert_eq!(desglose.cuota, eur(1));
+
+        let exento = DesgloseIva::calcular("exento", 0, eur(999)).unwrap();
+        assert_eq!(exento.cuota, eur(0));
+        assert_eq!(exento.total, eur(999));
+    }
+
+    #[test]
+    fn agrupa_por_tipo_con_las_cuotas_ya_redondeadas() {
+        let lineas = vec![
+            DesgloseIva::calcular("reducido", 1000, eur(5)).unwrap(),
+            DesgloseIva::calcular("general", 2100, eur(1_000)).unwrap(),
+            DesgloseIva::calcular("reducido", 1000, eur(5)).unwrap(),
+        ];
+        let grupos = DesgloseIva::agrupar(lineas).unwrap();
+
+        assert_eq!(grupos.len(), 2);
+        assert_eq!(grupos[0].tipo_iva, "general");
+        assert_eq!(grupos[1].tipo_iva, "reducido");
+        // cada línea redondea 0.005 a 0.01; el grupo suma 0.02, no 10% de 0.10
+        assert_eq!(grupos[1].base, eur(10));
+        assert_eq!(grupos[1].cuota, eur(2));
+        assert_eq!(grupos[1].total, eur(12));
+    }
+
+    #[test]
+    fn separa_el_mismo_tipo_con_porcentajes_distintos() {
+        let lineas = vec![
+            DesgloseIva::calcular("general", 1800, eur(100)).unwrap(),
+            DesgloseIva::calcular("general", 2100, eur(100)).unwrap(),
+        ];
+        let grupos = DesgloseIva::agrupar(lineas).unwrap();
+        let porcentajes: Vec<i32> = grupos.iter().map(|g| g.porcentaje_iva).collect();
+        assert_eq!(porcentajes, vec![2100, 1800]);
+    }
+
+    #[test]
+    fn agrupar_detecta_desbordamientos() {
+        let linea = DesgloseIva::calcular("exento", 0, eur(i64::MAX)).unwrap();
+        assert_eq!(
+            DesgloseIva::agrupar(vec![linea.clone(), linea]).unwrap_err(),
+            MoneyError::Overflow
+        );
+    }
+}
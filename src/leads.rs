@@ -0,0 +1,298 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+use crate::clientes::{CLIENTE_COLUMNS, Cliente};
+
+pub const ESTADO_NUEVO: &str = "nuevo";
+pub const ESTADO_CONTACTADO: &str = "contactado";
+pub const ESTADO_CUALIFICADO: &str = "cualificado";
+pub const ESTADO_DESCARTADO: &str = "descartado";
+pub const ESTADO_CONVERTIDO: &str = "convertido";
+
+// convertido solo se alcanza con POST /lead/<id>/cliente
+pub const ESTADOS_EDITABLES: [&str; 4] = [
+    ESTADO_NUEVO,
+    ESTADO_CONTACTADO,
+    ESTADO_CUALIFICADO,
+    ESTADO_DESCARTADO,
+];
+
+pub const ORIGENES_LEAD: [&str; 5] = ["web", "referido", "feria", "llamada", "otro"];
+
+pub fn validate_estado_lead(estado: &str) -> Result<(), ValidationError> {
+    if !ESTADOS_EDITABLES.contains(&estado) {
+        return Err(ValidationError::new("estado")
+            .with_message("debe ser nuevo, contactado, cualificado o descartado".into()));
+    }
+    Ok(())
+}
+
+pub fn validate_origen_lead(origen: &str) -> Result<(), ValidationError> {
+    if !ORIGENES_LEAD.contains(&origen) {
+        return Err(ValidationError::new("origen")
+            .with_message("debe ser web, referido, feria, llamada u otro".into()));
+    }
+    Ok(())
+}
+
+fn estado_por_defecto() -> String {
+    ESTADO_NUEVO.to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct LeadRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(
+        email(message = "formato de email no válido"),
+        length(max = 100, message = "máximo 100 caracteres")
+    )]
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(
+        length(max = 20, message = "máximo 20 caracteres"),
+        custom(function = "crate::validacion::validate_telefono")
+    )]
+    pub telefono: Option<String>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 100, message = "máximo 100 caracteres"))]
+    pub empresa: Option<String>,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_origen_lead"))]
+    pub origen: String,
+    #[serde(
+        default = "estado_por_defecto",
+        deserialize_with = "crate::validacion::trimmed"
+    )]
+    #[validate(custom(function = "validate_estado_lead"))]
+    pub estado: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 10000, message = "máximo 10000 caracteres"))]
+    pub notas: Option<String>,
+}
+
+// la cuenta de auth del cliente es opcional y se puede vincular más tarde
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct LeadConversionRequest {
+    #[serde(default)]
+    pub user_id: Option<i32>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    pub direccion: Option<String>,
+}
+
+// cliente_id se informa al convertir el lead
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Lead {
+    pub id: i32,
+    pub nombre: String,
+    pub email: Option<String>,
+    pub telefono: Option<String>,
+    pub empresa: Option<String>,
+    pub origen: String,
+    pub estado: String,
+    pub notas: Option<String>,
+    pub propietario_user_id: i32,
+    pub cliente_id: Option<i32>,
+    pub fecha_creacion: chrono::NaiveDateTime,
+    pub fecha_modificacion: Option<chrono::NaiveDateTime>,
+    pub fecha_conversion: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug)]
+pub enum LeadError {
+    Sqlx(sqlx::Error),
+    LeadNoEncontrado(i32),
+    YaConvertido(i32),
+    Descartado(i32),
+    SinEmail(i32),
+    ClienteDuplicado,
+}
+
+impl fmt::Display for LeadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeadError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            LeadError::LeadNoEncontrado(id) => write!(f, "lead {} no encontrado", id),
+            LeadError::YaConvertido(id) => write!(f, "el lead {} ya se convirtió en cliente", id),
+            LeadError::Descartado(id) => write!(f, "el lead {} está descartado", id),
+            LeadError::SinEmail(id) => {
+                write!(f, "el lead {} necesita un email para ser cliente", id)
+            }
+            LeadError::ClienteDuplicado => {
+                write!(f, "ya existe un cliente con ese user_id o email")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LeadError {}
+
+impl From<sqlx::Error> for LeadError {
+    fn from(e: sqlx::Error) -> Self {
+        match e.as_database_error() {
+            Some(db) if db.is_unique_violation() => LeadError::ClienteDuplicado,
+            _ => LeadError::Sqlx(e),
+        }
+    }
+}
+
+const LEAD_COLUMNS: &str = "id, nombre, email, telefono, empresa, origen, estado, notas, propietario_user_id, cliente_id, fecha_creacion, fecha_modificacion, fecha_conversion";
+
+pub async fn postgres_get_leads(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    estado: Option<&str>,
+    origen: Option<&str>,
+) -> Result<Vec<Lead>, sqlx::Error> {
+    let leads = sqlx::query_as::<_, Lead>(&format!(
+        "SELECT {}
+        FROM leads
+        WHERE ($1::TEXT IS NULL OR estado = $1)
+        AND ($2::TEXT IS NULL OR origen = $2)
+        ORDER BY fecha_creacion DESC, id DESC",
+        LEAD_COLUMNS
+    ))
+    .bind(estado)
+    .bind(origen)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(leads)
+}
+
+pub async fn postgres_get_lead_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Lead>, sqlx::Error> {
+    let lead =
+        sqlx::query_as::<_, Lead>(&format!("SELECT {} FROM leads WHERE id = $1", LEAD_COLUMNS))
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(lead)
+}
+
+pub async fn postgres_create_lead(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    propietario_user_id: i32,
+    lead: LeadRequest,
+) -> Result<Lead, sqlx::Error> {
+    let new_lead = sqlx::query_as::<_, Lead>(&format!(
+        "
+        INSERT INTO leads (
+            nombre, email, telefono, empresa, origen, estado, notas, propietario_user_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING {}",
+        LEAD_COLUMNS
+    ))
+    .bind(lead.nombre)
+    .bind(lead.email)
+    .bind(lead.telefono)
+    .bind(lead.empresa)
+    .bind(lead.origen)
+    .bind(lead.estado)
+    .bind(lead.notas)
+    .bind(propietario_user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(new_lead)
+}
+
+// None si el lead no existe o ya está convertido
+pub async fn postgres_update_lead(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    lead: LeadRequest,
+) -> Result<Option<Lead>, sqlx::Error> {
+    let updated_lead = sqlx::query_as::<_, Lead>(&format!(
+        "
+        UPDATE leads
+        SET nombre = $1, email = $2, telefono = $3, empresa = $4, origen = $5, estado = $6,
+            notas = $7, fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $8 AND estado <> $9
+        RETURNING {}",
+        LEAD_COLUMNS
+    ))
+    .bind(lead.nombre)
+    .bind(lead.email)
+    .bind(lead.telefono)
+    .bind(lead.empresa)
+    .bind(lead.origen)
+    .bind(lead.estado)
+    .bind(lead.notas)
+    .bind(id)
+    .bind(ESTADO_CONVERTIDO)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_lead)
+}
+
+// Crea el cliente con los datos del lead, marca el lead como convertido y pasa
+// sus oportunidades al nuevo cliente, todo en una transacción.
+pub async fn postgres_convert_lead(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    conversion: LeadConversionRequest,
+) -> Result<Cliente, LeadError> {
+    let mut tx = pool.begin().await?;
+
+    let lead = sqlx::query_as::<_, Lead>(&format!(
+        "SELECT {} FROM leads WHERE id = $1 FOR UPDATE",
+        LEAD_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(LeadError::LeadNoEncontrado(id))?;
+
+    match lead.estado.as_str() {
+        ESTADO_CONVERTIDO => return Err(LeadError::YaConvertido(id)),
+        ESTADO_DESCARTADO => return Err(LeadError::Descartado(id)),
+        _ => {}
+    }
+    let email = lead.email.ok_or(LeadError::SinEmail(id))?;
+
+    let cliente = sqlx::query_as::<_, Cliente>(&format!(
+        "INSERT INTO clientes (user_id, nombre, email, telefono, direccion)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
+    .bind(conversion.user_id)
+    .bind(lead.nombre)
+    .bind(email)
+    .bind(lead.telefono)
+    .bind(conversion.direccion)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "
+        UPDATE leads
+        SET estado = $1, cliente_id = $2, fecha_conversion = CURRENT_TIMESTAMP
+        WHERE id = $3",
+    )
+    .bind(ESTADO_CONVERTIDO)
+    .bind(cliente.id)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE oportunidades SET cliente_id = $1 WHERE lead_id = $2")
+        .bind(cliente.id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(cliente)
+}
@@ -1,35 +1,72 @@
 use reqwest::Client;
-use rocket::http::Status;
+use rocket::http::{ContentType, Status};
 use rocket::outcome::Outcome;
 use rocket::request::{self, FromRequest, Request};
 use rocket::response::status::NotFound;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
-use rocket::{State, get, launch, post, routes};
-use rocket::{catch, catchers, put};
+use rocket::{State, delete, get, launch, post, routes};
+use rocket::{catch, catchers, patch, put};
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
 use std::collections::HashMap;
 use std::env;
 
 mod articulos;
+mod catalogocache;
 mod clientes;
+mod consentimientos;
+mod contactos;
+mod corpcache;
 mod corpservice;
+mod direcciones;
+mod duplicados;
+mod etiquetas;
+mod facturas;
+mod interacciones;
 mod issuerequest;
 mod issueservice;
+mod iva;
+mod leads;
+mod mergepatch;
+mod money;
+mod notificaciones;
+mod oportunidades;
+mod paginacion;
+mod pagos;
+mod pdf;
+mod pedidos;
 mod postgresini;
+mod precios;
+mod presupuestos;
+mod rgpd;
+mod segmentos;
 mod sesion;
+mod sincronizacion;
+mod tareas;
+mod timeline;
+mod validacion;
 
 use articulos::{
-    Articulo, ArticuloRequest, postgres_create_articulo, postgres_get_articulo_by_id,
-    postgres_get_articulos, postgres_update_articulo,
+    Articulo, ArticuloPatch, ArticuloRequest, postgres_create_articulo,
+    postgres_get_articulo_by_id, postgres_patch_articulo, postgres_restore_articulo,
+    postgres_soft_delete_articulo, postgres_update_articulo,
 };
 use clientes::{Cliente, postgres_get_cliente_by_user_id};
-use sesion::{AuthProfile, redis_get_session_by_token, redis_set_session_by_token};
+use sesion::{
+    AuthProfile, redis_get_session_by_token, redis_is_token_revoked, redis_revoke_token,
+    redis_set_session_by_token,
+};
+use validacion::{FieldErrors, Validated, ValidationErrorResponse};
+use validator::Validate;
 
 struct AppState {
     pool: sqlx::Pool<sqlx::Postgres>,
-    redis_connection_string: String,
-    auth_redis_ttl: i64,
+    redis: redis::aio::ConnectionManager,
+    auth_redis_ttl: u64,
+    auth_revoked_ttl: u64,
+    politica_corp: sincronizacion::PoliticaSincronizacion,
+    corp_cache: corpcache::CorpCache,
+    catalogo_cache: catalogocache::CatalogoCache,
 }
 
 #[launch]
@@ -48,11 +85,25 @@ async fn rocket() -> _ {
 
     //print!("redis_connection_string: {}\n", redis_connection_string);
 
+    // una única conexión para toda la aplicación
+    let redis = sesion::redis_connection_manager(&redis_connection_string)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error connecting to redis: {:?}", e);
+            std::process::exit(1);
+        });
+
     let auth_redis_ttl = std::env::var("AUTH_REDIS_TTL")
         .unwrap_or_else(|_| "120".to_string())
-        .parse::<i64>()
+        .parse::<u64>()
         .expect("AUTH_REDIS_TTL must be a number");
 
+    // segundos que se recuerda un token revocado con /logout
+    let auth_revoked_ttl = std::env::var("AUTH_REVOKED_TTL")
+        .unwrap_or_else(|_| "86400".to_string())
+        .parse::<u64>()
+        .expect("AUTH_REVOKED_TTL must be a number");
+
     // sacamos de env POSTGRES_DB
     let postgres_db =
         env::var("POSTGRES_DB").expect("La variable de entorno POSTGRES_DB no está definida");
@@ -72,40 +123,198 @@ async fn rocket() -> _ {
 
     let pool: sqlx::Pool<sqlx::Postgres> = sqlx::postgres::PgPool::connect(postgres_url.as_str())
         .await
-        .or_else(|err| {
+        .inspect_err(|err| {
             eprintln!("Error connecting to the database: {:?}", err);
-            Err(err)
         })
         .unwrap();
 
     postgresini::initialization(pool.clone()).await;
 
+    // cada cuántos segundos se buscan tareas vencidas para notificarlas (0 lo desactiva)
+    let tareas_job_intervalo = std::env::var("TAREAS_JOB_INTERVALO")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse::<u64>()
+        .expect("TAREAS_JOB_INTERVALO must be a number");
+    if tareas_job_intervalo > 0 {
+        tareas::lanzar_job_vencidas(
+            pool.clone(),
+            std::time::Duration::from_secs(tareas_job_intervalo),
+        );
+    }
+
+    // segundos que se guardan en redis los datos del directorio corporativo
+    // (ttl_negativo para los 404) y cuánto más se sirven caducados mientras se revalidan
+    let corp_cache_ttl = |nombre: &str, por_defecto: &str| {
+        std::env::var(nombre)
+            .unwrap_or_else(|_| por_defecto.to_string())
+            .parse::<i64>()
+            .unwrap_or_else(|_| panic!("{} must be a number", nombre))
+    };
+    let corp_cache = corpcache::CorpCache {
+        redis: redis.clone(),
+        ttl: corp_cache_ttl("CORP_CACHE_TTL", "300"),
+        ttl_negativo: corp_cache_ttl("CORP_CACHE_TTL_NEGATIVO", "60"),
+        stale: corp_cache_ttl("CORP_CACHE_STALE", "3600"),
+    };
+
+    // segundos que se guardan en redis las lecturas del catálogo de artículos
+    let catalogo_cache = catalogocache::CatalogoCache {
+        redis: redis.clone(),
+        ttl: std::env::var("ARTICULOS_CACHE_TTL")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse::<u64>()
+            .expect("ARTICULOS_CACHE_TTL must be a number"),
+    };
+    // la base de datos se acaba de inicializar: lo cacheado antes ya no vale
+    catalogo_cache.invalidar().await;
+
+    // qué gana en cada campo al sincronizar con el directorio corporativo
+    let politica_corp = sincronizacion::PoliticaSincronizacion::parse(
+        &std::env::var("CORP_SYNC_PRECEDENCIA").unwrap_or_default(),
+    )
+    .expect("CORP_SYNC_PRECEDENCIA must be campo=corp|local pairs");
+
+    // cada cuántos segundos se sincroniza con el directorio corporativo (0 lo desactiva)
+    let corp_sync_intervalo = std::env::var("CORP_SYNC_INTERVALO")
+        .unwrap_or_else(|_| "3600".to_string())
+        .parse::<u64>()
+        .expect("CORP_SYNC_INTERVALO must be a number");
+    if corp_sync_intervalo > 0 {
+        sincronizacion::lanzar_job_sincronizacion(
+            pool.clone(),
+            corp_cache.clone(),
+            politica_corp,
+            std::time::Duration::from_secs(corp_sync_intervalo),
+        );
+    }
+
     let cors = cors_options().to_cors().expect("Error al configurar CORS");
 
     rocket::build()
         .manage(AppState {
             pool,
-            redis_connection_string,
+            redis,
             auth_redis_ttl,
+            auth_revoked_ttl,
+            politica_corp,
+            corp_cache,
+            catalogo_cache,
         })
         .mount(
             "/",
             routes![
                 auth,
                 authback,
+                deletearticulo,
+                deletecliente,
+                deletecontacto,
+                deletecorpcache,
+                deletedireccion,
+                deletepromocion,
+                deletesegment,
+                deleteinteraccion,
+                deleteprofile,
+                deletetarifaprecio,
+                deletetask,
                 getarticulo,
                 getarticulos,
+                getcliente,
+                getcorpconflictos,
+                getduplicados,
+                getetiquetas,
+                getfactura,
+                getfacturapdf,
+                getinformeiva,
+                getlead,
+                getleads,
+                getnotificaciones,
+                getoportunidad,
+                getoportunidades,
+                getpedido,
+                getpedidopdf,
+                getpipeline,
+                getprecio,
+                getpresupuesto,
+                getpromociones,
+                getsegment,
+                getsegmentclientes,
+                getsegments,
+                gettarifa,
+                gettarifas,
+                gettask,
+                gettasks,
+                gettiposiva,
+                clienteconsentimientos,
+                clienteconsentimientoshistorial,
+                clientecontactos,
+                clientedirecciones,
+                clienteetiquetas,
+                clienteexport,
+                clientefusiones,
+                clienteinteracciones,
+                clientesolicitudesrgpd,
+                clientetimeline,
                 healthz,
+                logout,
+                patcharticulo,
+                patchcliente,
+                patchprofile,
+                postclienteanonimizar,
+                postclienteconsentimiento,
+                postclientecontacto,
+                postclientedireccion,
+                postclientefusion,
+                postclienteinteraccion,
+                postcorpsync,
+                postinteraccion,
                 postarticulo,
+                postfactura,
+                postfacturarectificativa,
                 postissue,
+                postlead,
+                postleadcliente,
+                postoportunidad,
+                postpago,
+                postpedido,
+                postpresupuesto,
+                postpresupuestopedido,
+                postpromocion,
+                postsegment,
                 postprofile,
+                postprofilesync,
+                posttarifa,
+                posttask,
                 profile,
+                profileexport,
+                profileinteracciones,
+                profiletimeline,
                 profiles,
                 putarticulo,
+                putcliente,
+                putclienteetiquetas,
+                putclientetarifa,
+                putclienteusuario,
+                putcontacto,
+                putdireccion,
+                putinteraccion,
+                putlead,
+                putnotificacionleida,
+                putoportunidad,
+                putoportunidadetapa,
+                putpedidoestado,
+                putpresupuestoestado,
                 putprofile,
+                putprofiletarifa,
+                putsegment,
+                puttarifaprecio,
+                puttask,
+                puttaskestado,
+                restorearticulo,
+                restorecliente,
+                restoreprofile,
             ],
         )
-        .register("/", catchers![not_found])
+        .register("/", catchers![not_found, unprocessable_entity])
         .attach(cors)
 }
 
@@ -117,13 +326,25 @@ impl<'r> FromRequest<'r> for BearerToken {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        if let Some(auth_header) = request.headers().get_one("Authorization") {
-            if auth_header.starts_with("Bearer ") {
-                let token = auth_header[7..].to_string();
-                return Outcome::Success(BearerToken(token));
+        let Some(token) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|auth_header| auth_header.strip_prefix("Bearer "))
+        else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        // un token revocado con /logout se rechaza en todas las rutas; si redis
+        // no responde decide el servidor de auth
+        if let Some(state) = request.rocket().state::<AppState>() {
+            match redis_is_token_revoked(&state.redis, token).await {
+                Ok(true) => return Outcome::Error((Status::Unauthorized, ())),
+                Ok(false) => {}
+                Err(e) => eprintln!("Error checking token revocation: {:?}", e),
             }
         }
-        Outcome::Error((Status::Unauthorized, ()))
+
+        Outcome::Success(BearerToken(token.to_string()))
     }
 }
 
@@ -141,6 +362,18 @@ fn not_found(req: &Request) -> NotFound<String> {
     NotFound(format!("Lo siento, la ruta '{}' no existe.", req.uri()))
 }
 
+#[catch(422)]
+fn unprocessable_entity(req: &Request) -> Json<ValidationErrorResponse> {
+    // los errores por campo los deja el data guard Validated en la caché de la petición
+    let errors = req.local_cache(FieldErrors::default).clone();
+    eprintln!("Datos no válidos en {}: {:?}", req.uri(), errors);
+
+    Json(ValidationErrorResponse {
+        status: "error".to_string(),
+        errors,
+    })
+}
+
 fn cors_options() -> CorsOptions {
     let allowed_origins =
         AllowedOrigins::some_exact(&["https://crm.mydomain.com", "http://localhost:5173/"]);
@@ -151,6 +384,7 @@ fn cors_options() -> CorsOptions {
         allowed_methods: vec![
             rocket::http::Method::Delete,
             rocket::http::Method::Get,
+            rocket::http::Method::Patch,
             rocket::http::Method::Post,
             rocket::http::Method::Put,
             rocket::http::Method::Options,
@@ -218,6 +452,13 @@ async fn auth_profile(token: BearerToken) -> Result<Option<AuthProfile>, Status>
     }
 }
 
+fn is_admin(profile: &AuthProfile) -> bool {
+    profile
+        .attributes
+        .get("role")
+        .is_some_and(|role| role == "admin")
+}
+
 #[derive(Serialize, Deserialize)]
 struct AuthResponse {
     status: String,
@@ -236,18 +477,13 @@ async fn auth(
 
     let token_str = token.0.clone();
 
-    let redis_client =
-        redis::Client::open(state.redis_connection_string.clone()).map_err(|err| {
-            eprintln!("Error connecting to redis: {:?}", err);
-            Status::InternalServerError
-        })?;
-
-    let profile = redis_get_session_by_token(&redis_client, &token_str)
+    // si redis falla se pregunta al servidor de auth como si no hubiera sesión
+    let profile = redis_get_session_by_token(&state.redis, &token_str)
         .await
-        .map_err(|e| {
+        .unwrap_or_else(|e| {
             eprintln!("Error getting session: {:?}", e);
-            Status::InternalServerError
-        })?;
+            None
+        });
 
     println!("profile con redis: {:?}", profile);
 
@@ -282,12 +518,11 @@ async fn auth(
     let user_id = profile.user_id;
     let attributes = profile.attributes.clone();
 
-    redis_set_session_by_token(&redis_client, &token_str, &profile, state.auth_redis_ttl)
-        .await
-        .map_err(|e| {
-            eprintln!("Error setting session: {:?}", e);
-            Status::InternalServerError
-        })?;
+    if let Err(e) =
+        redis_set_session_by_token(&state.redis, &token_str, &profile, state.auth_redis_ttl).await
+    {
+        eprintln!("Error setting session: {:?}", e);
+    }
 
     Ok(Json(AuthResponse {
         status: "success".to_string(),
@@ -296,10 +531,68 @@ async fn auth(
     }))
 }
 
-#[get("/articulos")]
+// avisa al servidor de auth (RFC 7009); sin AUTH_REVOKE_URL no se avisa
+async fn auth_revoke_token(token: &str) -> Result<(), String> {
+    let Ok(revoke_url) = env::var("AUTH_REVOKE_URL") else {
+        return Ok(());
+    };
+    let client_id = env::var("CLIENT_ID").map_err(|_| "CLIENT_ID is not defined")?;
+    let client_secret = env::var("CLIENT_SECRET").map_err(|_| "CLIENT_SECRET is not defined")?;
+
+    let response = Client::new()
+        .post(revoke_url)
+        .basic_auth(client_id, Some(client_secret))
+        .form(&[("token", token), ("token_type_hint", "access_token")])
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+
+    Ok(())
+}
+
+// Cierra la sesión: borra la sesión cacheada y revoca el token, que deja de
+// valer en este servidor aunque el servidor de auth no se entere.
+#[post("/logout")]
+async fn logout(state: &rocket::State<AppState>, token: BearerToken) -> Result<Status, Status> {
+    if token.0.is_empty() {
+        return Err(Status::Unauthorized);
+    }
+
+    // solo se revoca un token con sesión cacheada o que el servidor de auth acepta
+    let session = redis_get_session_by_token(&state.redis, &token.0)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error getting session: {:?}", e);
+            None
+        });
+    if session.is_none() {
+        auth_profile(token.clone())
+            .await?
+            .ok_or(Status::Unauthorized)?;
+    }
+
+    redis_revoke_token(&state.redis, &token.0, state.auth_revoked_ttl)
+        .await
+        .map_err(|e| {
+            eprintln!("Error revoking token: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if let Err(e) = auth_revoke_token(&token.0).await {
+        eprintln!("Error notifying token revocation: {}", e);
+    }
+
+    Ok(Status::NoContent)
+}
+
+#[get("/articulos?<include_deleted>")]
 async fn getarticulos(
     state: &rocket::State<AppState>,
     token: BearerToken,
+    include_deleted: Option<bool>,
 ) -> Result<Json<Vec<Articulo>>, Status> {
     let profile = auth_profile(token).await?;
 
@@ -313,11 +606,21 @@ async fn getarticulos(
         return Err(Status::Forbidden);
     }
 
+    // solo un admin puede ver los artículos borrados
+    let include_deleted = include_deleted.unwrap_or(false);
+    if include_deleted && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
     let pool = state.pool.clone();
-    let varticulos = postgres_get_articulos(&pool).await.map_err(|e| {
-        eprintln!("Error getting articles: {:?}", e);
-        Status::InternalServerError
-    })?;
+    let varticulos = state
+        .catalogo_cache
+        .articulos(&pool, include_deleted)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting articles: {:?}", e);
+            Status::InternalServerError
+        })?;
 
     Ok(Json(varticulos))
 }
@@ -347,10 +650,14 @@ async fn getarticulo(
     }
 
     let pool = state.pool.clone();
-    let articulo = postgres_get_articulo_by_id(&pool, id).await.map_err(|e| {
-        eprintln!("Error getting article: {:?}", e);
-        Status::InternalServerError
-    })?;
+    let articulo = state
+        .catalogo_cache
+        .articulo(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting article: {:?}", e);
+            Status::InternalServerError
+        })?;
 
     let issue_requests = issuerequest::postgres_get_issue_requests_by_articulo(&pool, id)
         .await
@@ -369,7 +676,7 @@ async fn getarticulo(
 async fn postarticulo(
     state: &rocket::State<AppState>,
     token: BearerToken,
-    articulo: Json<ArticuloRequest>,
+    articulo: Validated<ArticuloRequest>,
     id: i32,
 ) -> Result<Json<Articulo>, Status> {
     let profile = auth_profile(token).await?;
@@ -400,6 +707,8 @@ async fn postarticulo(
             Status::InternalServerError
         })?;
 
+    state.catalogo_cache.invalidar().await;
+
     Ok(Json(new_articulo))
 }
 
@@ -407,7 +716,7 @@ async fn postarticulo(
 async fn putarticulo(
     state: &rocket::State<AppState>,
     token: BearerToken,
-    articulo: Json<ArticuloRequest>,
+    articulo: Validated<ArticuloRequest>,
     id: i32,
 ) -> Result<Json<Articulo>, Status> {
     let profile = auth_profile(token).await?;
@@ -438,407 +747,3695 @@ async fn putarticulo(
             Status::InternalServerError
         })?;
 
-    Ok(Json(new_articulo))
-}
+    state.catalogo_cache.invalidar().await;
 
-#[derive(Serialize, Deserialize)]
-struct GetProfileResponse {
-    cliente: Option<Cliente>,
-    corp_user: Option<corpservice::UserData>,
-    issue_requests: Vec<issuerequest::IssueRequest>,
+    Ok(Json(new_articulo))
 }
 
-#[get("/profile/<id>")]
-async fn profile(
-    state: &State<AppState>,
+#[patch("/articulo/<id>", data = "<patch>")]
+async fn patcharticulo(
+    state: &rocket::State<AppState>,
     token: BearerToken,
+    patch: Validated<ArticuloPatch>,
     id: i32,
-) -> Result<Json<GetProfileResponse>, Status> {
-    // Autenticación
+) -> Result<Json<Articulo>, Status> {
     let profile = auth_profile(token).await?;
-    let profile = profile.ok_or(Status::Unauthorized)?;
 
-    // Autorización
+    if profile.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let profile = profile.unwrap();
+
     if profile.user_id == 0 {
         return Err(Status::Forbidden);
     }
 
-    if id != profile.user_id {
-        let default_role = "".to_string();
-        let role = profile.attributes.get("role").unwrap_or(&default_role);
-        if role != "admin" {
-            return Err(Status::Forbidden);
-        }
-    }
-    // Obtener datos
     let pool = state.pool.clone();
+    let patch = patch.into_inner();
 
-    let cliente = postgres_get_cliente_by_user_id(&pool, id)
-        .await
-        .map_err(|e| {
-            eprintln!("Error getting client: {:?}", e);
-            Status::InternalServerError
-        })?;
-
-    let corp_user = corpservice::corp_service_userdata_by_id(id)
-        .await
-        .map_err(|e| {
-            eprintln!("Error fetching corp user data: {:?}", e);
-            Status::FailedDependency // 424 - Dependencia fallida
-        })?;
-
-    let issue_requests = issuerequest::postgres_get_issue_requests_by_cliente(&pool, id)
+    let articulo = postgres_patch_articulo(&pool, patch, id)
         .await
         .map_err(|e| {
-            eprintln!("Error getting issue requests: {:?}", e);
+            eprintln!("Error patching article: {:?}", e);
             Status::InternalServerError
-        })?;
+        })?
+        .ok_or(Status::NotFound)?;
 
-    let data = GetProfileResponse {
-        cliente,
-        corp_user,
-        issue_requests,
-    };
+    state.catalogo_cache.invalidar().await;
 
-    Ok(Json(data))
+    Ok(Json(articulo))
 }
 
-#[get("/profiles")]
-async fn profiles(
-    state: &State<AppState>,
+#[delete("/articulo/<id>")]
+async fn deletearticulo(
+    state: &rocket::State<AppState>,
     token: BearerToken,
-) -> Result<Json<Vec<Cliente>>, Status> {
+    id: i32,
+) -> Result<Json<Articulo>, Status> {
     let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
 
-    if profile.is_none() {
-        return Err(Status::Unauthorized);
-    }
-
-    let profile = profile.unwrap();
-
-    if profile.user_id == 0 {
+    if profile.user_id == 0 || !is_admin(&profile) {
         return Err(Status::Forbidden);
     }
 
     let pool = state.pool.clone();
 
-    let clientes = clientes::postgres_get_clientes(&pool).await.map_err(|e| {
-        eprintln!("Error getting clients: {:?}", e);
-        Status::InternalServerError
-    })?;
+    let articulo = postgres_soft_delete_articulo(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting article: {}", e);
+            match e {
+                articulos::BorradoArticuloError::ArticuloNoEncontrado(_) => Status::NotFound,
+                articulos::BorradoArticuloError::EnPedidosAbiertos(_) => Status::Conflict,
+                articulos::BorradoArticuloError::Sqlx(_) => Status::InternalServerError,
+            }
+        })?;
 
-    Ok(Json(clientes))
+    state.catalogo_cache.invalidar().await;
+
+    Ok(Json(articulo))
 }
 
-#[post("/profile", data = "<cliente>")]
-async fn postprofile(
-    state: &State<AppState>,
+#[post("/articulo/<id>/restore")]
+async fn restorearticulo(
+    state: &rocket::State<AppState>,
     token: BearerToken,
-    cliente: Json<clientes::ClienteRequest>,
-) -> Result<Json<Cliente>, Status> {
+    id: i32,
+) -> Result<Json<Articulo>, Status> {
     let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
 
-    if profile.is_none() {
-        return Err(Status::Unauthorized);
-    }
-
-    let profile = profile.unwrap();
-
-    if profile.user_id == 0 {
+    if profile.user_id == 0 || !is_admin(&profile) {
         return Err(Status::Forbidden);
     }
 
     let pool = state.pool.clone();
-    let cliente = cliente.into_inner();
 
-    let new_cliente = clientes::postgres_create_cliente(&pool, cliente)
+    let articulo = postgres_restore_articulo(&pool, id)
         .await
         .map_err(|e| {
-            eprintln!("Error creating client: {:?}", e);
+            eprintln!("Error restoring article: {:?}", e);
             Status::InternalServerError
-        })?;
+        })?
+        .ok_or(Status::NotFound)?;
 
-    Ok(Json(new_cliente))
+    state.catalogo_cache.invalidar().await;
+
+    Ok(Json(articulo))
 }
 
-#[put("/profile/<user_id>", data = "<cliente>")]
-async fn putprofile(
-    state: &State<AppState>,
-    token: BearerToken,
-    cliente: Json<clientes::ClienteRequest>,
-    user_id: i32,
-) -> Result<Json<Cliente>, Status> {
-    let profile = auth_profile(token).await?;
+#[derive(Serialize, Deserialize)]
+struct GetProfileResponse {
+    cliente: Option<Cliente>,
+    corp_user: Option<corpservice::UserData>,
+    corp_last_synced_at: Option<chrono::NaiveDateTime>,
+    issue_requests: Vec<issuerequest::IssueRequest>,
+    saldo: Vec<pagos::Saldo>,
+    pagos: Vec<pagos::Pago>,
+    interacciones: Vec<interacciones::Interaccion>,
+    direcciones: Vec<direcciones::Direccion>,
+    contactos: Vec<contactos::Contacto>,
+    consentimientos: Vec<consentimientos::Consentimiento>,
+}
 
-    if profile.is_none() {
-        return Err(Status::Unauthorized);
-    }
+// cliente vinculado a un usuario de auth
+async fn cliente_de_usuario(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> Result<Cliente, Status> {
+    postgres_get_cliente_by_user_id(pool, user_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
 
-    let profile = profile.unwrap();
+// cliente por su id; solo lo ve un admin o el usuario vinculado a él (o al
+// cliente en el que se fusionó, que conserva sus facturas)
+async fn cliente_autorizado(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    profile: &AuthProfile,
+) -> Result<Cliente, Status> {
+    let cliente = clientes::postgres_get_cliente_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
 
-    if profile.user_id == 0 {
-        return Err(Status::Forbidden);
+    if cliente.user_id != Some(profile.user_id) && !is_admin(profile) {
+        let fusionado = duplicados::postgres_fusionado_en_usuario(pool, id, profile.user_id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error getting client merges: {:?}", e);
+                Status::InternalServerError
+            })?;
+        if !fusionado {
+            return Err(Status::Forbidden);
+        }
     }
 
-    let pool = state.pool.clone();
-    let cliente = cliente.into_inner();
+    Ok(cliente)
+}
 
-    let new_cliente = clientes::postgres_update_cliente(&pool, cliente, user_id)
+// Copia local de los datos corporativos; solo se consulta el directorio la
+// primera vez. La copia se refresca con la sincronización.
+async fn copia_corp(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    corp_cache: &corpcache::CorpCache,
+    user_id: i32,
+) -> Result<Option<sincronizacion::CopiaCorp>, Status> {
+    let copia = sincronizacion::postgres_get_copia_corp(pool, user_id)
         .await
         .map_err(|e| {
-            eprintln!("Error updating client: {:?}", e);
+            eprintln!("Error getting corp user copy: {:?}", e);
             Status::InternalServerError
         })?;
+    if copia.is_some() {
+        return Ok(copia);
+    }
 
-    Ok(Json(new_cliente))
-}
+    let datos = corp_cache.userdata_by_id(user_id).await.map_err(|e| {
+        eprintln!("Error fetching corp user data: {:?}", e);
+        Status::FailedDependency // 424 - Dependencia fallida
+    })?;
+    let Some(datos) = datos else {
+        return Ok(None);
+    };
+    let last_synced_at = sincronizacion::postgres_guardar_copia_corp(pool, user_id, &datos)
+        .await
+        .map_err(|e| {
+            eprintln!("Error saving corp user copy: {:?}", e);
+            Status::InternalServerError
+        })?;
 
-#[derive(Serialize, Deserialize)]
-struct AccessTokenResponse {
-    access_token: String,
-    token_type: String,
-    expires_in: i32,
+    Ok(Some(sincronizacion::CopiaCorp {
+        datos,
+        last_synced_at,
+    }))
 }
 
-#[get("/authback/<code>")]
-async fn authback(code: &str) -> Result<Option<Json<AccessTokenResponse>>, Status> {
-    //saca authback_url de env
-    let authback_url = env::var("AUTH_ACCESSTOKEN_URL")
-        .expect("La variable de entorno AUTH_ACCESSTOKEN_URL no está definida");
-
-    let client_id =
-        env::var("CLIENT_ID").expect("La variable de entorno CLIENT_ID no está definida");
+// los datos corporativos solo existen para clientes con cuenta de auth
+async fn cliente_data(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    corp_cache: &corpcache::CorpCache,
+    cliente: Option<Cliente>,
+    user_id: Option<i32>,
+    profile: &AuthProfile,
+) -> Result<GetProfileResponse, Status> {
+    let copia = match user_id {
+        Some(user_id) => copia_corp(pool, corp_cache, user_id).await?,
+        None => None,
+    };
+    let (corp_user, corp_last_synced_at) = match copia {
+        Some(copia) => (Some(copia.datos), Some(copia.last_synced_at)),
+        None => (None, None),
+    };
 
-    let redirect_uri =
-        env::var("REDIRECT_URI").expect("La variable de entorno REDIRECT_URI no está definida");
+    let Some(cliente) = cliente else {
+        return Ok(GetProfileResponse {
+            cliente: None,
+            corp_user,
+            corp_last_synced_at,
+            issue_requests: Vec::new(),
+            saldo: Vec::new(),
+            pagos: Vec::new(),
+            interacciones: Vec::new(),
+            direcciones: Vec::new(),
+            contactos: Vec::new(),
+            consentimientos: Vec::new(),
+        });
+    };
 
-    // Crear un cliente que no verifique los certificados SSL
-    let client = Client::builder()
-        //.danger_accept_invalid_certs(true) // Desactiva la verificación SSL
-        .build()
+    let issue_requests = issuerequest::postgres_get_issue_requests_by_cliente(pool, cliente.id)
+        .await
         .map_err(|e| {
-            eprintln!("Error building client: {:?}", e);
+            eprintln!("Error getting issue requests: {:?}", e);
             Status::InternalServerError
         })?;
 
-    let response = client
-        .post(authback_url)
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(format!(
-            "grant_type=authorization_code&code={}&client_id={}&redirect_uri={}",
-            code, client_id, redirect_uri
-        ))
-        .send()
+    // saldo pendiente, vencido e historial de pagos del cliente
+    let saldo = pagos::postgres_get_saldo_cliente(pool, cliente.id)
         .await
         .map_err(|e| {
-            eprintln!("Error getting access token: {:?}", e);
+            eprintln!("Error getting client balance: {:?}", e);
+            Status::InternalServerError
+        })?;
+    let pagos = pagos::postgres_get_pagos_by_cliente(pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client payments: {:?}", e);
             Status::InternalServerError
         })?;
 
-    let response_text = response.text().await.map_err(|e| {
-        eprintln!("Error getting response text: {:?}", e);
+    // las interacciones son notas internas, el propio cliente no las ve
+    let interacciones = if is_admin(profile) {
+        interacciones::postgres_get_interacciones_by_cliente(pool, cliente.id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error getting client interactions: {:?}", e);
+                Status::InternalServerError
+            })?
+    } else {
+        Vec::new()
+    };
+
+    let direcciones = direcciones::postgres_get_direcciones_by_cliente(pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client addresses: {:?}", e);
+            Status::InternalServerError
+        })?;
+    let contactos = contactos::postgres_get_contactos_by_cliente(pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client contacts: {:?}", e);
+            Status::InternalServerError
+        })?;
+    let consentimientos = consentimientos::postgres_get_consentimientos(pool, cliente.id, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client consents: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(GetProfileResponse {
+        cliente: Some(cliente),
+        corp_user,
+        corp_last_synced_at,
+        issue_requests,
+        saldo,
+        pagos,
+        interacciones,
+        direcciones,
+        contactos,
+        consentimientos,
+    })
+}
+
+#[get("/profile/<id>")]
+async fn profile(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<GetProfileResponse>, Status> {
+    // Autenticación
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    // Autorización
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    if id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+    // Obtener datos
+    let pool = state.pool.clone();
+
+    let cliente = postgres_get_cliente_by_user_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    let data = cliente_data(&pool, &state.corp_cache, cliente, Some(id), &profile).await?;
+
+    Ok(Json(data))
+}
+
+#[get("/cliente/<id>")]
+async fn getcliente(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<GetProfileResponse>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+    let user_id = cliente.user_id;
+
+    let data = cliente_data(&pool, &state.corp_cache, Some(cliente), user_id, &profile).await?;
+
+    Ok(Json(data))
+}
+
+async fn cliente_timeline(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente: &Cliente,
+    profile: &AuthProfile,
+    pagina: Option<i64>,
+    por_pagina: Option<i64>,
+    tipo: Option<&str>,
+) -> Result<timeline::Timeline, Status> {
+    let paginacion = paginacion::Paginacion::new(
+        pagina,
+        por_pagina,
+        timeline::POR_PAGINA_POR_DEFECTO,
+        timeline::POR_PAGINA_MAXIMO,
+    )
+    .ok_or(Status::BadRequest)?;
+    if let Some(tipo) = tipo
+        && !timeline::TIPOS_EVENTO.contains(&tipo)
+    {
+        return Err(Status::BadRequest);
+    }
+
+    timeline::postgres_get_timeline(pool, cliente.id, is_admin(profile), tipo, &paginacion)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client timeline: {:?}", e);
+            Status::InternalServerError
+        })
+}
+
+// eventos del cliente en orden cronológico inverso; pagina empieza en 1
+#[get("/profile/<id>/timeline?<pagina>&<por_pagina>&<tipo>")]
+async fn profiletimeline(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    pagina: Option<i64>,
+    por_pagina: Option<i64>,
+    tipo: Option<&str>,
+) -> Result<Json<timeline::Timeline>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    if id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_de_usuario(&pool, id).await?;
+
+    let timeline = cliente_timeline(&pool, &cliente, &profile, pagina, por_pagina, tipo).await?;
+
+    Ok(Json(timeline))
+}
+
+#[get("/cliente/<id>/timeline?<pagina>&<por_pagina>&<tipo>")]
+async fn clientetimeline(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    pagina: Option<i64>,
+    por_pagina: Option<i64>,
+    tipo: Option<&str>,
+) -> Result<Json<timeline::Timeline>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let timeline = cliente_timeline(&pool, &cliente, &profile, pagina, por_pagina, tipo).await?;
+
+    Ok(Json(timeline))
+}
+
+async fn cliente_interacciones(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<interacciones::Interaccion>, Status> {
+    interacciones::postgres_get_interacciones_by_cliente(pool, cliente_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client interactions: {:?}", e);
+            Status::InternalServerError
+        })
+}
+
+#[get("/profile/<id>/interacciones")]
+async fn profileinteracciones(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<interacciones::Interaccion>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_de_usuario(&pool, id).await?;
+
+    Ok(Json(cliente_interacciones(&pool, cliente.id).await?))
+}
+
+#[get("/cliente/<id>/interacciones")]
+async fn clienteinteracciones(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<interacciones::Interaccion>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    Ok(Json(cliente_interacciones(&pool, cliente.id).await?))
+}
+
+// el autor es el usuario autenticado
+async fn crear_interaccion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    profile: &AuthProfile,
+    interaccion: interacciones::InteraccionRequest,
+) -> Result<interacciones::Interaccion, Status> {
+    interacciones::postgres_create_interaccion(pool, cliente_id, profile.user_id, interaccion)
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating interaction: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[post("/profile/<id>/interaccion", data = "<interaccion>")]
+async fn postinteraccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    interaccion: Validated<interacciones::InteraccionRequest>,
+) -> Result<Json<interacciones::Interaccion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_de_usuario(&pool, id).await?;
+
+    let interaccion =
+        crear_interaccion(&pool, cliente.id, &profile, interaccion.into_inner()).await?;
+
+    Ok(Json(interaccion))
+}
+
+#[post("/cliente/<id>/interaccion", data = "<interaccion>")]
+async fn postclienteinteraccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    interaccion: Validated<interacciones::InteraccionRequest>,
+) -> Result<Json<interacciones::Interaccion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let interaccion = crear_interaccion(&pool, id, &profile, interaccion.into_inner()).await?;
+
+    Ok(Json(interaccion))
+}
+
+// solo el autor puede editar o borrar su interacción
+async fn interaccion_del_autor(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    profile: &AuthProfile,
+) -> Result<interacciones::Interaccion, Status> {
+    let interaccion = interacciones::postgres_get_interaccion_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting interaction: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    if interaccion.autor_user_id != profile.user_id {
+        return Err(Status::Forbidden);
+    }
+
+    Ok(interaccion)
+}
+
+#[put("/interaccion/<id>", data = "<interaccion>")]
+async fn putinteraccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    interaccion: Validated<interacciones::InteraccionRequest>,
+) -> Result<Json<interacciones::Interaccion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    interaccion_del_autor(&pool, id, &profile).await?;
+
+    let interaccion =
+        interacciones::postgres_update_interaccion(&pool, id, interaccion.into_inner())
+            .await
+            .map_err(|e| {
+                eprintln!("Error updating interaction: {:?}", e);
+                Status::InternalServerError
+            })?
+            .ok_or(Status::NotFound)?;
+
+    Ok(Json(interaccion))
+}
+
+#[delete("/interaccion/<id>")]
+async fn deleteinteraccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    interaccion_del_autor(&pool, id, &profile).await?;
+
+    let deleted = interacciones::postgres_delete_interaccion(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting interaction: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if !deleted {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Status::NoContent)
+}
+
+// dos altas simultáneas marcadas como predeterminadas chocan con el índice único
+fn direccion_error_status(e: &sqlx::Error) -> Status {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => Status::Conflict,
+        _ => Status::InternalServerError,
+    }
+}
+
+#[get("/cliente/<id>/direcciones")]
+async fn clientedirecciones(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<direcciones::Direccion>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let direcciones = direcciones::postgres_get_direcciones_by_cliente(&pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client addresses: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(direcciones))
+}
+
+#[post("/cliente/<id>/direccion", data = "<direccion>")]
+async fn postclientedireccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    direccion: Validated<direcciones::DireccionRequest>,
+) -> Result<Json<direcciones::Direccion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let direccion =
+        direcciones::postgres_create_direccion(&pool, cliente.id, direccion.into_inner())
+            .await
+            .map_err(|e| {
+                eprintln!("Error creating address: {:?}", e);
+                direccion_error_status(&e)
+            })?
+            .ok_or(Status::NotFound)?;
+
+    Ok(Json(direccion))
+}
+
+// la dirección la gestiona el propio cliente o un admin
+async fn direccion_autorizada(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    profile: &AuthProfile,
+) -> Result<direcciones::Direccion, Status> {
+    let direccion = direcciones::postgres_get_direccion_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting address: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    cliente_autorizado(pool, direccion.cliente_id, profile).await?;
+
+    Ok(direccion)
+}
+
+#[put("/direccion/<id>", data = "<direccion>")]
+async fn putdireccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    direccion: Validated<direcciones::DireccionRequest>,
+) -> Result<Json<direcciones::Direccion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    direccion_autorizada(&pool, id, &profile).await?;
+
+    let direccion = direcciones::postgres_update_direccion(&pool, id, direccion.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating address: {:?}", e);
+            direccion_error_status(&e)
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(direccion))
+}
+
+#[delete("/direccion/<id>")]
+async fn deletedireccion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    direccion_autorizada(&pool, id, &profile).await?;
+
+    let deleted = direcciones::postgres_delete_direccion(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting address: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if !deleted {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Status::NoContent)
+}
+
+#[get("/cliente/<id>/contactos")]
+async fn clientecontactos(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<contactos::Contacto>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let contactos = contactos::postgres_get_contactos_by_cliente(&pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client contacts: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(contactos))
+}
+
+#[post("/cliente/<id>/contacto", data = "<contacto>")]
+async fn postclientecontacto(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    contacto: Validated<contactos::ContactoRequest>,
+) -> Result<Json<contactos::Contacto>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let contacto = contactos::postgres_create_contacto(&pool, cliente.id, contacto.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating contact: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(contacto))
+}
+
+// el contacto lo gestiona el propio cliente o un admin
+async fn contacto_autorizado(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    profile: &AuthProfile,
+) -> Result<contactos::Contacto, Status> {
+    let contacto = contactos::postgres_get_contacto_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting contact: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    cliente_autorizado(pool, contacto.cliente_id, profile).await?;
+
+    Ok(contacto)
+}
+
+#[put("/contacto/<id>", data = "<contacto>")]
+async fn putcontacto(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    contacto: Validated<contactos::ContactoRequest>,
+) -> Result<Json<contactos::Contacto>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    contacto_autorizado(&pool, id, &profile).await?;
+
+    let contacto = contactos::postgres_update_contacto(&pool, id, contacto.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating contact: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(contacto))
+}
+
+#[delete("/contacto/<id>")]
+async fn deletecontacto(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    contacto_autorizado(&pool, id, &profile).await?;
+
+    let deleted = contactos::postgres_delete_contacto(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting contact: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if !deleted {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Status::NoContent)
+}
+
+#[get("/etiquetas")]
+async fn getetiquetas(
+    state: &State<AppState>,
+    token: BearerToken,
+) -> Result<Json<Vec<etiquetas::EtiquetaUso>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let etiquetas = etiquetas::postgres_get_etiquetas(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting tags: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(etiquetas))
+}
+
+#[get("/cliente/<id>/etiquetas")]
+async fn clienteetiquetas(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<String>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let etiquetas = etiquetas::postgres_get_etiquetas_cliente(&pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client tags: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(etiquetas))
+}
+
+// sustituye todas las etiquetas del cliente por las indicadas
+#[put("/cliente/<id>/etiquetas", data = "<etiquetas>")]
+async fn putclienteetiquetas(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    etiquetas: Validated<etiquetas::EtiquetasRequest>,
+) -> Result<Json<Vec<String>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let etiquetas = etiquetas::postgres_set_etiquetas_cliente(
+        &pool,
+        cliente.id,
+        &etiquetas.into_inner().etiquetas,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Error updating client tags: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(etiquetas))
+}
+
+// un nombre de segmento repetido es un 409
+fn segmento_error_status(e: &sqlx::Error) -> Status {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => Status::Conflict,
+        _ => Status::InternalServerError,
+    }
+}
+
+#[get("/segments")]
+async fn getsegments(
+    state: &State<AppState>,
+    token: BearerToken,
+) -> Result<Json<Vec<segmentos::Segmento>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let segmentos = segmentos::postgres_get_segmentos(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting segments: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(segmentos))
+}
+
+async fn segmento_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<segmentos::Segmento, Status> {
+    segmentos::postgres_get_segmento_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting segment: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[get("/segment/<id>")]
+async fn getsegment(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<segmentos::Segmento>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    Ok(Json(segmento_by_id(&pool, id).await?))
+}
+
+#[post("/segment", data = "<segmento>")]
+async fn postsegment(
+    state: &State<AppState>,
+    token: BearerToken,
+    segmento: Validated<segmentos::SegmentoRequest>,
+) -> Result<Json<segmentos::Segmento>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let segmento =
+        segmentos::postgres_create_segmento(&pool, profile.user_id, segmento.into_inner())
+            .await
+            .map_err(|e| {
+                eprintln!("Error creating segment: {:?}", e);
+                segmento_error_status(&e)
+            })?;
+
+    Ok(Json(segmento))
+}
+
+#[put("/segment/<id>", data = "<segmento>")]
+async fn putsegment(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    segmento: Validated<segmentos::SegmentoRequest>,
+) -> Result<Json<segmentos::Segmento>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let segmento = segmentos::postgres_update_segmento(&pool, id, segmento.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating segment: {:?}", e);
+            segmento_error_status(&e)
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(segmento))
+}
+
+#[delete("/segment/<id>")]
+async fn deletesegment(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let deleted = segmentos::postgres_delete_segmento(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting segment: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if !deleted {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Status::NoContent)
+}
+
+// las reglas se evalúan en cada llamada; pagina empieza en 1 y canal deja solo
+// a los clientes que aceptan comunicaciones por ese canal
+#[get("/segments/<id>/clientes?<canal>&<pagina>&<por_pagina>")]
+async fn getsegmentclientes(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    canal: Option<&str>,
+    pagina: Option<i64>,
+    por_pagina: Option<i64>,
+) -> Result<Json<segmentos::ClientesSegmento>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let paginacion = paginacion::Paginacion::new(
+        pagina,
+        por_pagina,
+        segmentos::POR_PAGINA_POR_DEFECTO,
+        segmentos::POR_PAGINA_MAXIMO,
+    )
+    .ok_or(Status::BadRequest)?;
+    if let Some(canal) = canal
+        && !consentimientos::CANALES.contains(&canal)
+    {
+        return Err(Status::BadRequest);
+    }
+
+    let pool = state.pool.clone();
+    let segmento = segmento_by_id(&pool, id).await?;
+
+    let clientes = segmentos::postgres_get_clientes_segmento(&pool, &segmento, canal, &paginacion)
+        .await
+        .map_err(|e| {
+            eprintln!("Error evaluating segment: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(clientes))
+}
+
+// pares de clientes activos que parecen el mismo, de más a menos probable
+#[get("/clientes/duplicados?<umbral>")]
+async fn getduplicados(
+    state: &State<AppState>,
+    token: BearerToken,
+    umbral: Option<i32>,
+) -> Result<Json<Vec<duplicados::ParDuplicado>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let umbral = umbral.unwrap_or(duplicados::UMBRAL_POR_DEFECTO);
+    if !(1..=100).contains(&umbral) {
+        return Err(Status::BadRequest);
+    }
+
+    let pool = state.pool.clone();
+    let pares = duplicados::postgres_get_duplicados(&pool, umbral)
+        .await
+        .map_err(|e| {
+            eprintln!("Error finding duplicate clients: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(pares))
+}
+
+fn fusion_error_status(e: duplicados::FusionError) -> Status {
+    eprintln!("Error merging clients: {}", e);
+    match e {
+        duplicados::FusionError::ClienteNoEncontrado(_) => Status::NotFound,
+        duplicados::FusionError::MismoCliente => Status::UnprocessableEntity,
+        duplicados::FusionError::UsuariosDistintos => Status::Conflict,
+        duplicados::FusionError::Sqlx(_) => Status::InternalServerError,
+    }
+}
+
+// el cliente <id> se queda con todo lo del duplicado
+#[post("/cliente/<id>/fusion", data = "<fusion>")]
+async fn postclientefusion(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    fusion: Validated<duplicados::FusionRequest>,
+) -> Result<Json<duplicados::Fusion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let fusion = duplicados::postgres_fusionar_clientes(
+        &pool,
+        id,
+        fusion.into_inner().duplicado_id,
+        profile.user_id,
+    )
+    .await
+    .map_err(fusion_error_status)?;
+
+    Ok(Json(fusion))
+}
+
+#[get("/cliente/<id>/fusiones")]
+async fn clientefusiones(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<duplicados::Fusion>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let fusiones = duplicados::postgres_get_fusiones_by_cliente(&pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client merges: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(fusiones))
+}
+
+async fn exportar_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    profile: &AuthProfile,
+) -> Result<rgpd::Exportacion, Status> {
+    rgpd::postgres_exportar_cliente(pool, cliente_id, profile.user_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error exporting client data: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+// acceso del interesado (RGPD): todo lo que se guarda de él
+#[get("/profile/<id>/export")]
+async fn profileexport(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<rgpd::Exportacion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    if id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_de_usuario(&pool, id).await?;
+
+    Ok(Json(exportar_cliente(&pool, cliente.id, &profile).await?))
+}
+
+#[get("/cliente/<id>/export")]
+async fn clienteexport(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<rgpd::Exportacion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    Ok(Json(exportar_cliente(&pool, cliente.id, &profile).await?))
+}
+
+// derecho de supresión; no se puede deshacer
+#[post("/cliente/<id>/anonimizar")]
+async fn postclienteanonimizar(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = rgpd::postgres_anonimizar_cliente(&pool, id, profile.user_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error anonymizing client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(cliente))
+}
+
+#[get("/cliente/<id>/rgpd")]
+async fn clientesolicitudesrgpd(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<rgpd::SolicitudRgpd>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let solicitudes = rgpd::postgres_get_solicitudes_rgpd(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting GDPR requests: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(solicitudes))
+}
+
+// estado de cada canal en un momento dado: fecha YYYY-MM-DD (al final del día) o
+// YYYY-MM-DDTHH:MM:SS; sin fecha, el estado actual
+fn fecha_consulta(fecha: Option<&str>) -> Result<Option<chrono::NaiveDateTime>, Status> {
+    let Some(fecha) = fecha else {
+        return Ok(None);
+    };
+    if let Ok(fecha) = fecha.parse::<chrono::NaiveDateTime>() {
+        return Ok(Some(fecha));
+    }
+    fecha
+        .parse::<chrono::NaiveDate>()
+        .ok()
+        .and_then(|d| d.and_hms_opt(23, 59, 59))
+        .map(Some)
+        .ok_or(Status::BadRequest)
+}
+
+#[get("/cliente/<id>/consentimientos?<fecha>")]
+async fn clienteconsentimientos(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    fecha: Option<&str>,
+) -> Result<Json<Vec<consentimientos::Consentimiento>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let fecha = fecha_consulta(fecha)?;
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let consentimientos = consentimientos::postgres_get_consentimientos(&pool, cliente.id, fecha)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting client consents: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(consentimientos))
+}
+
+#[get("/cliente/<id>/consentimientos/historial")]
+async fn clienteconsentimientoshistorial(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Vec<consentimientos::Consentimiento>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let historial = consentimientos::postgres_get_historial_consentimientos(&pool, cliente.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting consent history: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(historial))
+}
+
+// el cliente da o retira su consentimiento, o un admin lo registra por él
+#[post("/cliente/<id>/consentimiento", data = "<consentimiento>")]
+async fn postclienteconsentimiento(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    consentimiento: Validated<consentimientos::ConsentimientoRequest>,
+) -> Result<Json<consentimientos::Consentimiento>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente_autorizado(&pool, id, &profile).await?;
+
+    let consentimiento = consentimientos::postgres_create_consentimiento(
+        &pool,
+        cliente.id,
+        profile.user_id,
+        consentimiento.into_inner(),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Error recording consent: {:?}", e);
+        Status::InternalServerError
+    })?
+    .ok_or(Status::NotFound)?;
+
+    Ok(Json(consentimiento))
+}
+
+// sincroniza todos los clientes con cuenta de auth con el directorio corporativo
+#[post("/corp/sync")]
+async fn postcorpsync(
+    state: &State<AppState>,
+    token: BearerToken,
+) -> Result<Json<sincronizacion::ResumenSincronizacion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let resumen = sincronizacion::sincronizar_todos(&pool, &state.corp_cache, &state.politica_corp)
+        .await
+        .map_err(|e| {
+            eprintln!("Error synchronizing with corp directory: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(resumen))
+}
+
+#[post("/profile/<id>/sync")]
+async fn postprofilesync(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<sincronizacion::Sincronizacion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let sincronizacion =
+        sincronizacion::sincronizar_usuario(&pool, &state.corp_cache, id, &state.politica_corp)
+            .await
+            .map_err(|e| {
+                eprintln!("Error synchronizing user {}: {:?}", id, e);
+                match e {
+                    sincronizacion::SincronizacionError::Corp(_) => Status::FailedDependency,
+                    sincronizacion::SincronizacionError::Sqlx(_) => Status::InternalServerError,
+                }
+            })?
+            .ok_or(Status::NotFound)?;
+
+    Ok(Json(sincronizacion))
+}
+
+// descarta los datos corporativos cacheados del usuario; la siguiente lectura
+// vuelve a consultar el directorio
+#[delete("/corp/cache/<id>")]
+async fn deletecorpcache(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    state.corp_cache.invalidar(id).await.map_err(|e| {
+        eprintln!("Error invalidating corp cache: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Status::NoContent)
+}
+
+// diferencias con el directorio corporativo encontradas en la última sincronización
+#[get("/corp/conflictos?<cliente_id>")]
+async fn getcorpconflictos(
+    state: &State<AppState>,
+    token: BearerToken,
+    cliente_id: Option<i32>,
+) -> Result<Json<Vec<sincronizacion::Conflicto>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let conflictos = sincronizacion::postgres_get_conflictos(&pool, cliente_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting corp conflicts: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(conflictos))
+}
+
+// una referencia a un cliente, artículo, pedido o lead inexistente es un 422, como en presupuestos
+fn vinculo_error_status(e: &sqlx::Error) -> Status {
+    match e.as_database_error() {
+        Some(db) if db.is_foreign_key_violation() => Status::UnprocessableEntity,
+        _ => Status::InternalServerError,
+    }
+}
+
+// la tarea solo la ven y editan su asignado, su creador o un admin
+async fn tarea_visible(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    profile: &AuthProfile,
+) -> Result<tareas::Tarea, Status> {
+    let tarea = tareas::postgres_get_tarea_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting task: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    if !tarea.visible_para(profile.user_id) && !is_admin(profile) {
+        return Err(Status::Forbidden);
+    }
+
+    Ok(tarea)
+}
+
+// assignee=me o un user_id (otro que no sea uno mismo solo para admin); sin
+// assignee un admin ve todas y el resto solo las suyas
+#[get("/tasks?<assignee>&<due>&<estado>")]
+async fn gettasks(
+    state: &State<AppState>,
+    token: BearerToken,
+    assignee: Option<&str>,
+    due: Option<&str>,
+    estado: Option<&str>,
+) -> Result<Json<Vec<tareas::Tarea>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let asignado_user_id = match assignee {
+        Some("me") => Some(profile.user_id),
+        Some(assignee) => {
+            let user_id = assignee.parse::<i32>().map_err(|_| Status::BadRequest)?;
+            if user_id != profile.user_id && !is_admin(&profile) {
+                return Err(Status::Forbidden);
+            }
+            Some(user_id)
+        }
+        None if is_admin(&profile) => None,
+        None => Some(profile.user_id),
+    };
+
+    let vencimiento = match due {
+        Some(due) => Some(tareas::Vencimiento::from_param(due).ok_or(Status::BadRequest)?),
+        None => None,
+    };
+
+    if let Some(estado) = estado
+        && !tareas::ESTADOS_TAREA.contains(&estado)
+    {
+        return Err(Status::BadRequest);
+    }
+
+    let pool = state.pool.clone();
+
+    let tareas = tareas::postgres_get_tareas(&pool, asignado_user_id, vencimiento, estado)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting tasks: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(tareas))
+}
+
+#[get("/task/<id>")]
+async fn gettask(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<tareas::Tarea>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let tarea = tarea_visible(&pool, id, &profile).await?;
+
+    Ok(Json(tarea))
+}
+
+#[post("/task", data = "<tarea>")]
+async fn posttask(
+    state: &State<AppState>,
+    token: BearerToken,
+    tarea: Validated<tareas::TareaRequest>,
+) -> Result<Json<tareas::Tarea>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    // las tareas son del equipo: un cliente no las crea ni las vincula a nada
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let tarea = tareas::postgres_create_tarea(&pool, profile.user_id, tarea.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating task: {:?}", e);
+            vinculo_error_status(&e)
+        })?;
+
+    Ok(Json(tarea))
+}
+
+#[put("/task/<id>", data = "<tarea>")]
+async fn puttask(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    tarea: Validated<tareas::TareaRequest>,
+) -> Result<Json<tareas::Tarea>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let actual = tarea_visible(&pool, id, &profile).await?;
+    let tarea = tarea.into_inner();
+
+    // el asignado puede editar la tarea pero solo un admin cambia sus vínculos
+    if !is_admin(&profile)
+        && (tarea.cliente_id != actual.cliente_id
+            || tarea.articulo_id != actual.articulo_id
+            || tarea.pedido_id != actual.pedido_id)
+    {
+        return Err(Status::Forbidden);
+    }
+
+    let tarea = tareas::postgres_update_tarea(&pool, id, tarea)
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating task: {:?}", e);
+            vinculo_error_status(&e)
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(tarea))
+}
+
+#[put("/task/<id>/estado", data = "<estado>")]
+async fn puttaskestado(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    estado: Validated<tareas::TareaEstadoRequest>,
+) -> Result<Json<tareas::Tarea>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    tarea_visible(&pool, id, &profile).await?;
+
+    let tarea = tareas::postgres_update_tarea_estado(&pool, id, &estado.into_inner().estado)
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating task status: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(tarea))
+}
+
+// solo el creador o un admin pueden borrarla
+#[delete("/task/<id>")]
+async fn deletetask(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let tarea = tarea_visible(&pool, id, &profile).await?;
+
+    if tarea.creador_user_id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let deleted = tareas::postgres_delete_tarea(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting task: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if !deleted {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Status::NoContent)
+}
+
+#[get("/notificaciones?<no_leidas>")]
+async fn getnotificaciones(
+    state: &State<AppState>,
+    token: BearerToken,
+    no_leidas: Option<bool>,
+) -> Result<Json<Vec<notificaciones::Notificacion>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let notificaciones = notificaciones::postgres_get_notificaciones(
+        &pool,
+        profile.user_id,
+        no_leidas.unwrap_or(false),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Error getting notifications: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(notificaciones))
+}
+
+#[put("/notificacion/<id>/leida")]
+async fn putnotificacionleida(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<notificaciones::Notificacion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let notificacion =
+        notificaciones::postgres_marcar_notificacion_leida(&pool, id, profile.user_id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error marking notification as read: {:?}", e);
+                Status::InternalServerError
+            })?
+            .ok_or(Status::NotFound)?;
+
+    Ok(Json(notificacion))
+}
+
+fn lead_error_status(e: leads::LeadError) -> Status {
+    eprintln!("Error converting lead: {}", e);
+    match e {
+        leads::LeadError::LeadNoEncontrado(_) => Status::NotFound,
+        leads::LeadError::YaConvertido(_)
+        | leads::LeadError::Descartado(_)
+        | leads::LeadError::ClienteDuplicado => Status::Conflict,
+        leads::LeadError::SinEmail(_) => Status::UnprocessableEntity,
+        leads::LeadError::Sqlx(_) => Status::InternalServerError,
+    }
+}
+
+#[get("/leads?<estado>&<origen>")]
+async fn getleads(
+    state: &State<AppState>,
+    token: BearerToken,
+    estado: Option<&str>,
+    origen: Option<&str>,
+) -> Result<Json<Vec<leads::Lead>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let leads = leads::postgres_get_leads(&pool, estado, origen)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting leads: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(leads))
+}
+
+#[get("/lead/<id>")]
+async fn getlead(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<leads::Lead>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let lead = leads::postgres_get_lead_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting lead: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(lead))
+}
+
+#[post("/lead", data = "<lead>")]
+async fn postlead(
+    state: &State<AppState>,
+    token: BearerToken,
+    lead: Validated<leads::LeadRequest>,
+) -> Result<Json<leads::Lead>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let lead = leads::postgres_create_lead(&pool, profile.user_id, lead.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating lead: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(lead))
+}
+
+// un lead convertido ya no se edita, sus datos viven en el cliente
+#[put("/lead/<id>", data = "<lead>")]
+async fn putlead(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    lead: Validated<leads::LeadRequest>,
+) -> Result<Json<leads::Lead>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    leads::postgres_get_lead_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting lead: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    let lead = leads::postgres_update_lead(&pool, id, lead.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating lead: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::Conflict)?;
+
+    Ok(Json(lead))
+}
+
+#[post("/lead/<id>/cliente", data = "<conversion>")]
+async fn postleadcliente(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    conversion: Validated<leads::LeadConversionRequest>,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let cliente = leads::postgres_convert_lead(&pool, id, conversion.into_inner())
+        .await
+        .map_err(lead_error_status)?;
+
+    Ok(Json(cliente))
+}
+
+#[get("/oportunidades?<lead_id>&<cliente_id>&<etapa>&<propietario>")]
+async fn getoportunidades(
+    state: &State<AppState>,
+    token: BearerToken,
+    lead_id: Option<i32>,
+    cliente_id: Option<i32>,
+    etapa: Option<&str>,
+    propietario: Option<i32>,
+) -> Result<Json<Vec<oportunidades::Oportunidad>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let oportunidades =
+        oportunidades::postgres_get_oportunidades(&pool, lead_id, cliente_id, etapa, propietario)
+            .await
+            .map_err(|e| {
+                eprintln!("Error getting opportunities: {:?}", e);
+                Status::InternalServerError
+            })?;
+
+    Ok(Json(oportunidades))
+}
+
+#[get("/oportunidad/<id>")]
+async fn getoportunidad(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<oportunidades::Oportunidad>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let oportunidad = oportunidades::postgres_get_oportunidad_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting opportunity: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(oportunidad))
+}
+
+#[post("/oportunidad", data = "<oportunidad>")]
+async fn postoportunidad(
+    state: &State<AppState>,
+    token: BearerToken,
+    oportunidad: Validated<oportunidades::OportunidadRequest>,
+) -> Result<Json<oportunidades::Oportunidad>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let oportunidad = oportunidades::postgres_create_oportunidad(
+        &pool,
+        profile.user_id,
+        oportunidad.into_inner(),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Error creating opportunity: {:?}", e);
+        vinculo_error_status(&e)
+    })?;
+
+    Ok(Json(oportunidad))
+}
+
+#[put("/oportunidad/<id>", data = "<oportunidad>")]
+async fn putoportunidad(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    oportunidad: Validated<oportunidades::OportunidadRequest>,
+) -> Result<Json<oportunidades::Oportunidad>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let oportunidad =
+        oportunidades::postgres_update_oportunidad(&pool, id, oportunidad.into_inner())
+            .await
+            .map_err(|e| {
+                eprintln!("Error updating opportunity: {:?}", e);
+                vinculo_error_status(&e)
+            })?
+            .ok_or(Status::NotFound)?;
+
+    Ok(Json(oportunidad))
+}
+
+// mover la tarjeta en el tablero; cualquier etapa es alcanzable, también reabrir
+#[put("/oportunidad/<id>/etapa", data = "<etapa>")]
+async fn putoportunidadetapa(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    etapa: Validated<oportunidades::OportunidadEtapaRequest>,
+) -> Result<Json<oportunidades::Oportunidad>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let oportunidad =
+        oportunidades::postgres_update_oportunidad_etapa(&pool, id, &etapa.into_inner().etapa)
+            .await
+            .map_err(|e| {
+                eprintln!("Error updating opportunity stage: {:?}", e);
+                Status::InternalServerError
+            })?
+            .ok_or(Status::NotFound)?;
+
+    Ok(Json(oportunidad))
+}
+
+#[get("/pipeline?<propietario>")]
+async fn getpipeline(
+    state: &State<AppState>,
+    token: BearerToken,
+    propietario: Option<i32>,
+) -> Result<Json<Vec<oportunidades::EtapaPipeline>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let oportunidades =
+        oportunidades::postgres_get_oportunidades(&pool, None, None, None, propietario)
+            .await
+            .map_err(|e| {
+                eprintln!("Error getting opportunities: {:?}", e);
+                Status::InternalServerError
+            })?;
+
+    let pipeline = oportunidades::EtapaPipeline::agrupar(oportunidades).map_err(|e| {
+        eprintln!("Error computing pipeline totals: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(pipeline))
+}
+
+#[get("/profiles?<include_deleted>")]
+async fn profiles(
+    state: &State<AppState>,
+    token: BearerToken,
+    include_deleted: Option<bool>,
+) -> Result<Json<Vec<Cliente>>, Status> {
+    let profile = auth_profile(token).await?;
+
+    if profile.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let profile = profile.unwrap();
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    // solo un admin puede ver los clientes borrados
+    let include_deleted = include_deleted.unwrap_or(false);
+    if include_deleted && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let clientes = clientes::postgres_get_clientes(&pool, include_deleted)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting clients: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(clientes))
+}
+
+#[post("/profile", data = "<cliente>")]
+async fn postprofile(
+    state: &State<AppState>,
+    token: BearerToken,
+    cliente: Validated<clientes::ClienteRequest>,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+
+    if profile.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let profile = profile.unwrap();
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let cliente = cliente.into_inner();
+
+    // un cliente sin cuenta de auth lo da de alta el personal
+    if cliente.user_id.is_none() && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let new_cliente = clientes::postgres_create_cliente(&pool, cliente)
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating client: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(new_cliente))
+}
+
+async fn actualizar_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    cliente: clientes::ClienteRequest,
+) -> Result<Cliente, Status> {
+    clientes::postgres_update_cliente(pool, cliente, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[put("/profile/<user_id>", data = "<cliente>")]
+async fn putprofile(
+    state: &State<AppState>,
+    token: BearerToken,
+    cliente: Validated<clientes::ClienteRequest>,
+    user_id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    if user_id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let id = cliente_de_usuario(&pool, user_id).await?.id;
+
+    Ok(Json(
+        actualizar_cliente(&pool, id, cliente.into_inner()).await?,
+    ))
+}
+
+#[put("/cliente/<id>", data = "<cliente>")]
+async fn putcliente(
+    state: &State<AppState>,
+    token: BearerToken,
+    cliente: Validated<clientes::ClienteRequest>,
+    id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    cliente_autorizado(&pool, id, &profile).await?;
+
+    Ok(Json(
+        actualizar_cliente(&pool, id, cliente.into_inner()).await?,
+    ))
+}
+
+async fn parchear_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    patch: clientes::ClientePatch,
+) -> Result<Cliente, Status> {
+    clientes::postgres_patch_cliente(pool, patch, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error patching client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[patch("/profile/<user_id>", data = "<patch>")]
+async fn patchprofile(
+    state: &State<AppState>,
+    token: BearerToken,
+    patch: Validated<clientes::ClientePatch>,
+    user_id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    if user_id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let id = cliente_de_usuario(&pool, user_id).await?.id;
+
+    Ok(Json(parchear_cliente(&pool, id, patch.into_inner()).await?))
+}
+
+#[patch("/cliente/<id>", data = "<patch>")]
+async fn patchcliente(
+    state: &State<AppState>,
+    token: BearerToken,
+    patch: Validated<clientes::ClientePatch>,
+    id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    cliente_autorizado(&pool, id, &profile).await?;
+
+    Ok(Json(parchear_cliente(&pool, id, patch.into_inner()).await?))
+}
+
+async fn borrar_cliente(pool: &sqlx::Pool<sqlx::Postgres>, id: i32) -> Result<Cliente, Status> {
+    clientes::postgres_soft_delete_cliente(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[delete("/profile/<user_id>")]
+async fn deleteprofile(
+    state: &State<AppState>,
+    token: BearerToken,
+    user_id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    if user_id != profile.user_id && !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let id = cliente_de_usuario(&pool, user_id).await?.id;
+
+    Ok(Json(borrar_cliente(&pool, id).await?))
+}
+
+#[delete("/cliente/<id>")]
+async fn deletecliente(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    cliente_autorizado(&pool, id, &profile).await?;
+
+    Ok(Json(borrar_cliente(&pool, id).await?))
+}
+
+async fn restaurar_cliente(pool: &sqlx::Pool<sqlx::Postgres>, id: i32) -> Result<Cliente, Status> {
+    clientes::postgres_restore_cliente(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error restoring client: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[post("/profile/<user_id>/restore")]
+async fn restoreprofile(
+    state: &State<AppState>,
+    token: BearerToken,
+    user_id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let id = cliente_de_usuario(&pool, user_id).await?.id;
+
+    Ok(Json(restaurar_cliente(&pool, id).await?))
+}
+
+#[post("/cliente/<id>/restore")]
+async fn restorecliente(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    Ok(Json(restaurar_cliente(&pool, id).await?))
+}
+
+// vincula o desvincula la cuenta de auth; 409 si ya pertenece a otro cliente
+#[put("/cliente/<id>/usuario", data = "<usuario>")]
+async fn putclienteusuario(
+    state: &State<AppState>,
+    token: BearerToken,
+    id: i32,
+    usuario: Validated<clientes::ClienteUsuarioRequest>,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+
+    let cliente = clientes::postgres_set_cliente_user_id(&pool, id, usuario.into_inner().user_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error linking client user: {:?}", e);
+            match e.as_database_error() {
+                Some(db) if db.is_unique_violation() => Status::Conflict,
+                _ => Status::InternalServerError,
+            }
+        })?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(cliente))
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: i32,
+}
+
+#[get("/authback/<code>")]
+async fn authback(code: &str) -> Result<Option<Json<AccessTokenResponse>>, Status> {
+    //saca authback_url de env
+    let authback_url = env::var("AUTH_ACCESSTOKEN_URL")
+        .expect("La variable de entorno AUTH_ACCESSTOKEN_URL no está definida");
+
+    let client_id =
+        env::var("CLIENT_ID").expect("La variable de entorno CLIENT_ID no está definida");
+
+    let redirect_uri =
+        env::var("REDIRECT_URI").expect("La variable de entorno REDIRECT_URI no está definida");
+
+    // Crear un cliente que no verifique los certificados SSL
+    let client = Client::builder()
+        //.danger_accept_invalid_certs(true) // Desactiva la verificación SSL
+        .build()
+        .map_err(|e| {
+            eprintln!("Error building client: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    let response = client
+        .post(authback_url)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(format!(
+            "grant_type=authorization_code&code={}&client_id={}&redirect_uri={}",
+            code, client_id, redirect_uri
+        ))
+        .send()
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting access token: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    let response_text = response.text().await.map_err(|e| {
+        eprintln!("Error getting response text: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    let response: AccessTokenResponse = serde_json::from_str(&response_text).map_err(|e| {
+        eprintln!(
+            "Error parsing access token response: {:?} {}",
+            e, response_text
+        );
+        Status::InternalServerError
+    })?;
+
+    Ok(Some(Json(response)))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct IssuePostRequest {
+    #[serde(deserialize_with = "validacion::trimmed")]
+    #[validate(length(min = 1, max = 255, message = "entre 1 y 255 caracteres"))]
+    pub subject: String,
+    #[serde(deserialize_with = "validacion::trimmed")]
+    #[validate(length(min = 1, message = "no puede estar vacío"))]
+    pub description: String,
+    pub project_id: Option<i32>,
+    pub tracker_id: Option<i32>,
+}
+
+#[post("/issue/<tipo>/<id>", data = "<issuepostrequest>")]
+async fn postissue(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    issuepostrequest: Validated<IssuePostRequest>,
+    tipo: &str,
+    id: i32,
+) -> Result<Json<issuerequest::IssueRequest>, Status> {
+    //print!("postissue: tipo: {}, id: {}\n", tipo, id);
+
+    let profile = auth_profile(token.clone()).await?;
+
+    if profile.is_none() {
+        return Err(Status::Unauthorized);
+    }
+
+    let profile = profile.unwrap();
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    // si id es 0 da error
+    if id == 0 {
+        eprintln!("Error creating issue request: id must not be 0");
+        return Err(Status::BadRequest);
+    }
+    // si tipo es vacio da error
+    if tipo.is_empty() {
+        eprintln!("Error creating issue request: type cannot be empty");
+        return Err(Status::BadRequest);
+    }
+    // si tipo no es articulo o pedido da error
+    if tipo != "articulo" && tipo != "cliente" && tipo != "pedido" {
+        eprintln!("Error creating issue request: type must be articulo,cliente or pedido");
+        return Err(Status::BadRequest);
+    }
+
+    let mut issuepostrequest = issuepostrequest.into_inner();
+
+    if tipo == "articulo" {
+        issuepostrequest.description = format!(
+            "{}\nhttps://crm.mydomain.com/articulo/{}",
+            issuepostrequest.description, id
+        );
+    } else if tipo == "cliente" {
+        issuepostrequest.description = format!(
+            "{}\nhttps://crm.mydomain.com/cliente/{}",
+            issuepostrequest.description, id
+        );
+    } else if tipo == "pedido" {
+        issuepostrequest.description = format!(
+            "{}\nhttps://crm.mydomain.com/pedido/{}",
+            issuepostrequest.description, id
+        );
+    }
+
+    // si project_id o tracker_id son 0 o nulos asigna el valor por defecto
+    if issuepostrequest.project_id.is_none() || issuepostrequest.project_id.unwrap() == 0 {
+        let default_project_id = env::var("ISSUE_DEFAULT_PROJECT_ID").map_err(|_| {
+            eprintln!("La variable ISSUE_DEFAULT_PROJECT_ID no está definida");
+            Status::InternalServerError
+        })?;
+        let num_default_project_id = default_project_id.parse::<i32>().map_err(|_| {
+            eprintln!("La variable ISSUE_DEFAULT_PROJECT_ID no es un número");
+            Status::InternalServerError
+        })?;
+        issuepostrequest.project_id = Some(num_default_project_id);
+    }
+    if issuepostrequest.tracker_id.is_none() || issuepostrequest.tracker_id.unwrap() == 0 {
+        let default_track_id = env::var("ISSUE_DEFAULT_TRACKER_ID").map_err(|_| {
+            eprintln!("La variable ISSUE_DEFAULT_TRACKER_ID no está definida");
+            Status::InternalServerError
+        })?;
+
+        let num_default_track_id = default_track_id.parse::<i32>().map_err(|_| {
+            eprintln!("La variable ISSUE_DEFAULT_TRACKER_ID no es un número");
+            Status::InternalServerError
+        })?;
+        issuepostrequest.tracker_id = Some(num_default_track_id);
+    }
+
+    let pool = state.pool.clone();
+    let issue_request = issuerequest::IssueRequest {
+        id: 0,
+        fecha_creacion: chrono::Utc::now().naive_utc(),
+        data: serde_json::json!({
+            "type": tipo,
+            "id": id,
+            "subject": issuepostrequest.subject,
+            "description": issuepostrequest.description,
+            "project_id": issuepostrequest.project_id,
+            "tracker_id": issuepostrequest.tracker_id,
+        }),
+    };
+
+    let new_issue_request = issuerequest::postgres_create_issue_request(&pool, issue_request)
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating issue request: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if new_issue_request.id == 0 {
+        eprintln!("Error creating issue request: id is 0");
+        return Err(Status::InternalServerError);
+    }
+
+    // si tipo es articulo o cliente o pedido crea la relacion
+    if tipo == "articulo" {
+        issuerequest::postgres_create_issue_request_articulo(&pool, new_issue_request.id, id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error creating issue request article relation: {:?}", e);
+                Status::InternalServerError
+            })?;
+    } else if tipo == "cliente" {
+        issuerequest::postgres_create_issue_request_cliente(&pool, new_issue_request.id, id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error creating issue request client relation: {:?}", e);
+                vinculo_error_status(&e)
+            })?;
+    } else if tipo == "pedido" {
+        issuerequest::postgres_create_issue_request_pedido(&pool, new_issue_request.id, id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error creating issue request order relation: {:?}", e);
+                Status::InternalServerError
+            })?;
+    }
+
+    // TODO hacer peticion rest con el token, id_proyecto fijo, subject y description
+    let issue_service_post_data = issueservice::IssueServicePostData {
+        subject: issuepostrequest.subject.clone(),
+        description: issuepostrequest.description.clone(),
+        project_id: issuepostrequest.project_id.unwrap(),
+        tracker_id: issuepostrequest.tracker_id.unwrap(),
+    };
+
+    let issue_service_post_ret =
+        issueservice::issue_service_post(issue_service_post_data, token.0.clone())
+            .await
+            .map_err(|e| {
+                eprintln!("Error creating issue in issue service: {:?}", e);
+                Status::InternalServerError
+            })?;
+    if issue_service_post_ret.is_empty() {
+        eprintln!("Error creating issue in issue service: empty response");
+        return Err(Status::InternalServerError);
+    }
+    // si issue_service_post_ret no es un numero da error
+    if issue_service_post_ret.parse::<i32>().is_err() {
+        eprintln!("Error creating issue in issue service: invalid response");
+        return Err(Status::InternalServerError);
+    }
+
+    let issue_id = issue_service_post_ret.parse::<i32>().unwrap();
+
+    if issue_id == 0 {
+        eprintln!("Error creating issue in issue service: id is 0");
+        return Err(Status::InternalServerError);
+    }
+
+    issuerequest::postgres_update_issue_set_issue_id_where_id(
+        &pool,
+        new_issue_request.id,
+        issue_id,
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("Error updating issue request with issue id: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(new_issue_request))
+}
+
+#[get("/tipos-iva")]
+async fn gettiposiva(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+) -> Result<Json<Vec<iva::TipoIva>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let tipos_iva = iva::postgres_get_tipos_iva(&pool).await.map_err(|e| {
+        eprintln!("Error getting tax rates: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(tipos_iva))
+}
+
+#[derive(Serialize, Deserialize)]
+struct PedidoData {
+    pedido: pedidos::Pedido,
+    detalles: Vec<pedidos::PedidoDetalle>,
+    desglose_iva: Vec<iva::DesgloseIva>,
+}
+
+async fn pedido_data(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    pedido: pedidos::Pedido,
+) -> Result<PedidoData, Status> {
+    let detalles = pedidos::postgres_get_pedido_detalles(pool, pedido.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting order lines: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    let desglose_iva = iva::DesgloseIva::agrupar(detalles.iter().map(|d| d.desglose_iva()))
+        .map_err(|e| {
+            eprintln!("Error computing tax breakdown: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(PedidoData {
+        pedido,
+        detalles,
+        desglose_iva,
+    })
+}
+
+fn pedido_error_status(e: pedidos::PedidoError) -> Status {
+    eprintln!("Error processing order: {}", e);
+    match e {
+        pedidos::PedidoError::ClienteNoEncontrado(_)
+        | pedidos::PedidoError::ArticuloNoEncontrado(_)
+        | pedidos::PedidoError::DireccionNoValida(_)
+        | pedidos::PedidoError::Money(_) => Status::UnprocessableEntity,
+        pedidos::PedidoError::StockInsuficiente(_)
+        | pedidos::PedidoError::TransicionNoValida(_, _) => Status::Conflict,
+        pedidos::PedidoError::PedidoNoEncontrado(_) => Status::NotFound,
+        pedidos::PedidoError::Sqlx(_) => Status::InternalServerError,
+    }
+}
+
+#[get("/pedido/<id>")]
+async fn getpedido(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<PedidoData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let pedido = pedidos::postgres_get_pedido_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting order: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+    cliente_autorizado(&pool, pedido.cliente_id, &profile).await?;
+
+    Ok(Json(pedido_data(&pool, pedido).await?))
+}
+
+#[post("/pedido", data = "<pedido>")]
+async fn postpedido(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    pedido: Validated<pedidos::PedidoRequest>,
+) -> Result<Json<PedidoData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let pedido = pedido.into_inner();
+    cliente_autorizado(&pool, pedido.cliente_id, &profile).await?;
+
+    let new_pedido = pedidos::postgres_create_pedido(&pool, pedido)
+        .await
+        .map_err(pedido_error_status)?;
+
+    // el pedido descuenta stock
+    state.catalogo_cache.invalidar().await;
+
+    Ok(Json(pedido_data(&pool, new_pedido).await?))
+}
+
+#[get("/informes/iva?<desde>&<hasta>")]
+async fn getinformeiva(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    desde: Option<&str>,
+    hasta: Option<&str>,
+) -> Result<Json<Vec<iva::DesgloseIva>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    // fechas en formato YYYY-MM-DD, ambas incluidas
+    let parse_fecha = |fecha: Option<&str>| {
+        fecha
+            .map(|f| chrono::NaiveDate::parse_from_str(f, "%Y-%m-%d"))
+            .transpose()
+            .map_err(|e| {
+                eprintln!("Error parsing report date: {:?}", e);
+                Status::BadRequest
+            })
+    };
+    let desde = parse_fecha(desde)?;
+    let hasta = parse_fecha(hasta)?;
+
+    let pool = state.pool.clone();
+    let informe = iva::postgres_get_informe_iva(&pool, desde, hasta)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting tax report: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(informe))
+}
+
+#[put("/pedido/<id>/estado", data = "<estado>")]
+async fn putpedidoestado(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    estado: Validated<pedidos::PedidoEstadoRequest>,
+    id: i32,
+) -> Result<Json<pedidos::Pedido>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let estado = estado.into_inner().estado;
+
+    // el cliente solo puede cancelar sus pedidos pendientes; el resto es de admin
+    let desde = if is_admin(&profile) {
+        None
+    } else {
+        let pedido = pedidos::postgres_get_pedido_by_id(&pool, id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error getting order: {:?}", e);
+                Status::InternalServerError
+            })?
+            .ok_or(Status::NotFound)?;
+        cliente_autorizado(&pool, pedido.cliente_id, &profile).await?;
+        if estado != pedidos::ESTADO_CANCELADO {
+            return Err(Status::Forbidden);
+        }
+        Some(pedidos::ESTADO_PENDIENTE)
+    };
+
+    let pedido = pedidos::postgres_update_pedido_estado(&pool, id, &estado, desde)
+        .await
+        .map_err(pedido_error_status)?;
+
+    // al cancelar se devuelve el stock
+    state.catalogo_cache.invalidar().await;
+
+    Ok(Json(pedido))
+}
+
+#[derive(Serialize, Deserialize)]
+struct PresupuestoData {
+    presupuesto: presupuestos::Presupuesto,
+    lineas: Vec<presupuestos::PresupuestoLinea>,
+    desglose_iva: Vec<iva::DesgloseIva>,
+}
+
+async fn presupuesto_data(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    presupuesto: presupuestos::Presupuesto,
+) -> Result<PresupuestoData, Status> {
+    let lineas = presupuestos::postgres_get_presupuesto_lineas(pool, presupuesto.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting quote lines: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    let desglose_iva =
+        iva::DesgloseIva::agrupar(lineas.iter().map(|l| l.desglose_iva())).map_err(|e| {
+            eprintln!("Error computing tax breakdown: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(PresupuestoData {
+        presupuesto,
+        lineas,
+        desglose_iva,
+    })
+}
+
+fn presupuesto_error_status(e: presupuestos::PresupuestoError) -> Status {
+    // los fallos al generar el pedido se tratan igual que en POST /pedido
+    if let presupuestos::PresupuestoError::Pedido(e) = e {
+        return pedido_error_status(e);
+    }
+    eprintln!("Error processing quote: {}", e);
+    match e {
+        presupuestos::PresupuestoError::ClienteNoEncontrado(_)
+        | presupuestos::PresupuestoError::ArticuloNoEncontrado(_)
+        | presupuestos::PresupuestoError::Money(_) => Status::UnprocessableEntity,
+        presupuestos::PresupuestoError::TransicionNoValida(_, _)
+        | presupuestos::PresupuestoError::Caducado(_)
+        | presupuestos::PresupuestoError::NoAceptado(_)
+        | presupuestos::PresupuestoError::YaConvertido(_) => Status::Conflict,
+        presupuestos::PresupuestoError::PresupuestoNoEncontrado(_) => Status::NotFound,
+        presupuestos::PresupuestoError::Sqlx(_) | presupuestos::PresupuestoError::Pedido(_) => {
+            Status::InternalServerError
+        }
+    }
+}
+
+#[get("/presupuesto/<id>")]
+async fn getpresupuesto(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<PresupuestoData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let presupuesto = presupuestos::postgres_get_presupuesto_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting quote: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+    cliente_autorizado(&pool, presupuesto.cliente_id, &profile).await?;
+
+    Ok(Json(presupuesto_data(&pool, presupuesto).await?))
+}
+
+// presupuesto que puede tocar el usuario: el de su cliente, o cualquiera si es admin
+async fn presupuesto_autorizado(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    profile: &AuthProfile,
+) -> Result<presupuestos::Presupuesto, Status> {
+    let presupuesto = presupuestos::postgres_get_presupuesto_by_id(pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting quote: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+    cliente_autorizado(pool, presupuesto.cliente_id, profile).await?;
+
+    Ok(presupuesto)
+}
+
+#[post("/presupuesto", data = "<presupuesto>")]
+async fn postpresupuesto(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    presupuesto: Validated<presupuestos::PresupuestoRequest>,
+) -> Result<Json<PresupuestoData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let presupuesto = presupuesto.into_inner();
+    cliente_autorizado(&pool, presupuesto.cliente_id, &profile).await?;
+
+    let new_presupuesto = presupuestos::postgres_create_presupuesto(&pool, presupuesto)
+        .await
+        .map_err(presupuesto_error_status)?;
+
+    Ok(Json(presupuesto_data(&pool, new_presupuesto).await?))
+}
+
+#[put("/presupuesto/<id>/estado", data = "<estado>")]
+async fn putpresupuestoestado(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    estado: Validated<presupuestos::PresupuestoEstadoRequest>,
+    id: i32,
+) -> Result<Json<presupuestos::Presupuesto>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let estado = estado.into_inner().estado;
+    presupuesto_autorizado(&pool, id, &profile).await?;
+
+    // el cliente solo acepta o rechaza; el resto de cambios son de admin
+    if !is_admin(&profile)
+        && estado != presupuestos::ESTADO_ACEPTADO
+        && estado != presupuestos::ESTADO_RECHAZADO
+    {
+        return Err(Status::Forbidden);
+    }
+
+    let presupuesto = presupuestos::postgres_update_presupuesto_estado(&pool, id, &estado)
+        .await
+        .map_err(presupuesto_error_status)?;
+
+    Ok(Json(presupuesto))
+}
+
+// genera el pedido de un presupuesto aceptado
+#[post("/presupuesto/<id>/pedido")]
+async fn postpresupuestopedido(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<PedidoData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    presupuesto_autorizado(&pool, id, &profile).await?;
+
+    let pedido = presupuestos::postgres_convert_presupuesto(&pool, id)
+        .await
+        .map_err(presupuesto_error_status)?;
+
+    // el pedido descuenta stock
+    state.catalogo_cache.invalidar().await;
+
+    Ok(Json(pedido_data(&pool, pedido).await?))
+}
+
+#[derive(Serialize, Deserialize)]
+struct FacturaData {
+    factura: facturas::Factura,
+    lineas: Vec<facturas::FacturaLinea>,
+    desglose_iva: Vec<iva::DesgloseIva>,
+}
+
+async fn factura_data(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    factura: facturas::Factura,
+) -> Result<FacturaData, Status> {
+    let lineas = facturas::postgres_get_factura_lineas(pool, factura.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting invoice lines: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    let desglose_iva =
+        iva::DesgloseIva::agrupar(lineas.iter().map(|l| l.desglose_iva())).map_err(|e| {
+            eprintln!("Error computing tax breakdown: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(FacturaData {
+        factura,
+        lineas,
+        desglose_iva,
+    })
+}
+
+fn factura_error_status(e: facturas::FacturaError) -> Status {
+    eprintln!("Error issuing invoice: {}", e);
+    match e {
+        facturas::FacturaError::PedidoNoEncontrado(_)
+        | facturas::FacturaError::FacturaNoEncontrada(_) => Status::NotFound,
+        facturas::FacturaError::PedidoNoEntregado(_)
+        | facturas::FacturaError::PedidoYaFacturado(_)
+        | facturas::FacturaError::FacturaNoRectificable(_) => Status::Conflict,
+        facturas::FacturaError::Sqlx(_) => Status::InternalServerError,
+    }
+}
+
+#[get("/factura/<id>")]
+async fn getfactura(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<FacturaData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let factura = facturas::postgres_get_factura_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting invoice: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+    cliente_autorizado(&pool, factura.cliente_id, &profile).await?;
+
+    Ok(Json(factura_data(&pool, factura).await?))
+}
+
+#[post("/pedido/<id>/factura")]
+async fn postfactura(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<FacturaData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let factura = facturas::postgres_create_factura_from_pedido(&pool, id)
+        .await
+        .map_err(factura_error_status)?;
+
+    Ok(Json(factura_data(&pool, factura).await?))
+}
+
+#[post("/factura/<id>/rectificativa", data = "<rectificativa>")]
+async fn postfacturarectificativa(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    rectificativa: Validated<facturas::RectificativaRequest>,
+    id: i32,
+) -> Result<Json<FacturaData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let rectificativa = rectificativa.into_inner();
+    let factura = facturas::postgres_create_factura_rectificativa(&pool, id, &rectificativa.motivo)
+        .await
+        .map_err(factura_error_status)?;
+
+    Ok(Json(factura_data(&pool, factura).await?))
+}
+
+// /factura/<id>.pdf y /pedido/<id>.pdf: el segmento no es un i32 así que llega aquí
+fn pdf_id(archivo: &str) -> Result<i32, Status> {
+    archivo
+        .strip_suffix(".pdf")
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or(Status::NotFound)
+}
+
+fn render_pdf(documento: &pdf::Documento) -> Result<(ContentType, Vec<u8>), Status> {
+    let bytes = pdf::render(&pdf::Empresa::from_env(), documento).map_err(|e| {
+        eprintln!("Error rendering PDF: {:?}", e);
         Status::InternalServerError
     })?;
 
-    let response: AccessTokenResponse = serde_json::from_str(&response_text).map_err(|e| {
-        eprintln!(
-            "Error parsing access token response: {:?} {}",
-            e, response_text
-        );
+    Ok((ContentType::PDF, bytes))
+}
+
+#[get("/factura/<archivo>", rank = 2)]
+async fn getfacturapdf(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    archivo: &str,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let id = pdf_id(archivo)?;
+
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let factura = facturas::postgres_get_factura_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting invoice: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+    cliente_autorizado(&pool, factura.cliente_id, &profile).await?;
+
+    let rectificada = match factura.factura_rectificada_id {
+        Some(rectificada_id) => facturas::postgres_get_factura_by_id(&pool, rectificada_id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error getting rectified invoice: {:?}", e);
+                Status::InternalServerError
+            })?,
+        None => None,
+    };
+
+    let data = factura_data(&pool, factura).await?;
+    let documento = pdf::documento_factura(
+        &data.factura,
+        &data.lineas,
+        data.desglose_iva.clone(),
+        rectificada.as_ref().map(|f| f.codigo.as_str()),
+    );
+
+    render_pdf(&documento)
+}
+
+#[get("/pedido/<archivo>", rank = 2)]
+async fn getpedidopdf(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    archivo: &str,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let id = pdf_id(archivo)?;
+
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let pedido = pedidos::postgres_get_pedido_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting order: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+    let cliente = cliente_autorizado(&pool, pedido.cliente_id, &profile).await?;
+
+    let data = pedido_data(&pool, pedido).await?;
+    let documento = pdf::documento_pedido(
+        &data.pedido,
+        &data.detalles,
+        data.desglose_iva.clone(),
+        &cliente,
+    );
+
+    render_pdf(&documento)
+}
+
+#[get("/tarifas")]
+async fn gettarifas(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+) -> Result<Json<Vec<precios::Tarifa>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let tarifas = precios::postgres_get_tarifas(&pool).await.map_err(|e| {
+        eprintln!("Error getting price lists: {:?}", e);
         Status::InternalServerError
     })?;
 
-    Ok(Some(Json(response)))
+    Ok(Json(tarifas))
+}
+
+#[derive(Serialize, Deserialize)]
+struct TarifaData {
+    tarifa: precios::Tarifa,
+    precios: Vec<precios::TarifaPrecio>,
+}
+
+#[get("/tarifa/<id>")]
+async fn gettarifa(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Json<TarifaData>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let tarifa = precios::postgres_get_tarifa_by_id(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting price list: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    let precios = precios::postgres_get_tarifa_precios(&pool, id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting price list prices: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(TarifaData { tarifa, precios }))
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct IssuePostRequest {
-    pub subject: String,
-    pub description: String,
-    pub project_id: Option<i32>,
-    pub tracker_id: Option<i32>,
+#[post("/tarifa", data = "<tarifa>")]
+async fn posttarifa(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    tarifa: Validated<precios::TarifaRequest>,
+) -> Result<Json<precios::Tarifa>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let tarifa = precios::postgres_create_tarifa(&pool, tarifa.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating price list: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(tarifa))
 }
 
-#[post("/issue/<tipo>/<id>", data = "<issuepostrequest>")]
-async fn postissue(
+#[put("/tarifa/<id>/precio/<articulo_id>", data = "<precio>")]
+async fn puttarifaprecio(
     state: &rocket::State<AppState>,
     token: BearerToken,
-    mut issuepostrequest: Json<IssuePostRequest>,
-    tipo: &str,
     id: i32,
-) -> Result<Json<issuerequest::IssueRequest>, Status> {
-    //print!("postissue: tipo: {}, id: {}\n", tipo, id);
-
-    let profile = auth_profile(token.clone()).await?;
+    articulo_id: i32,
+    precio: Validated<precios::TarifaPrecioRequest>,
+) -> Result<Json<precios::TarifaPrecio>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
 
-    if profile.is_none() {
-        return Err(Status::Unauthorized);
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
     }
 
-    let profile = profile.unwrap();
+    let pool = state.pool.clone();
+    let tarifa_precio =
+        precios::postgres_set_tarifa_precio(&pool, id, articulo_id, precio.into_inner().precio)
+            .await
+            .map_err(|e| {
+                eprintln!("Error setting price list price: {:?}", e);
+                Status::InternalServerError
+            })?
+            .ok_or(Status::NotFound)?;
 
-    if profile.user_id == 0 {
+    Ok(Json(tarifa_precio))
+}
+
+#[delete("/tarifa/<id>/precio/<articulo_id>")]
+async fn deletetarifaprecio(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+    articulo_id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
         return Err(Status::Forbidden);
     }
 
-    // si id es 0 da error
-    if id == 0 {
-        eprintln!("Error creating issue request: id must not be 0");
-        return Err(Status::BadRequest);
-    }
-    // si tipo es vacio da error
-    if tipo.is_empty() {
-        eprintln!("Error creating issue request: type cannot be empty");
-        return Err(Status::BadRequest);
+    let pool = state.pool.clone();
+    let deleted = precios::postgres_delete_tarifa_precio(&pool, id, articulo_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error deleting price list price: {:?}", e);
+            Status::InternalServerError
+        })?;
+
+    if !deleted {
+        return Err(Status::NotFound);
     }
-    // si tipo no es articulo o pedido da error
-    if tipo != "articulo" && tipo != "cliente" && tipo != "pedido" {
-        eprintln!("Error creating issue request: type must be articulo,cliente or pedido");
-        return Err(Status::BadRequest);
+
+    Ok(Status::NoContent)
+}
+
+async fn asignar_tarifa(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    tarifa_id: Option<i32>,
+) -> Result<Cliente, Status> {
+    clientes::postgres_set_cliente_tarifa(pool, id, tarifa_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error setting client price list: {:?}", e);
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)
+}
+
+#[put("/profile/<user_id>/tarifa", data = "<tarifa>")]
+async fn putprofiletarifa(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    user_id: i32,
+    tarifa: Validated<clientes::ClienteTarifaRequest>,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
     }
 
-    // si subject o description son vacios da error
-    if issuepostrequest.subject.is_empty() || issuepostrequest.description.is_empty() {
-        eprintln!("Error creating issue request: subject or description cannot be empty");
-        return Err(Status::BadRequest);
+    let pool = state.pool.clone();
+    let id = cliente_de_usuario(&pool, user_id).await?.id;
+
+    Ok(Json(
+        asignar_tarifa(&pool, id, tarifa.into_inner().tarifa_id).await?,
+    ))
+}
+
+#[put("/cliente/<id>/tarifa", data = "<tarifa>")]
+async fn putclientetarifa(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+    tarifa: Validated<clientes::ClienteTarifaRequest>,
+) -> Result<Json<Cliente>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
     }
 
-    if tipo == "articulo" {
-        issuepostrequest.description = format!(
-            "{}\nhttps://crm.mydomain.com/articulo/{}",
-            issuepostrequest.description, id
-        );
-    } else if tipo == "cliente" {
-        issuepostrequest.description = format!(
-            "{}\nhttps://crm.mydomain.com/profile/{}",
-            issuepostrequest.description, id
-        );
-    } else if tipo == "pedido" {
-        issuepostrequest.description = format!(
-            "{}\nhttps://crm.mydomain.com/pedido/{}",
-            issuepostrequest.description, id
-        );
+    let pool = state.pool.clone();
+
+    Ok(Json(
+        asignar_tarifa(&pool, id, tarifa.into_inner().tarifa_id).await?,
+    ))
+}
+
+#[get("/promociones?<vigentes>")]
+async fn getpromociones(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    vigentes: Option<bool>,
+) -> Result<Json<Vec<precios::Promocion>>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
     }
 
-    // si project_id o tracker_id son 0 o nulos asigna el valor por defecto
-    if issuepostrequest.project_id.is_none() || issuepostrequest.project_id.unwrap() == 0 {
-        let default_project_id = env::var("ISSUE_DEFAULT_PROJECT_ID").map_err(|_| {
-            eprintln!("La variable ISSUE_DEFAULT_PROJECT_ID no está definida");
-            Status::InternalServerError
-        })?;
-        let num_default_project_id = default_project_id.parse::<i32>().map_err(|_| {
-            eprintln!("La variable ISSUE_DEFAULT_PROJECT_ID no es un número");
+    let pool = state.pool.clone();
+    let promociones = precios::postgres_get_promociones(&pool, vigentes.unwrap_or(false))
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting promotions: {:?}", e);
             Status::InternalServerError
         })?;
-        issuepostrequest.project_id = Some(num_default_project_id);
+
+    Ok(Json(promociones))
+}
+
+#[post("/promocion", data = "<promocion>")]
+async fn postpromocion(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    promocion: Validated<precios::PromocionRequest>,
+) -> Result<Json<precios::Promocion>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
     }
-    if issuepostrequest.tracker_id.is_none() || issuepostrequest.tracker_id.unwrap() == 0 {
-        let default_track_id = env::var("ISSUE_DEFAULT_TRACKER_ID").map_err(|_| {
-            eprintln!("La variable ISSUE_DEFAULT_TRACKER_ID no está definida");
-            Status::InternalServerError
-        })?;
 
-        let num_default_track_id = default_track_id.parse::<i32>().map_err(|_| {
-            eprintln!("La variable ISSUE_DEFAULT_TRACKER_ID no es un número");
+    let pool = state.pool.clone();
+    let promocion = precios::postgres_create_promocion(&pool, promocion.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Error creating promotion: {:?}", e);
             Status::InternalServerError
-        })?;
-        issuepostrequest.tracker_id = Some(num_default_track_id);
+        })?
+        .ok_or(Status::UnprocessableEntity)?;
+
+    Ok(Json(promocion))
+}
+
+#[delete("/promocion/<id>")]
+async fn deletepromocion(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    id: i32,
+) -> Result<Status, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
     }
 
     let pool = state.pool.clone();
-    let issue_request = issuerequest::IssueRequest {
-        id: 0,
-        fecha_creacion: chrono::Utc::now().naive_utc(),
-        data: serde_json::json!({
-            "type": tipo,
-            "id": id,
-            "subject": issuepostrequest.subject,
-            "description": issuepostrequest.description,
-            "project_id": issuepostrequest.project_id,
-            "tracker_id": issuepostrequest.tracker_id,
-        }),
-    };
-
-    let new_issue_request = issuerequest::postgres_create_issue_request(&pool, issue_request)
+    let deleted = precios::postgres_delete_promocion(&pool, id)
         .await
         .map_err(|e| {
-            eprintln!("Error creating issue request: {:?}", e);
+            eprintln!("Error deleting promotion: {:?}", e);
             Status::InternalServerError
         })?;
 
-    if new_issue_request.id == 0 {
-        eprintln!("Error creating issue request: id is 0");
-        return Err(Status::InternalServerError);
+    if !deleted {
+        return Err(Status::NotFound);
     }
 
-    // si tipo es articulo o cliente o pedido crea la relacion
-    if tipo == "articulo" {
-        let _ =
-            issuerequest::postgres_create_issue_request_articulo(&pool, new_issue_request.id, id)
-                .await
-                .map_err(|e| {
-                    eprintln!("Error creating issue request article relation: {:?}", e);
-                    Status::InternalServerError
-                })?;
-    } else if tipo == "cliente" {
-        let _ =
-            issuerequest::postgres_create_issue_request_cliente(&pool, new_issue_request.id, id)
-                .await
-                .map_err(|e| {
-                    eprintln!("Error creating issue request client relation: {:?}", e);
-                    Status::InternalServerError
-                })?;
-    } else if tipo == "pedido" {
-        let _ = issuerequest::postgres_create_issue_request_pedido(&pool, new_issue_request.id, id)
-            .await
-            .map_err(|e| {
-                eprintln!("Error creating issue request order relation: {:?}", e);
-                Status::InternalServerError
-            })?;
-    }
+    Ok(Status::NoContent)
+}
 
-    // TODO hacer peticion rest con el token, id_proyecto fijo, subject y description
-    let issue_service_post_data = issueservice::IssueServicePostData {
-        subject: issuepostrequest.subject.clone(),
-        description: issuepostrequest.description.clone(),
-        project_id: issuepostrequest.project_id.unwrap(),
-        tracker_id: issuepostrequest.tracker_id.unwrap(),
-    };
+// precio que pagaría el cliente por una línea, sin crear el pedido
+#[get("/precio?<cliente_id>&<articulo_id>&<cantidad>")]
+async fn getprecio(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    cliente_id: i32,
+    articulo_id: i32,
+    cantidad: Option<i32>,
+) -> Result<Json<precios::PrecioAplicado>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
 
-    let issue_service_post_ret =
-        issueservice::issue_service_post(issue_service_post_data, token.0.clone())
-            .await
-            .map_err(|e| {
-                eprintln!("Error creating issue in issue service: {:?}", e);
-                Status::InternalServerError
-            })?;
-    if issue_service_post_ret.is_empty() {
-        eprintln!("Error creating issue in issue service: empty response");
-        return Err(Status::InternalServerError);
-    }
-    // si issue_service_post_ret no es un numero da error
-    if issue_service_post_ret.parse::<i32>().is_err() {
-        eprintln!("Error creating issue in issue service: invalid response");
-        return Err(Status::InternalServerError);
+    if profile.user_id == 0 {
+        return Err(Status::Forbidden);
     }
 
-    let issue_id = issue_service_post_ret.parse::<i32>().unwrap();
+    let pool = state.pool.clone();
+    cliente_autorizado(&pool, cliente_id, &profile).await?;
 
-    if issue_id == 0 {
-        eprintln!("Error creating issue in issue service: id is 0");
-        return Err(Status::InternalServerError);
+    let articulo = postgres_get_articulo_by_id(&pool, articulo_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => Status::NotFound,
+            e => {
+                eprintln!("Error getting article: {:?}", e);
+                Status::InternalServerError
+            }
+        })?;
+    if articulo.deleted_at.is_some() {
+        return Err(Status::NotFound);
     }
 
-    issuerequest::postgres_update_issue_set_issue_id_where_id(
-        &pool,
-        new_issue_request.id,
-        issue_id,
+    let mut conn = pool.acquire().await.map_err(|e| {
+        eprintln!("Error acquiring connection: {:?}", e);
+        Status::InternalServerError
+    })?;
+    let precio = precios::mejor_precio(
+        &mut conn,
+        cliente_id,
+        articulo_id,
+        cantidad.unwrap_or(1),
+        articulo.precio,
     )
     .await
     .map_err(|e| {
-        eprintln!("Error updating issue request with issue id: {:?}", e);
+        eprintln!("Error computing price: {:?}", e);
         Status::InternalServerError
     })?;
 
-    Ok(Json(new_issue_request))
+    Ok(Json(precio))
+}
+
+fn pago_error_status(e: pagos::PagoError) -> Status {
+    eprintln!("Error registering payment: {}", e);
+    match e {
+        pagos::PagoError::PedidoNoEncontrado(_) | pagos::PagoError::FacturaNoEncontrada(_) => {
+            Status::NotFound
+        }
+        pagos::PagoError::Money(_) => Status::UnprocessableEntity,
+        pagos::PagoError::PedidoCancelado(_)
+        | pagos::PagoError::FacturaRectificativa(_)
+        | pagos::PagoError::ImporteExcedePendiente(_) => Status::Conflict,
+        pagos::PagoError::Sqlx(_) => Status::InternalServerError,
+    }
+}
+
+#[post("/pago", data = "<pago>")]
+async fn postpago(
+    state: &rocket::State<AppState>,
+    token: BearerToken,
+    pago: Validated<pagos::PagoRequest>,
+) -> Result<Json<pagos::Pago>, Status> {
+    let profile = auth_profile(token).await?;
+    let profile = profile.ok_or(Status::Unauthorized)?;
+
+    if profile.user_id == 0 || !is_admin(&profile) {
+        return Err(Status::Forbidden);
+    }
+
+    let pool = state.pool.clone();
+    let pago = pagos::postgres_create_pago(&pool, pago.into_inner())
+        .await
+        .map_err(pago_error_status)?;
+
+    Ok(Json(pago))
 }
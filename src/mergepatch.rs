@@ -0,0 +1,43 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::validacion::Trim;
+
+// JSON Merge Patch (RFC 7396): un campo ausente no se toca, un campo a null se borra.
+// Con #[serde(default, deserialize_with = "mergepatch::nullable")] sobre un
+// Option<Option<T>> queda: ausente -> None, null -> Some(None), valor -> Some(Some(v))
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub fn nullable_trimmed<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de> + Trim,
+    D: Deserializer<'de>,
+{
+    nullable::<T, D>(deserializer).map(|value| value.map(Trim::trim))
+}
+
+// Para columnas NOT NULL: ausente -> None, valor -> Some(v) y null es un error
+pub fn required<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    match Option::<T>::deserialize(deserializer)? {
+        Some(value) => Ok(Some(value)),
+        None => Err(D::Error::custom("el campo no puede ser null")),
+    }
+}
+
+pub fn required_trimmed<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de> + Trim,
+    D: Deserializer<'de>,
+{
+    required::<T, D>(deserializer).map(Trim::trim)
+}
@@ -0,0 +1,414 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use sqlx::postgres::PgRow;
+
+// Importes en unidades menores (céntimos) con moneda explícita. En base de datos
+// se guardan como BIGINT + CHAR(3) y en JSON como {"amount": "1200.00", "currency": "EUR"}.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Currency {
+    #[default]
+    Eur,
+    Usd,
+    Gbp,
+}
+
+impl Currency {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Eur => "EUR",
+            Currency::Usd => "USD",
+            Currency::Gbp => "GBP",
+        }
+    }
+
+    // número de decimales de la moneda
+    pub fn minor_units(&self) -> u32 {
+        match self {
+            Currency::Eur | Currency::Usd | Currency::Gbp => 2,
+        }
+    }
+
+    fn scale(&self) -> i64 {
+        10_i64.pow(self.minor_units())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code.trim().to_ascii_uppercase().as_str() {
+            "EUR" => Ok(Currency::Eur),
+            "USD" => Ok(Currency::Usd),
+            "GBP" => Ok(Currency::Gbp),
+            _ => Err(MoneyError::UnknownCurrency(code.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    CurrencyMismatch(Currency, Currency),
+    UnknownCurrency(String),
+    InvalidAmount(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => write!(f, "desbordamiento en la operación con importes"),
+            MoneyError::CurrencyMismatch(a, b) => write!(f, "monedas distintas: {} y {}", a, b),
+            MoneyError::UnknownCurrency(code) => write!(f, "moneda desconocida: {}", code),
+            MoneyError::InvalidAmount(amount) => write!(f, "importe no válido: {}", amount),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    amount: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn from_minor(amount: i64, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::from_minor(0, currency)
+    }
+
+    // importe y código de moneda tal como vienen de base de datos
+    pub fn from_db(amount: i64, currency: &str) -> Result<Self, MoneyError> {
+        Ok(Money::from_minor(amount, currency.parse()?))
+    }
+
+    pub fn amount_minor(&self) -> i64 {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount < 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        let amount = self
+            .amount
+            .checked_add(other.amount)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(amount, self.currency))
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        let amount = self
+            .amount
+            .checked_sub(other.amount)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(amount, self.currency))
+    }
+
+    // precio unitario por cantidad, para los subtotales de línea
+    pub fn checked_mul(self, quantity: i64) -> Result<Money, MoneyError> {
+        let amount = self
+            .amount
+            .checked_mul(quantity)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(amount, self.currency))
+    }
+
+    // porcentaje expresado en puntos básicos (2100 = 21%), redondeado a la unidad
+    // menor de la moneda; los empates se alejan de cero (-0.5 -> -1)
+    pub fn percentage(self, basis_points: i32) -> Result<Money, MoneyError> {
+        let product = (self.amount as i128) * (basis_points as i128);
+        let amount = div_round_half_away(product, 10_000);
+        let amount = i64::try_from(amount).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::from_minor(amount, self.currency))
+    }
+
+    // suma de importes de la misma moneda, por ejemplo el total de un pedido
+    pub fn sum<I>(currency: Currency, amounts: I) -> Result<Money, MoneyError>
+    where
+        I: IntoIterator<Item = Money>,
+    {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), Money::checked_add)
+    }
+
+    // "1200.00" con tantos decimales como la moneda
+    pub fn to_decimal_string(self) -> String {
+        let decimals = self.currency.minor_units() as usize;
+        let scale = self.currency.scale();
+        let sign = if self.amount < 0 { "-" } else { "" };
+        let abs = self.amount.unsigned_abs();
+        let units = abs / scale as u64;
+        if decimals == 0 {
+            return format!("{}{}", sign, units);
+        }
+        let fraction = abs % scale as u64;
+        format!("{}{}.{:0width$}", sign, units, fraction, width = decimals)
+    }
+
+    // parsea "1200", "1200.5" o "1200.50"; si trae más decimales que la moneda
+    // se redondea a la unidad menor con los empates alejándose de cero
+    // ("0.125" EUR -> 0.13, "-0.125" EUR -> -0.13)
+    pub fn parse(amount: &str, currency: Currency) -> Result<Money, MoneyError> {
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+        let text = amount.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (units, fraction) = text.split_once('.').unwrap_or((text, ""));
+        let valid_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if units.is_empty() || !valid_digits(units) || !valid_digits(fraction) {
+            return Err(invalid());
+        }
+        if fraction.len() > 18 || (text.contains('.') && fraction.is_empty()) {
+            return Err(invalid());
+        }
+        let digits: i128 = format!("{}{}", units, fraction)
+            .parse()
+            .map_err(|_| MoneyError::Overflow)?;
+        let digits = if negative { -digits } else { digits };
+        let decimals = currency.minor_units() as usize;
+        let amount = if fraction.len() > decimals {
+            div_round_half_away(digits, 10_i128.pow((fraction.len() - decimals) as u32))
+        } else {
+            digits
+                .checked_mul(10_i128.pow((decimals - fraction.len()) as u32))
+                .ok_or(MoneyError::Overflow)?
+        };
+        let amount = i64::try_from(amount).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::from_minor(amount, currency))
+    }
+
+    fn same_currency(&self, other: Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        Ok(())
+    }
+}
+
+// lee un importe de una fila a partir de su columna BIGINT y la columna de moneda
+pub fn money_from_row(
+    row: &PgRow,
+    amount_column: &str,
+    currency_column: &str,
+) -> Result<Money, sqlx::Error> {
+    let amount: i64 = row.try_get(amount_column)?;
+    let currency: String = row.try_get(currency_column)?;
+    Money::from_db(amount, &currency).map_err(|e| sqlx::Error::ColumnDecode {
+        index: currency_column.to_string(),
+        source: Box::new(e),
+    })
+}
+
+fn div_round_half_away(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if remainder.abs() * 2 >= denominator.abs() {
+        quotient + numerator.signum() * denominator.signum()
+    } else {
+        quotient
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.to_decimal_string(), self.currency)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Money", 2)?;
+        state.serialize_field("amount", &self.to_decimal_string())?;
+        state.serialize_field("currency", self.currency.code())?;
+        state.end()
+    }
+}
+
+// amount del objeto: texto decimal o entero en unidades menores
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Amount {
+    Minor(i64),
+    Decimal(String),
+}
+
+// acepta {"amount": "12.50", "currency": "EUR"}, directamente "12.50" (EUR) o
+// un entero en unidades menores (1250 = 12.50 EUR), como el precio del esquema anterior
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl<'de> Visitor<'de> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("un importe decimal en texto, un entero en unidades menores o {\"amount\", \"currency\"}")
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+                Ok(Money::from_minor(value, Currency::default()))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+                let value = i64::try_from(value).map_err(|_| E::custom(MoneyError::Overflow))?;
+                Ok(Money::from_minor(value, Currency::default()))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+                Money::parse(value, Currency::default()).map_err(E::custom)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Money, A::Error> {
+                let mut amount: Option<Amount> = None;
+                let mut currency: Option<String> = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "amount" => amount = Some(map.next_value()?),
+                        "currency" => currency = Some(map.next_value()?),
+                        other => {
+                            return Err(de::Error::unknown_field(other, &["amount", "currency"]));
+                        }
+                    }
+                }
+                let amount = amount.ok_or_else(|| de::Error::missing_field("amount"))?;
+                let currency = match currency {
+                    Some(code) => code.parse().map_err(de::Error::custom)?,
+                    None => Currency::default(),
+                };
+                match amount {
+                    Amount::Minor(amount) => Ok(Money::from_minor(amount, currency)),
+                    Amount::Decimal(amount) => {
+                        Money::parse(&amount, currency).map_err(de::Error::custom)
+                    }
+                }
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eur(amount: i64) -> Money {
+        Money::from_minor(amount, Currency::Eur)
+    }
+
+    #[test]
+    fn parsea_importes_decimales() {
+        assert_eq!(Money::parse("1200", Currency::Eur), Ok(eur(120_000)));
+        assert_eq!(Money::parse("1200.5", Currency::Eur), Ok(eur(120_050)));
+        assert_eq!(Money::parse(" 1200.50 ", Currency::Eur), Ok(eur(120_050)));
+        assert_eq!(Money::parse("-3.07", Currency::Eur), Ok(eur(-307)));
+    }
+
+    #[test]
+    fn parse_redondea_los_empates_alejandose_de_cero() {
+        assert_eq!(Money::parse("0.125", Currency::Eur), Ok(eur(13)));
+        assert_eq!(Money::parse("-0.125", Currency::Eur), Ok(eur(-13)));
+        assert_eq!(Money::parse("0.124", Currency::Eur), Ok(eur(12)));
+    }
+
+    #[test]
+    fn parse_rechaza_importes_no_validos() {
+        for texto in ["", "-", "abc", "1.", ".5", "1.2.3", "1,50", "+1", "1e3"] {
+            assert!(
+                matches!(
+                    Money::parse(texto, Currency::Eur),
+                    Err(MoneyError::InvalidAmount(_))
+                ),
+                "{:?}",
+                texto
+            );
+        }
+    }
+
+    #[test]
+    fn parse_detecta_desbordamientos() {
+        assert_eq!(
+            Money::parse("92233720368547758.08", Currency::Eur),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::parse("92233720368547758.07", Currency::Eur),
+            Ok(eur(i64::MAX))
+        );
+    }
+
+    #[test]
+    fn porcentajes_en_puntos_basicos() {
+        assert_eq!(eur(10_000).percentage(2100), Ok(eur(2_100)));
+        // 0.5 céntimos se redondean hacia fuera en los dos signos
+        assert_eq!(eur(50).percentage(100), Ok(eur(1)));
+        assert_eq!(eur(-50).percentage(100), Ok(eur(-1)));
+        assert_eq!(eur(49).percentage(100), Ok(eur(0)));
+        assert_eq!(eur(i64::MAX).percentage(20_000), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn operaciones_que_desbordan() {
+        assert_eq!(eur(i64::MAX).checked_add(eur(1)), Err(MoneyError::Overflow));
+        assert_eq!(eur(i64::MIN).checked_sub(eur(1)), Err(MoneyError::Overflow));
+        assert_eq!(eur(i64::MAX).checked_mul(2), Err(MoneyError::Overflow));
+        assert_eq!(
+            Money::sum(Currency::Eur, [eur(i64::MAX), eur(1)]),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            eur(1).checked_add(Money::from_minor(1, Currency::Usd)),
+            Err(MoneyError::CurrencyMismatch(Currency::Eur, Currency::Usd))
+        );
+    }
+
+    #[test]
+    fn texto_decimal() {
+        assert_eq!(eur(120_050).to_decimal_string(), "1200.50");
+        assert_eq!(eur(-5).to_decimal_string(), "-0.05");
+        assert_eq!(eur(i64::MIN).to_decimal_string(), "-92233720368547758.08");
+    }
+
+    #[test]
+    fn deserializa_texto_enteros_y_objetos() {
+        let money: Money = serde_json::from_str("\"12.30\"").unwrap();
+        assert_eq!(money, eur(1_230));
+        let money: Money = serde_json::from_str("1230").unwrap();
+        assert_eq!(money, eur(1_230));
+        let money: Money = serde_json::from_str(r#"{"amount": 1230, "currency": "usd"}"#).unwrap();
+        assert_eq!(money, Money::from_minor(1_230, Currency::Usd));
+        let money: Money =
+            serde_json::from_str(r#"{"amount": "12.30", "currency": "GBP"}"#).unwrap();
+        assert_eq!(money, Money::from_minor(1_230, Currency::Gbp));
+        assert!(serde_json::from_str::<Money>(r#"{"amount": "1", "currency": "XXX"}"#).is_err());
+        assert!(serde_json::from_str::<Money>("12.3").is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+pub const NOTIFICACION_TAREA_VENCIDA: &str = "tarea_vencida";
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Notificacion {
+    pub id: i32,
+    pub user_id: i32,
+    pub tipo: String,
+    pub mensaje: String,
+    pub tarea_id: Option<i32>,
+    pub leida: bool,
+    pub fecha_creacion: chrono::NaiveDateTime,
+}
+
+const NOTIFICACION_COLUMNS: &str = "id, user_id, tipo, mensaje, tarea_id, leida, fecha_creacion";
+
+pub async fn postgres_get_notificaciones(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    solo_no_leidas: bool,
+) -> Result<Vec<Notificacion>, sqlx::Error> {
+    let notificaciones = sqlx::query_as::<_, Notificacion>(&format!(
+        "SELECT {}
+        FROM notificaciones
+        WHERE user_id = $1 AND (NOT $2 OR NOT leida)
+        ORDER BY fecha_creacion DESC, id DESC",
+        NOTIFICACION_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(solo_no_leidas)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(notificaciones)
+}
+
+// None si la notificación no existe o es de otro usuario
+pub async fn postgres_marcar_notificacion_leida(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    user_id: i32,
+) -> Result<Option<Notificacion>, sqlx::Error> {
+    let notificacion = sqlx::query_as::<_, Notificacion>(&format!(
+        "UPDATE notificaciones
+        SET leida = TRUE
+        WHERE id = $1 AND user_id = $2
+        RETURNING {}",
+        NOTIFICACION_COLUMNS
+    ))
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(notificacion)
+}
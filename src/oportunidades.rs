@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::{Validate, ValidationError};
+
+use crate::money::{Money, MoneyError, money_from_row};
+
+pub const ETAPA_PROSPECCION: &str = "prospeccion";
+pub const ETAPA_CUALIFICACION: &str = "cualificacion";
+pub const ETAPA_PROPUESTA: &str = "propuesta";
+pub const ETAPA_NEGOCIACION: &str = "negociacion";
+pub const ETAPA_GANADA: &str = "ganada";
+pub const ETAPA_PERDIDA: &str = "perdida";
+
+// en el orden en que se muestran las columnas del pipeline
+pub const ETAPAS: [&str; 6] = [
+    ETAPA_PROSPECCION,
+    ETAPA_CUALIFICACION,
+    ETAPA_PROPUESTA,
+    ETAPA_NEGOCIACION,
+    ETAPA_GANADA,
+    ETAPA_PERDIDA,
+];
+
+pub fn etapa_cerrada(etapa: &str) -> bool {
+    etapa == ETAPA_GANADA || etapa == ETAPA_PERDIDA
+}
+
+pub fn validate_etapa(etapa: &str) -> Result<(), ValidationError> {
+    if !ETAPAS.contains(&etapa) {
+        return Err(ValidationError::new("etapa").with_message(
+            "debe ser prospeccion, cualificacion, propuesta, negociacion, ganada o perdida".into(),
+        ));
+    }
+    Ok(())
+}
+
+// la oportunidad es de un lead, de un cliente o de ambos si el lead ya se convirtió
+fn validate_oportunidad(oportunidad: &OportunidadRequest) -> Result<(), ValidationError> {
+    if oportunidad.lead_id.is_none() && oportunidad.cliente_id.is_none() {
+        return Err(ValidationError::new("destino")
+            .with_message("indica lead_id, cliente_id o ambos".into()));
+    }
+    Ok(())
+}
+
+fn etapa_por_defecto() -> String {
+    ETAPA_PROSPECCION.to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+#[validate(schema(function = "validate_oportunidad"))]
+pub struct OportunidadRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 200, message = "entre 1 y 200 caracteres"))]
+    pub titulo: String,
+    pub lead_id: Option<i32>,
+    pub cliente_id: Option<i32>,
+    #[serde(
+        default = "etapa_por_defecto",
+        deserialize_with = "crate::validacion::trimmed"
+    )]
+    #[validate(custom(function = "validate_etapa"))]
+    pub etapa: String,
+    #[validate(custom(function = "crate::validacion::validate_non_negative_money"))]
+    pub valor_esperado: Money,
+    pub fecha_cierre_prevista: Option<chrono::NaiveDate>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct OportunidadEtapaRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_etapa"))]
+    pub etapa: String,
+}
+
+// al convertir el lead se informa también cliente_id; fecha_cierre solo en ganada o perdida
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Oportunidad {
+    pub id: i32,
+    pub titulo: String,
+    pub lead_id: Option<i32>,
+    pub cliente_id: Option<i32>,
+    pub etapa: String,
+    pub valor_esperado: Money,
+    pub fecha_cierre_prevista: Option<chrono::NaiveDate>,
+    pub fecha_cierre: Option<chrono::NaiveDateTime>,
+    pub propietario_user_id: i32,
+    pub fecha_creacion: chrono::NaiveDateTime,
+    pub fecha_modificacion: Option<chrono::NaiveDateTime>,
+}
+
+impl<'r> FromRow<'r, PgRow> for Oportunidad {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Oportunidad {
+            id: row.try_get("id")?,
+            titulo: row.try_get("titulo")?,
+            lead_id: row.try_get("lead_id")?,
+            cliente_id: row.try_get("cliente_id")?,
+            etapa: row.try_get("etapa")?,
+            valor_esperado: money_from_row(row, "valor_esperado", "moneda")?,
+            fecha_cierre_prevista: row.try_get("fecha_cierre_prevista")?,
+            fecha_cierre: row.try_get("fecha_cierre")?,
+            propietario_user_id: row.try_get("propietario_user_id")?,
+            fecha_creacion: row.try_get("fecha_creacion")?,
+            fecha_modificacion: row.try_get("fecha_modificacion")?,
+        })
+    }
+}
+
+// una columna del tablero con el valor esperado sumado por moneda
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EtapaPipeline {
+    pub etapa: String,
+    pub oportunidades: Vec<Oportunidad>,
+    pub totales: Vec<Money>,
+}
+
+impl EtapaPipeline {
+    fn new(etapa: &str) -> Self {
+        EtapaPipeline {
+            etapa: etapa.to_string(),
+            oportunidades: Vec::new(),
+            totales: Vec::new(),
+        }
+    }
+
+    // tablero con todas las etapas en orden, también las vacías
+    pub fn agrupar<I>(oportunidades: I) -> Result<Vec<EtapaPipeline>, MoneyError>
+    where
+        I: IntoIterator<Item = Oportunidad>,
+    {
+        let mut pipeline: Vec<EtapaPipeline> = ETAPAS
+            .iter()
+            .map(|etapa| EtapaPipeline::new(etapa))
+            .collect();
+        for oportunidad in oportunidades {
+            if let Some(columna) = pipeline.iter_mut().find(|c| c.etapa == oportunidad.etapa) {
+                columna.push(oportunidad)?;
+            }
+        }
+        Ok(pipeline)
+    }
+
+    fn push(&mut self, oportunidad: Oportunidad) -> Result<(), MoneyError> {
+        let valor = oportunidad.valor_esperado;
+        match self
+            .totales
+            .iter_mut()
+            .find(|total| total.currency() == valor.currency())
+        {
+            Some(total) => *total = total.checked_add(valor)?,
+            None => self.totales.push(valor),
+        }
+        self.oportunidades.push(oportunidad);
+        Ok(())
+    }
+}
+
+const OPORTUNIDAD_COLUMNS: &str = "id, titulo, lead_id, cliente_id, etapa, valor_esperado, moneda, fecha_cierre_prevista, fecha_cierre, propietario_user_id, fecha_creacion, fecha_modificacion";
+
+pub async fn postgres_get_oportunidades(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    lead_id: Option<i32>,
+    cliente_id: Option<i32>,
+    etapa: Option<&str>,
+    propietario_user_id: Option<i32>,
+) -> Result<Vec<Oportunidad>, sqlx::Error> {
+    let oportunidades = sqlx::query_as::<_, Oportunidad>(&format!(
+        "SELECT {}
+        FROM oportunidades
+        WHERE ($1::INT IS NULL OR lead_id = $1)
+        AND ($2::INT IS NULL OR cliente_id = $2)
+        AND ($3::TEXT IS NULL OR etapa = $3)
+        AND ($4::INT IS NULL OR propietario_user_id = $4)
+        ORDER BY fecha_cierre_prevista ASC NULLS LAST, id",
+        OPORTUNIDAD_COLUMNS
+    ))
+    .bind(lead_id)
+    .bind(cliente_id)
+    .bind(etapa)
+    .bind(propietario_user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(oportunidades)
+}
+
+pub async fn postgres_get_oportunidad_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Oportunidad>, sqlx::Error> {
+    let oportunidad = sqlx::query_as::<_, Oportunidad>(&format!(
+        "SELECT {} FROM oportunidades WHERE id = $1",
+        OPORTUNIDAD_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(oportunidad)
+}
+
+// un lead o cliente inexistente falla con foreign key violation
+pub async fn postgres_create_oportunidad(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    propietario_user_id: i32,
+    oportunidad: OportunidadRequest,
+) -> Result<Oportunidad, sqlx::Error> {
+    let new_oportunidad = sqlx::query_as::<_, Oportunidad>(&format!(
+        "
+        INSERT INTO oportunidades (
+            titulo, lead_id, cliente_id, etapa, valor_esperado, moneda, fecha_cierre_prevista,
+            fecha_cierre, propietario_user_id
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, $6, $7, CASE WHEN $8 THEN CURRENT_TIMESTAMP END, $9
+        )
+        RETURNING {}",
+        OPORTUNIDAD_COLUMNS
+    ))
+    .bind(oportunidad.titulo)
+    .bind(oportunidad.lead_id)
+    .bind(oportunidad.cliente_id)
+    .bind(&oportunidad.etapa)
+    .bind(oportunidad.valor_esperado.amount_minor())
+    .bind(oportunidad.valor_esperado.currency().code())
+    .bind(oportunidad.fecha_cierre_prevista)
+    .bind(etapa_cerrada(&oportunidad.etapa))
+    .bind(propietario_user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(new_oportunidad)
+}
+
+// fecha_cierre se fija al entrar en ganada o perdida y se limpia al reabrir
+const FECHA_CIERRE: &str =
+    "CASE WHEN NOT $1 THEN NULL ELSE COALESCE(fecha_cierre, CURRENT_TIMESTAMP) END";
+
+pub async fn postgres_update_oportunidad(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    oportunidad: OportunidadRequest,
+) -> Result<Option<Oportunidad>, sqlx::Error> {
+    let updated_oportunidad = sqlx::query_as::<_, Oportunidad>(&format!(
+        "
+        UPDATE oportunidades
+        SET fecha_cierre = {}, titulo = $2, lead_id = $3, cliente_id = $4, etapa = $5,
+            valor_esperado = $6, moneda = $7, fecha_cierre_prevista = $8,
+            fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $9
+        RETURNING {}",
+        FECHA_CIERRE, OPORTUNIDAD_COLUMNS
+    ))
+    .bind(etapa_cerrada(&oportunidad.etapa))
+    .bind(oportunidad.titulo)
+    .bind(oportunidad.lead_id)
+    .bind(oportunidad.cliente_id)
+    .bind(&oportunidad.etapa)
+    .bind(oportunidad.valor_esperado.amount_minor())
+    .bind(oportunidad.valor_esperado.currency().code())
+    .bind(oportunidad.fecha_cierre_prevista)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_oportunidad)
+}
+
+pub async fn postgres_update_oportunidad_etapa(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    etapa: &str,
+) -> Result<Option<Oportunidad>, sqlx::Error> {
+    let updated_oportunidad = sqlx::query_as::<_, Oportunidad>(&format!(
+        "
+        UPDATE oportunidades
+        SET fecha_cierre = {}, etapa = $2, fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $3
+        RETURNING {}",
+        FECHA_CIERRE, OPORTUNIDAD_COLUMNS
+    ))
+    .bind(etapa_cerrada(etapa))
+    .bind(etapa)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_oportunidad)
+}
@@ -0,0 +1,80 @@
+// Página pedida en un listado; pagina empieza en 1. Solo se construye si el
+// desplazamiento cabe en i64, así que offset() no puede desbordar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Paginacion {
+    pagina: i64,
+    por_pagina: i64,
+    offset: i64,
+}
+
+impl Paginacion {
+    // None si pagina < 1, por_pagina está fuera de 1..=maximo o la página es tan
+    // alta que el desplazamiento no cabe en i64
+    pub fn new(
+        pagina: Option<i64>,
+        por_pagina: Option<i64>,
+        por_defecto: i64,
+        maximo: i64,
+    ) -> Option<Self> {
+        let pagina = pagina.unwrap_or(1);
+        let por_pagina = por_pagina.unwrap_or(por_defecto);
+        if pagina < 1 || !(1..=maximo).contains(&por_pagina) {
+            return None;
+        }
+        let offset = pagina.checked_sub(1)?.checked_mul(por_pagina)?;
+        Some(Paginacion {
+            pagina,
+            por_pagina,
+            offset,
+        })
+    }
+
+    pub fn pagina(&self) -> i64 {
+        self.pagina
+    }
+
+    pub fn por_pagina(&self) -> i64 {
+        self.por_pagina
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valores_por_defecto() {
+        let paginacion = Paginacion::new(None, None, 50, 200).unwrap();
+        assert_eq!(paginacion.pagina(), 1);
+        assert_eq!(paginacion.por_pagina(), 50);
+        assert_eq!(paginacion.offset(), 0);
+    }
+
+    #[test]
+    fn offset_de_la_pagina() {
+        let paginacion = Paginacion::new(Some(3), Some(20), 50, 200).unwrap();
+        assert_eq!(paginacion.offset(), 40);
+    }
+
+    #[test]
+    fn rechaza_valores_fuera_de_rango() {
+        assert_eq!(Paginacion::new(Some(0), None, 50, 200), None);
+        assert_eq!(Paginacion::new(Some(-1), None, 50, 200), None);
+        assert_eq!(Paginacion::new(None, Some(0), 50, 200), None);
+        assert_eq!(Paginacion::new(None, Some(201), 50, 200), None);
+    }
+
+    #[test]
+    fn rechaza_desplazamientos_que_desbordan() {
+        assert_eq!(Paginacion::new(Some(i64::MAX), Some(50), 50, 200), None);
+        assert_eq!(
+            Paginacion::new(Some(i64::MAX / 2 + 2), Some(2), 50, 200),
+            None
+        );
+        assert!(Paginacion::new(Some(i64::MAX / 200), Some(200), 50, 200).is_some());
+    }
+}
@@ -0,0 +1,287 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::{Validate, ValidationError};
+
+use crate::facturas::{TIPO_ORDINARIA, TIPO_RECTIFICATIVA};
+use crate::money::{Money, MoneyError, money_from_row};
+use crate::pedidos::ESTADO_CANCELADO;
+
+pub const METODOS_PAGO: [&str; 4] = ["transferencia", "tarjeta", "efectivo", "domiciliacion"];
+
+// días desde la factura (o desde el pedido si no está facturado) hasta el vencimiento
+pub const DIAS_VENCIMIENTO: i32 = 30;
+
+pub fn validate_metodo_pago(metodo: &str) -> Result<(), ValidationError> {
+    if !METODOS_PAGO.contains(&metodo) {
+        return Err(ValidationError::new("metodo")
+            .with_message("debe ser transferencia, tarjeta, efectivo o domiciliacion".into()));
+    }
+    Ok(())
+}
+
+pub fn validate_importe_positivo(importe: &Money) -> Result<(), ValidationError> {
+    if importe.amount_minor() <= 0 {
+        return Err(ValidationError::new("range").with_message("debe ser mayor que cero".into()));
+    }
+    Ok(())
+}
+
+// el pago se imputa a un pedido o a una de sus facturas, nunca a ambos
+fn validate_pago(pago: &PagoRequest) -> Result<(), ValidationError> {
+    if pago.pedido_id.is_some() == pago.factura_id.is_some() {
+        return Err(ValidationError::new("destino")
+            .with_message("indica pedido_id o factura_id, solo uno de los dos".into()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+#[validate(schema(function = "validate_pago"))]
+pub struct PagoRequest {
+    pub pedido_id: Option<i32>,
+    pub factura_id: Option<i32>,
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_metodo_pago"))]
+    pub metodo: String,
+    #[validate(custom(function = "validate_importe_positivo"))]
+    pub importe: Money,
+    // por defecto la fecha de hoy
+    pub fecha: Option<chrono::NaiveDate>,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 100, message = "máximo 100 caracteres"))]
+    pub referencia: Option<String>,
+}
+
+// pedido_id siempre informado; factura_id solo si el pago se imputó a la factura
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Pago {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub pedido_id: i32,
+    pub factura_id: Option<i32>,
+    pub metodo: String,
+    pub importe: Money,
+    pub fecha: chrono::NaiveDate,
+    pub referencia: Option<String>,
+    pub fecha_creacion: chrono::NaiveDateTime,
+}
+
+impl<'r> FromRow<'r, PgRow> for Pago {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Pago {
+            id: row.try_get("id")?,
+            cliente_id: row.try_get("cliente_id")?,
+            pedido_id: row.try_get("pedido_id")?,
+            factura_id: row.try_get("factura_id")?,
+            metodo: row.try_get("metodo")?,
+            importe: money_from_row(row, "importe", "moneda")?,
+            fecha: row.try_get("fecha")?,
+            referencia: row.try_get("referencia")?,
+            fecha_creacion: row.try_get("fecha_creacion")?,
+        })
+    }
+}
+
+// saldo del cliente en una moneda: todo lo pendiente y la parte ya vencida
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Saldo {
+    pub pendiente: Money,
+    pub vencido: Money,
+}
+
+impl<'r> FromRow<'r, PgRow> for Saldo {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Saldo {
+            pendiente: money_from_row(row, "pendiente", "moneda")?,
+            vencido: money_from_row(row, "vencido", "moneda")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PagoError {
+    Sqlx(sqlx::Error),
+    Money(MoneyError),
+    PedidoNoEncontrado(i32),
+    FacturaNoEncontrada(i32),
+    PedidoCancelado(i32),
+    FacturaRectificativa(i32),
+    ImporteExcedePendiente(Money),
+}
+
+impl fmt::Display for PagoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PagoError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            PagoError::Money(e) => write!(f, "{}", e),
+            PagoError::PedidoNoEncontrado(id) => write!(f, "pedido {} no encontrado", id),
+            PagoError::FacturaNoEncontrada(id) => write!(f, "factura {} no encontrada", id),
+            PagoError::PedidoCancelado(id) => write!(f, "el pedido {} está cancelado", id),
+            PagoError::FacturaRectificativa(id) => {
+                write!(f, "la factura {} es rectificativa y no admite pagos", id)
+            }
+            PagoError::ImporteExcedePendiente(pendiente) => write!(
+                f,
+                "el importe supera lo pendiente ({} {})",
+                pendiente.to_decimal_string(),
+                pendiente.currency()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PagoError {}
+
+impl From<sqlx::Error> for PagoError {
+    fn from(e: sqlx::Error) -> Self {
+        PagoError::Sqlx(e)
+    }
+}
+
+impl From<MoneyError> for PagoError {
+    fn from(e: MoneyError) -> Self {
+        PagoError::Money(e)
+    }
+}
+
+const PAGO_COLUMNS: &str = "id, cliente_id, pedido_id, factura_id, metodo, importe, moneda, fecha, referencia, fecha_creacion";
+
+// Lo pendiente de un pedido es su total, menos lo que restan sus facturas
+// rectificativas, menos los pagos imputados al pedido o a sus facturas.
+const PENDIENTE_PEDIDO: &str = "
+    p.total
+    + COALESCE((
+        SELECT SUM(f.total) FROM facturas f
+        WHERE f.pedido_id = p.id AND f.tipo = 'rectificativa'
+    ), 0)
+    - COALESCE((SELECT SUM(pg.importe) FROM pagos pg WHERE pg.pedido_id = p.id), 0)";
+
+// Registra un pago bloqueando el pedido para que dos pagos simultáneos no
+// superen entre ambos lo pendiente.
+pub async fn postgres_create_pago(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    pago: PagoRequest,
+) -> Result<Pago, PagoError> {
+    let mut tx = pool.begin().await?;
+
+    let pedido_id = match (pago.pedido_id, pago.factura_id) {
+        (_, Some(factura_id)) => {
+            let factura = sqlx::query("SELECT pedido_id, tipo FROM facturas WHERE id = $1")
+                .bind(factura_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or(PagoError::FacturaNoEncontrada(factura_id))?;
+            let tipo: String = factura.try_get("tipo")?;
+            if tipo == TIPO_RECTIFICATIVA {
+                return Err(PagoError::FacturaRectificativa(factura_id));
+            }
+            factura.try_get::<i32, _>("pedido_id")?
+        }
+        (Some(pedido_id), None) => pedido_id,
+        (None, None) => unreachable!("validado en PagoRequest"),
+    };
+
+    let pedido = sqlx::query("SELECT cliente_id, estado FROM pedidos WHERE id = $1 FOR UPDATE")
+        .bind(pedido_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(PagoError::PedidoNoEncontrado(pedido_id))?;
+    let estado: String = pedido.try_get("estado")?;
+    if estado == ESTADO_CANCELADO {
+        return Err(PagoError::PedidoCancelado(pedido_id));
+    }
+
+    let row = sqlx::query(&format!(
+        "SELECT ({})::BIGINT AS pendiente, p.moneda FROM pedidos p WHERE p.id = $1",
+        PENDIENTE_PEDIDO
+    ))
+    .bind(pedido_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    let pendiente = money_from_row(&row, "pendiente", "moneda")?;
+    let restante = pendiente.checked_sub(pago.importe)?;
+    if restante.is_negative() {
+        return Err(PagoError::ImporteExcedePendiente(pendiente));
+    }
+
+    let new_pago = sqlx::query_as::<_, Pago>(&format!(
+        "
+        INSERT INTO pagos (
+            cliente_id, pedido_id, factura_id, metodo, importe, moneda, fecha, referencia
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, COALESCE($7, CURRENT_DATE), $8)
+        RETURNING {}",
+        PAGO_COLUMNS
+    ))
+    .bind(pedido.try_get::<i32, _>("cliente_id")?)
+    .bind(pedido_id)
+    .bind(pago.factura_id)
+    .bind(pago.metodo)
+    .bind(pago.importe.amount_minor())
+    .bind(pago.importe.currency().code())
+    .bind(pago.fecha)
+    .bind(pago.referencia)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(new_pago)
+}
+
+pub async fn postgres_get_pagos_by_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Pago>, sqlx::Error> {
+    let pagos = sqlx::query_as::<_, Pago>(&format!(
+        "SELECT {} FROM pagos WHERE cliente_id = $1 ORDER BY fecha DESC, id DESC",
+        PAGO_COLUMNS
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pagos)
+}
+
+// saldo por moneda de los pedidos no cancelados del cliente; un saldo a favor
+// del cliente (pendiente negativo) no compensa lo vencido
+pub async fn postgres_get_saldo_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<Saldo>, sqlx::Error> {
+    let saldo = sqlx::query_as::<_, Saldo>(&format!(
+        "
+        WITH deuda AS (
+            SELECT
+                p.moneda,
+                {} AS pendiente,
+                COALESCE((
+                    SELECT MIN(f.fecha_emision)::DATE FROM facturas f
+                    WHERE f.pedido_id = p.id AND f.tipo = $2
+                ), p.fecha_pedido::DATE) + $3::INT AS vencimiento
+            FROM pedidos p
+            WHERE p.cliente_id = $1 AND p.estado <> $4
+        )
+        SELECT
+            moneda,
+            SUM(pendiente)::BIGINT AS pendiente,
+            SUM(CASE WHEN vencimiento < CURRENT_DATE THEN GREATEST(pendiente, 0) ELSE 0 END)::BIGINT
+                AS vencido
+        FROM deuda
+        GROUP BY moneda
+        ORDER BY moneda",
+        PENDIENTE_PEDIDO
+    ))
+    .bind(cliente_id)
+    .bind(TIPO_ORDINARIA)
+    .bind(DIAS_VENCIMIENTO)
+    .bind(ESTADO_CANCELADO)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(saldo)
+}
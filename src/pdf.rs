@@ -0,0 +1,434 @@
+use std::env;
+
+use printpdf::{
+    BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference,
+    Point,
+};
+
+use crate::clientes::Cliente;
+use crate::facturas::{Factura, FacturaLinea, TIPO_RECTIFICATIVA};
+use crate::iva::DesgloseIva;
+use crate::money::{Currency, Money};
+use crate::pedidos::{Pedido, PedidoDetalle};
+
+// Documentos imprimibles (facturas y confirmaciones de pedido) generados con
+// printpdf y las fuentes estándar de PDF, sin servicios externos.
+
+const ANCHO_PAGINA: f32 = 210.0;
+const ALTO_PAGINA: f32 = 297.0;
+const MARGEN: f32 = 15.0;
+const ALTO_FILA: f32 = 6.0;
+
+// columnas de la tabla de líneas, las numéricas alineadas a la derecha
+const COL_DESCRIPCION: f32 = MARGEN;
+const COL_CANTIDAD: f32 = 115.0;
+const COL_PRECIO: f32 = 145.0;
+const COL_IVA: f32 = 162.0;
+const COL_IMPORTE: f32 = ANCHO_PAGINA - MARGEN;
+
+// datos de la empresa para la cabecera, configurables por entorno
+pub struct Empresa {
+    pub nombre: String,
+    pub nif: String,
+    pub direccion: String,
+    pub email: String,
+}
+
+impl Empresa {
+    pub fn from_env() -> Self {
+        let var =
+            |name: &str, default: &str| env::var(name).unwrap_or_else(|_| default.to_string());
+        Empresa {
+            nombre: var("PDF_EMPRESA_NOMBRE", "Dummy CRM S.L."),
+            nif: var("PDF_EMPRESA_NIF", ""),
+            direccion: var("PDF_EMPRESA_DIRECCION", ""),
+            email: var("PDF_EMPRESA_EMAIL", ""),
+        }
+    }
+}
+
+pub struct ClienteDocumento {
+    pub nombre: String,
+    pub email: Option<String>,
+    pub telefono: Option<String>,
+    pub direccion: Option<String>,
+}
+
+pub struct LineaDocumento {
+    pub descripcion: String,
+    pub cantidad: i32,
+    pub precio_unitario: Money,
+    pub porcentaje_iva: i32,
+    pub subtotal: Money,
+}
+
+pub struct Documento {
+    pub titulo: String,
+    pub codigo: String,
+    pub fecha: chrono::NaiveDateTime,
+    pub referencia: Option<String>,
+    pub cliente: ClienteDocumento,
+    pub lineas: Vec<LineaDocumento>,
+    pub desglose_iva: Vec<DesgloseIva>,
+    pub base_imponible: Money,
+    pub cuota_iva: Money,
+    pub total: Money,
+}
+
+pub fn documento_factura(
+    factura: &Factura,
+    lineas: &[FacturaLinea],
+    desglose_iva: Vec<DesgloseIva>,
+    codigo_rectificada: Option<&str>,
+) -> Documento {
+    let (titulo, referencia) = if factura.tipo == TIPO_RECTIFICATIVA {
+        let referencia = format!(
+            "Rectifica {}: {}",
+            codigo_rectificada.unwrap_or_default(),
+            factura.motivo.clone().unwrap_or_default()
+        );
+        ("Factura rectificativa", referencia)
+    } else {
+        ("Factura", format!("Pedido nº {}", factura.pedido_id))
+    };
+    Documento {
+        titulo: titulo.to_string(),
+        codigo: factura.codigo.clone(),
+        fecha: factura.fecha_emision,
+        referencia: Some(referencia),
+        cliente: ClienteDocumento {
+            nombre: factura.cliente_nombre.clone(),
+            email: factura.cliente_email.clone(),
+            telefono: factura.cliente_telefono.clone(),
+            direccion: factura.cliente_direccion.clone(),
+        },
+        lineas: lineas
+            .iter()
+            .map(|l| LineaDocumento {
+                descripcion: descripcion_linea(&l.descripcion, l.descuento),
+                cantidad: l.cantidad,
+                precio_unitario: l.precio_unitario,
+                porcentaje_iva: l.porcentaje_iva,
+                subtotal: l.subtotal,
+            })
+            .collect(),
+        desglose_iva,
+        base_imponible: factura.base_imponible,
+        cuota_iva: factura.cuota_iva,
+        total: factura.total,
+    }
+}
+
+pub fn documento_pedido(
+    pedido: &Pedido,
+    detalles: &[PedidoDetalle],
+    desglose_iva: Vec<DesgloseIva>,
+    cliente: &Cliente,
+) -> Documento {
+    Documento {
+        titulo: "Confirmación de pedido".to_string(),
+        codigo: format!("Pedido nº {}", pedido.id),
+        fecha: pedido.fecha_pedido,
+        referencia: Some(format!("Estado: {}", pedido.estado)),
+        cliente: ClienteDocumento {
+            nombre: cliente.nombre.clone(),
+            email: Some(cliente.email.clone()),
+            telefono: cliente.telefono.clone(),
+            // la dirección copiada en el pedido, no la actual del cliente
+            direccion: pedido.direccion_envio.clone(),
+        },
+        lineas: detalles
+            .iter()
+            .map(|d| LineaDocumento {
+                descripcion: descripcion_linea(&d.articulo_nombre, d.descuento),
+                cantidad: d.cantidad,
+                precio_unitario: d.precio_unitario,
+                porcentaje_iva: d.porcentaje_iva,
+                subtotal: d.subtotal,
+            })
+            .collect(),
+        desglose_iva,
+        base_imponible: pedido.base_imponible,
+        cuota_iva: pedido.cuota_iva,
+        total: pedido.total,
+    }
+}
+
+struct Fuentes {
+    normal: IndirectFontRef,
+    negrita: IndirectFontRef,
+}
+
+// va escribiendo de arriba abajo y abre página nueva cuando no cabe más
+struct Escritor {
+    doc: PdfDocumentReference,
+    capa: PdfLayerReference,
+    fuentes: Fuentes,
+    y: f32,
+    paginas: usize,
+}
+
+impl Escritor {
+    fn texto(&self, texto: &str, size: f32, x: f32, negrita: bool) {
+        let fuente = if negrita {
+            &self.fuentes.negrita
+        } else {
+            &self.fuentes.normal
+        };
+        self.capa.use_text(texto, size, Mm(x), Mm(self.y), fuente);
+    }
+
+    fn texto_derecha(&self, texto: &str, size: f32, x: f32, negrita: bool) {
+        self.texto(texto, size, x - ancho_texto(texto, size), negrita);
+    }
+
+    fn linea_horizontal(&self) {
+        let y = self.y + 1.5;
+        self.capa.add_line(Line {
+            points: vec![
+                (Point::new(Mm(MARGEN), Mm(y)), false),
+                (Point::new(Mm(ANCHO_PAGINA - MARGEN), Mm(y)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    fn salto(&mut self, alto: f32) {
+        self.y -= alto;
+    }
+
+    // true si se ha abierto una página nueva
+    fn asegurar_espacio(&mut self, alto: f32) -> bool {
+        if self.y - alto >= MARGEN + 10.0 {
+            return false;
+        }
+        self.paginas += 1;
+        let (pagina, capa) = self.doc.add_page(
+            Mm(ANCHO_PAGINA),
+            Mm(ALTO_PAGINA),
+            format!("Página {}", self.paginas),
+        );
+        self.capa = self.doc.get_page(pagina).get_layer(capa);
+        self.y = ALTO_PAGINA - MARGEN - 5.0;
+        true
+    }
+}
+
+pub fn render(empresa: &Empresa, documento: &Documento) -> Result<Vec<u8>, printpdf::Error> {
+    let (doc, pagina, capa) = PdfDocument::new(
+        format!("{} {}", documento.titulo, documento.codigo),
+        Mm(ANCHO_PAGINA),
+        Mm(ALTO_PAGINA),
+        "Página 1",
+    );
+    let fuentes = Fuentes {
+        normal: doc.add_builtin_font(BuiltinFont::Helvetica)?,
+        negrita: doc.add_builtin_font(BuiltinFont::HelveticaBold)?,
+    };
+    let capa = doc.get_page(pagina).get_layer(capa);
+    let mut w = Escritor {
+        doc,
+        capa,
+        fuentes,
+        y: ALTO_PAGINA - MARGEN - 5.0,
+        paginas: 1,
+    };
+
+    // cabecera: empresa a la izquierda, documento a la derecha
+    w.texto(&empresa.nombre, 16.0, MARGEN, true);
+    w.texto_derecha(&documento.titulo, 16.0, ANCHO_PAGINA - MARGEN, true);
+    w.salto(7.0);
+    let y_datos = w.y;
+    for dato in [&empresa.nif, &empresa.direccion, &empresa.email] {
+        if !dato.is_empty() {
+            w.texto(dato, 9.0, MARGEN, false);
+            w.salto(4.5);
+        }
+    }
+    let y_fin_empresa = w.y;
+    w.y = y_datos;
+    w.texto_derecha(&documento.codigo, 11.0, ANCHO_PAGINA - MARGEN, true);
+    w.salto(5.0);
+    let fecha = format!("Fecha: {}", documento.fecha.format("%d/%m/%Y"));
+    w.texto_derecha(&fecha, 9.0, ANCHO_PAGINA - MARGEN, false);
+    if let Some(referencia) = &documento.referencia {
+        w.salto(4.5);
+        w.texto_derecha(referencia, 9.0, ANCHO_PAGINA - MARGEN, false);
+    }
+    w.y = w.y.min(y_fin_empresa);
+    w.salto(10.0);
+
+    // datos del cliente
+    w.texto("Cliente", 10.0, MARGEN, true);
+    w.salto(5.0);
+    let cliente = &documento.cliente;
+    w.texto(&cliente.nombre, 10.0, MARGEN, false);
+    for dato in [&cliente.direccion, &cliente.email, &cliente.telefono]
+        .into_iter()
+        .flatten()
+    {
+        w.salto(4.5);
+        w.texto(dato, 9.0, MARGEN, false);
+    }
+    w.salto(12.0);
+
+    // tabla de líneas
+    cabecera_tabla(&w);
+    w.salto(ALTO_FILA);
+    for linea in &documento.lineas {
+        if w.asegurar_espacio(ALTO_FILA) {
+            cabecera_tabla(&w);
+            w.salto(ALTO_FILA);
+        }
+        w.texto(
+            &recortar(&linea.descripcion, 55),
+            9.0,
+            COL_DESCRIPCION,
+            false,
+        );
+        w.texto_derecha(&linea.cantidad.to_string(), 9.0, COL_CANTIDAD, false);
+        w.texto_derecha(
+            &formato_importe(linea.precio_unitario),
+            9.0,
+            COL_PRECIO,
+            false,
+        );
+        w.texto_derecha(
+            &formato_porcentaje(linea.porcentaje_iva),
+            9.0,
+            COL_IVA,
+            false,
+        );
+        w.texto_derecha(&formato_importe(linea.subtotal), 9.0, COL_IMPORTE, false);
+        w.salto(ALTO_FILA);
+    }
+    w.linea_horizontal();
+    w.salto(6.0);
+
+    // desglose de IVA por tipo
+    w.asegurar_espacio(ALTO_FILA * (documento.desglose_iva.len() as f32 + 2.0));
+    w.texto("Desglose de IVA", 10.0, MARGEN, true);
+    w.salto(ALTO_FILA);
+    w.texto("Tipo", 9.0, MARGEN, true);
+    w.texto_derecha("Base", 9.0, COL_PRECIO, true);
+    w.texto_derecha("Cuota", 9.0, COL_IVA + 15.0, true);
+    w.texto_derecha("Total", 9.0, COL_IMPORTE, true);
+    w.salto(ALTO_FILA);
+    for desglose in &documento.desglose_iva {
+        w.texto(
+            &format!(
+                "{} ({})",
+                desglose.tipo_iva,
+                formato_porcentaje(desglose.porcentaje_iva)
+            ),
+            9.0,
+            MARGEN,
+            false,
+        );
+        w.texto_derecha(&formato_importe(desglose.base), 9.0, COL_PRECIO, false);
+        w.texto_derecha(&formato_importe(desglose.cuota), 9.0, COL_IVA + 15.0, false);
+        w.texto_derecha(&formato_importe(desglose.total), 9.0, COL_IMPORTE, false);
+        w.salto(ALTO_FILA);
+    }
+    w.salto(4.0);
+
+    // totales
+    w.asegurar_espacio(ALTO_FILA * 3.0);
+    for (etiqueta, importe, negrita) in [
+        ("Base imponible", documento.base_imponible, false),
+        ("IVA", documento.cuota_iva, false),
+        ("Total", documento.total, true),
+    ] {
+        w.texto_derecha(etiqueta, 10.0, COL_IVA, negrita);
+        w.texto_derecha(&formato_importe(importe), 10.0, COL_IMPORTE, negrita);
+        w.salto(ALTO_FILA);
+    }
+
+    w.doc.save_to_bytes()
+}
+
+fn cabecera_tabla(w: &Escritor) {
+    w.texto("Descripción", 9.0, COL_DESCRIPCION, true);
+    w.texto_derecha("Cant.", 9.0, COL_CANTIDAD, true);
+    w.texto_derecha("Precio", 9.0, COL_PRECIO, true);
+    w.texto_derecha("IVA", 9.0, COL_IVA, true);
+    w.texto_derecha("Importe", 9.0, COL_IMPORTE, true);
+    w.linea_horizontal();
+}
+
+// "1.200,00 €" con separador de miles y coma decimal
+pub fn formato_importe(importe: Money) -> String {
+    let decimal = importe.to_decimal_string();
+    let (signo, decimal) = match decimal.strip_prefix('-') {
+        Some(resto) => ("-", resto.to_string()),
+        None => ("", decimal),
+    };
+    let (enteros, decimales) = decimal.split_once('.').unwrap_or((&decimal, ""));
+    let mut miles = String::new();
+    for (i, c) in enteros.chars().enumerate() {
+        if i > 0 && (enteros.len() - i) % 3 == 0 {
+            miles.push('.');
+        }
+        miles.push(c);
+    }
+    let simbolo = match importe.currency() {
+        Currency::Eur => "€",
+        Currency::Usd => "$",
+        Currency::Gbp => "£",
+    };
+    if decimales.is_empty() {
+        format!("{}{} {}", signo, miles, simbolo)
+    } else {
+        format!("{}{},{} {}", signo, miles, decimales, simbolo)
+    }
+}
+
+// el importe de la línea ya lleva el descuento aplicado, se indica junto al artículo
+fn descripcion_linea(descripcion: &str, descuento: i32) -> String {
+    if descuento == 0 {
+        return descripcion.to_string();
+    }
+    format!("{} (dto. {})", descripcion, formato_porcentaje(descuento))
+}
+
+// 2100 puntos básicos -> "21%", 550 -> "5,5%"
+fn formato_porcentaje(puntos_basicos: i32) -> String {
+    let enteros = puntos_basicos / 100;
+    let decimales = puntos_basicos % 100;
+    if decimales == 0 {
+        format!("{}%", enteros)
+    } else {
+        format!(
+            "{},{}%",
+            enteros,
+            format!("{:02}", decimales).trim_end_matches('0')
+        )
+    }
+}
+
+fn recortar(texto: &str, max: usize) -> String {
+    if texto.chars().count() <= max {
+        return texto.to_string();
+    }
+    let recortado: String = texto.chars().take(max - 3).collect();
+    format!("{}...", recortado)
+}
+
+// ancho aproximado en mm con las métricas de Helvetica (en milésimas de em)
+fn ancho_texto(texto: &str, size: f32) -> f32 {
+    let unidades: u32 = texto
+        .chars()
+        .map(|c| match c {
+            '0'..='9' | '€' | '$' | '£' => 556,
+            '.' | ',' | ' ' | ':' | '/' => 278,
+            '-' | '(' | ')' => 333,
+            '%' => 889,
+            'I' | 'i' | 'l' | 'j' | 't' | 'f' => 278,
+            'm' | 'M' | 'W' => 833,
+            'w' => 722,
+            c if c.is_uppercase() => 667,
+            _ => 556,
+        })
+        .sum();
+    unidades as f32 / 1000.0 * size * 0.3528
+}
@@ -0,0 +1,497 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::Validate;
+
+use crate::direcciones;
+use crate::iva::DesgloseIva;
+use crate::money::{Money, MoneyError, money_from_row};
+use crate::precios::{self, PrecioAplicado};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PedidoLineaRequest {
+    pub articulo_id: i32,
+    #[validate(range(min = 1, message = "debe ser al menos 1"))]
+    pub cantidad: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PedidoRequest {
+    pub cliente_id: i32,
+    // por defecto la dirección de envío predeterminada del cliente
+    #[serde(default)]
+    pub direccion_envio_id: Option<i32>,
+    #[validate(
+        length(min = 1, message = "el pedido necesita al menos una línea"),
+        nested
+    )]
+    pub lineas: Vec<PedidoLineaRequest>,
+}
+
+pub const ESTADO_PENDIENTE: &str = "Pendiente";
+pub const ESTADO_ENVIADO: &str = "Enviado";
+pub const ESTADO_ENTREGADO: &str = "Entregado";
+pub const ESTADO_CANCELADO: &str = "Cancelado";
+
+// transiciones permitidas: Pendiente -> Enviado -> Entregado, y cancelar antes de entregar
+pub fn transicion_valida(desde: &str, hasta: &str) -> bool {
+    matches!(
+        (desde, hasta),
+        (ESTADO_PENDIENTE, ESTADO_ENVIADO)
+            | (ESTADO_PENDIENTE, ESTADO_CANCELADO)
+            | (ESTADO_ENVIADO, ESTADO_ENTREGADO)
+            | (ESTADO_ENVIADO, ESTADO_CANCELADO)
+    )
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PedidoEstadoRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 50, message = "entre 1 y 50 caracteres"))]
+    pub estado: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Pedido {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub fecha_pedido: chrono::NaiveDateTime,
+    pub estado: String,
+    pub base_imponible: Money,
+    pub cuota_iva: Money,
+    pub total: Money,
+    pub direccion_envio_id: Option<i32>,
+    // copia de la dirección al hacer el pedido; no cambia si se edita o borra la dirección
+    pub direccion_envio: Option<String>,
+}
+
+impl<'r> FromRow<'r, PgRow> for Pedido {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Pedido {
+            id: row.try_get("id")?,
+            cliente_id: row.try_get("cliente_id")?,
+            fecha_pedido: row.try_get("fecha_pedido")?,
+            estado: row.try_get("estado")?,
+            base_imponible: money_from_row(row, "base_imponible", "moneda")?,
+            cuota_iva: money_from_row(row, "cuota_iva", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+            direccion_envio_id: row.try_get("direccion_envio_id")?,
+            direccion_envio: row.try_get("direccion_envio")?,
+        })
+    }
+}
+
+// subtotal es la base imponible de la línea (cantidad * precio_unitario menos
+// el descuento, en puntos básicos)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PedidoDetalle {
+    pub id: i32,
+    pub pedido_id: i32,
+    pub articulo_id: i32,
+    pub articulo_nombre: String,
+    pub cantidad: i32,
+    pub precio_unitario: Money,
+    pub regla_precio: String,
+    pub regla_id: Option<i32>,
+    pub descuento: i32,
+    pub subtotal: Money,
+    pub tipo_iva: String,
+    pub porcentaje_iva: i32,
+    pub cuota_iva: Money,
+    pub total: Money,
+}
+
+impl PedidoDetalle {
+    pub fn desglose_iva(&self) -> DesgloseIva {
+        DesgloseIva {
+            tipo_iva: self.tipo_iva.clone(),
+            porcentaje_iva: self.porcentaje_iva,
+            base: self.subtotal,
+            cuota: self.cuota_iva,
+            total: self.total,
+        }
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for PedidoDetalle {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(PedidoDetalle {
+            id: row.try_get("id")?,
+            pedido_id: row.try_get("pedido_id")?,
+            articulo_id: row.try_get("articulo_id")?,
+            articulo_nombre: row.try_get("articulo_nombre")?,
+            cantidad: row.try_get("cantidad")?,
+            precio_unitario: money_from_row(row, "precio_unitario", "moneda")?,
+            regla_precio: row.try_get("regla_precio")?,
+            regla_id: row.try_get("regla_id")?,
+            descuento: row.try_get("descuento")?,
+            subtotal: money_from_row(row, "subtotal", "moneda")?,
+            tipo_iva: row.try_get("tipo_iva")?,
+            porcentaje_iva: row.try_get("porcentaje_iva")?,
+            cuota_iva: money_from_row(row, "cuota_iva", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+        })
+    }
+}
+
+// línea ya calculada lista para guardar en pedidos_detalles
+#[derive(Clone, Debug)]
+pub struct LineaPedido {
+    pub articulo_id: i32,
+    pub cantidad: i32,
+    pub precio: PrecioAplicado,
+    pub descuento: i32,
+    pub desglose: DesgloseIva,
+}
+
+impl LineaPedido {
+    // base = cantidad * precio_unitario menos el descuento redondeado
+    pub fn calcular(
+        articulo_id: i32,
+        cantidad: i32,
+        precio: PrecioAplicado,
+        descuento: i32,
+        tipo_iva: &str,
+        porcentaje_iva: i32,
+    ) -> Result<Self, MoneyError> {
+        let bruto = precio.precio_unitario.checked_mul(cantidad as i64)?;
+        let base = bruto.checked_sub(bruto.percentage(descuento)?)?;
+        Ok(LineaPedido {
+            articulo_id,
+            cantidad,
+            precio,
+            descuento,
+            desglose: DesgloseIva::calcular(tipo_iva, porcentaje_iva, base)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PedidoError {
+    Sqlx(sqlx::Error),
+    Money(MoneyError),
+    ClienteNoEncontrado(i32),
+    ArticuloNoEncontrado(i32),
+    StockInsuficiente(i32),
+    PedidoNoEncontrado(i32),
+    TransicionNoValida(String, String),
+    DireccionNoValida(i32),
+}
+
+impl fmt::Display for PedidoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PedidoError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            PedidoError::Money(e) => write!(f, "{}", e),
+            PedidoError::ClienteNoEncontrado(id) => write!(f, "cliente {} no encontrado", id),
+            PedidoError::ArticuloNoEncontrado(id) => write!(f, "artículo {} no encontrado", id),
+            PedidoError::StockInsuficiente(id) => {
+                write!(f, "stock insuficiente del artículo {}", id)
+            }
+            PedidoError::PedidoNoEncontrado(id) => write!(f, "pedido {} no encontrado", id),
+            PedidoError::TransicionNoValida(desde, hasta) => {
+                write!(f, "no se puede pasar de {} a {}", desde, hasta)
+            }
+            PedidoError::DireccionNoValida(id) => {
+                write!(f, "la dirección {} no es de envío de este cliente", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PedidoError {}
+
+impl From<sqlx::Error> for PedidoError {
+    fn from(e: sqlx::Error) -> Self {
+        PedidoError::Sqlx(e)
+    }
+}
+
+impl From<MoneyError> for PedidoError {
+    fn from(e: MoneyError) -> Self {
+        PedidoError::Money(e)
+    }
+}
+
+const PEDIDO_COLUMNS: &str = "id, cliente_id, fecha_pedido, estado, base_imponible, cuota_iva, total, moneda, direccion_envio_id, direccion_envio";
+
+pub async fn postgres_get_pedido_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Pedido>, sqlx::Error> {
+    let pedido = sqlx::query_as::<_, Pedido>(&format!(
+        "SELECT {} FROM pedidos WHERE id = $1",
+        PEDIDO_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(pedido)
+}
+
+pub async fn postgres_get_pedido_detalles(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    pedido_id: i32,
+) -> Result<Vec<PedidoDetalle>, sqlx::Error> {
+    let detalles = sqlx::query_as::<_, PedidoDetalle>(
+        "
+        SELECT
+            pd.id, pd.pedido_id, pd.articulo_id, a.nombre AS articulo_nombre, pd.cantidad,
+            pd.precio_unitario, pd.regla_precio, pd.regla_id, pd.descuento, pd.subtotal, pd.tipo_iva, pd.porcentaje_iva, pd.cuota_iva,
+            pd.total, p.moneda
+        FROM pedidos_detalles pd
+        JOIN pedidos p ON p.id = pd.pedido_id
+        JOIN articulos a ON a.id = pd.articulo_id
+        WHERE pd.pedido_id = $1
+        ORDER BY pd.id",
+    )
+    .bind(pedido_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(detalles)
+}
+
+// Crea el pedido en una transacción: toma el mejor precio para el cliente y el
+// tipo de IVA vigente de cada artículo, descuenta stock y guarda en cada línea
+// la regla de precio aplicada, base, tipo, cuota y total.
+pub async fn postgres_create_pedido(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    pedido: PedidoRequest,
+) -> Result<Pedido, PedidoError> {
+    let mut tx = pool.begin().await?;
+
+    let cliente_exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM clientes WHERE id = $1 AND deleted_at IS NULL)",
+    )
+    .bind(pedido.cliente_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !cliente_exists {
+        return Err(PedidoError::ClienteNoEncontrado(pedido.cliente_id));
+    }
+    let direccion_envio =
+        direccion_envio(&mut tx, pedido.cliente_id, pedido.direccion_envio_id).await?;
+
+    let mut lineas = Vec::new();
+    for linea in &pedido.lineas {
+        let row = sqlx::query(
+            "
+            SELECT
+                a.precio, a.moneda, a.tipo_iva, t.porcentaje AS porcentaje_iva
+            FROM articulos a
+            JOIN tipos_iva t ON t.codigo = a.tipo_iva
+            WHERE a.id = $1 AND a.deleted_at IS NULL",
+        )
+        .bind(linea.articulo_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(PedidoError::ArticuloNoEncontrado(linea.articulo_id))?;
+
+        descontar_stock(&mut tx, linea.articulo_id, linea.cantidad).await?;
+
+        let precio = precios::mejor_precio(
+            &mut tx,
+            pedido.cliente_id,
+            linea.articulo_id,
+            linea.cantidad,
+            money_from_row(&row, "precio", "moneda")?,
+        )
+        .await?;
+        let tipo_iva: String = row.try_get("tipo_iva")?;
+        lineas.push(LineaPedido::calcular(
+            linea.articulo_id,
+            linea.cantidad,
+            precio,
+            0,
+            &tipo_iva,
+            row.try_get("porcentaje_iva")?,
+        )?);
+    }
+
+    let new_pedido = insertar_pedido(&mut tx, pedido.cliente_id, direccion_envio, lineas).await?;
+
+    tx.commit().await?;
+
+    Ok(new_pedido)
+}
+
+// la dirección indicada tiene que ser de envío y del cliente; si no se indica
+// se usa la predeterminada, y el pedido puede quedar sin dirección
+pub async fn direccion_envio(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    cliente_id: i32,
+    direccion_id: Option<i32>,
+) -> Result<Option<direcciones::Direccion>, PedidoError> {
+    let direccion = match direccion_id {
+        Some(id) => {
+            let direccion = direcciones::postgres_get_direccion_by_id(&mut **tx, id)
+                .await?
+                .filter(|d| d.cliente_id == cliente_id && d.tipo == direcciones::TIPO_ENVIO)
+                .ok_or(PedidoError::DireccionNoValida(id))?;
+            Some(direccion)
+        }
+        None => {
+            direcciones::postgres_get_direccion_predeterminada(
+                &mut **tx,
+                cliente_id,
+                direcciones::TIPO_ENVIO,
+            )
+            .await?
+        }
+    };
+
+    Ok(direccion)
+}
+
+// descuenta stock bloqueando el artículo; falla si no hay suficiente
+pub async fn descontar_stock(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    articulo_id: i32,
+    cantidad: i32,
+) -> Result<(), PedidoError> {
+    let stock: i32 = sqlx::query_scalar(
+        "SELECT stock FROM articulos WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+    )
+    .bind(articulo_id)
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(PedidoError::ArticuloNoEncontrado(articulo_id))?;
+
+    if stock < cantidad {
+        return Err(PedidoError::StockInsuficiente(articulo_id));
+    }
+    sqlx::query("UPDATE articulos SET stock = stock - $1 WHERE id = $2")
+        .bind(cantidad)
+        .bind(articulo_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+// inserta cabecera y líneas; los totales del pedido son la suma de las líneas.
+// La dirección se copia formateada, como en las facturas; sin dirección de
+// envío se copia la dirección libre del cliente.
+pub async fn insertar_pedido(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    cliente_id: i32,
+    direccion_envio: Option<direcciones::Direccion>,
+    lineas: Vec<LineaPedido>,
+) -> Result<Pedido, PedidoError> {
+    // todas las líneas van en la moneda de la primera
+    let moneda = lineas[0].precio.precio_unitario.currency();
+    let base_imponible = Money::sum(moneda, lineas.iter().map(|l| l.desglose.base))?;
+    let cuota_iva = Money::sum(moneda, lineas.iter().map(|l| l.desglose.cuota))?;
+    let total = Money::sum(moneda, lineas.iter().map(|l| l.desglose.total))?;
+
+    let new_pedido = sqlx::query_as::<_, Pedido>(&format!(
+        "
+        INSERT INTO pedidos (
+            cliente_id, base_imponible, cuota_iva, total, moneda,
+            direccion_envio_id, direccion_envio
+        )
+        SELECT $1, $2, $3, $4, $5, $6, COALESCE($7, c.direccion)
+        FROM clientes c
+        WHERE c.id = $1
+        RETURNING {}",
+        PEDIDO_COLUMNS
+    ))
+    .bind(cliente_id)
+    .bind(base_imponible.amount_minor())
+    .bind(cuota_iva.amount_minor())
+    .bind(total.amount_minor())
+    .bind(moneda.code())
+    .bind(direccion_envio.as_ref().map(|d| d.id))
+    .bind(direccion_envio.as_ref().map(|d| d.formatear()))
+    .fetch_one(&mut **tx)
+    .await?;
+
+    for linea in lineas {
+        sqlx::query(
+            "
+            INSERT INTO pedidos_detalles (
+                pedido_id, articulo_id, cantidad, precio_unitario, regla_precio, regla_id,
+                descuento, subtotal, tipo_iva, porcentaje_iva, cuota_iva, total
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+        )
+        .bind(new_pedido.id)
+        .bind(linea.articulo_id)
+        .bind(linea.cantidad)
+        .bind(linea.precio.precio_unitario.amount_minor())
+        .bind(&linea.precio.regla_precio)
+        .bind(linea.precio.regla_id)
+        .bind(linea.descuento)
+        .bind(linea.desglose.base.amount_minor())
+        .bind(&linea.desglose.tipo_iva)
+        .bind(linea.desglose.porcentaje_iva)
+        .bind(linea.desglose.cuota.amount_minor())
+        .bind(linea.desglose.total.amount_minor())
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(new_pedido)
+}
+
+// cambia el estado del pedido; al cancelar se devuelve el stock de sus líneas.
+// Con desde solo se cambia si el pedido sigue en ese estado.
+pub async fn postgres_update_pedido_estado(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    estado: &str,
+    desde: Option<&str>,
+) -> Result<Pedido, PedidoError> {
+    let mut tx = pool.begin().await?;
+
+    let estado_actual: String =
+        sqlx::query_scalar("SELECT estado FROM pedidos WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(PedidoError::PedidoNoEncontrado(id))?;
+
+    if !transicion_valida(&estado_actual, estado)
+        || desde.is_some_and(|desde| desde != estado_actual)
+    {
+        return Err(PedidoError::TransicionNoValida(
+            estado_actual,
+            estado.to_string(),
+        ));
+    }
+
+    if estado == ESTADO_CANCELADO {
+        sqlx::query(
+            "
+            UPDATE articulos a
+            SET stock = a.stock + pd.cantidad
+            FROM (
+                SELECT articulo_id, SUM(cantidad)::INT AS cantidad
+                FROM pedidos_detalles
+                WHERE pedido_id = $1
+                GROUP BY articulo_id
+            ) pd
+            WHERE pd.articulo_id = a.id",
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let pedido = sqlx::query_as::<_, Pedido>(&format!(
+        "UPDATE pedidos SET estado = $1 WHERE id = $2 RETURNING {}",
+        PEDIDO_COLUMNS
+    ))
+    .bind(estado)
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(pedido)
+}
@@ -1,16 +1,511 @@
+use crate::iva::{DesgloseIva, TIPOS_IVA};
+use crate::money::{Currency, Money};
+
 pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS issue_request_articulos;
+        DROP TABLE IF EXISTS segmentos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS oportunidades;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS leads;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS notificaciones;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tareas_pedidos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tareas_articulos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tareas_clientes;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tareas;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS pagos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS facturas_lineas;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS facturas;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS facturas_contadores;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS presupuestos_lineas;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS presupuestos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS issue_request_articulos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS issue_request_clientes;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS issue_request_pedidos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS issue_request;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS promociones;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tarifas_precios;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS pedidos_historial;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS pedidos_detalles;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS pedidos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS clientes_etiquetas;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS contactos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS direcciones;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS interacciones;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS consentimientos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS rgpd_solicitudes;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS corp_conflictos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS corp_usuarios;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS clientes_fusiones;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS clientes_historial;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS clientes;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tarifas;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS articulos;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        DROP TABLE IF EXISTS tipos_iva;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tipos_iva (
+            codigo VARCHAR(20) PRIMARY KEY,     -- general, reducido, superreducido, exento
+            descripcion VARCHAR(100) NOT NULL,  -- Descripción del tipo
+            porcentaje INT NOT NULL             -- Porcentaje en puntos básicos (2100 = 21%)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    for (codigo, descripcion, porcentaje) in TIPOS_IVA {
+        sqlx::query(
+            r#"        
+            INSERT INTO tipos_iva (codigo, descripcion, porcentaje)
+            VALUES ($1, $2, $3);
+            "#,
+        )
+        .bind(codigo)
+        .bind(descripcion)
+        .bind(porcentaje)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tarifas (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la tarifa
+            nombre VARCHAR(100) NOT NULL,       -- Nombre de la tarifa
+            descripcion TEXT,                   -- Descripción de la tarifa
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP -- Fecha de creación
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS clientes (
+            id SERIAL PRIMARY KEY,              -- Identificador único del cliente
+            user_id INT UNIQUE,                 -- Usuario de auth vinculado (NULL si no tiene cuenta)
+            nombre VARCHAR(100) NOT NULL,       -- Nombre del cliente
+            email VARCHAR(100) UNIQUE,          -- Email del cliente (único)
+            telefono VARCHAR(20),               -- Teléfono del cliente
+            direccion TEXT,                     -- Dirección del cliente
+            fecha_registro TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de registro
+            deleted_at TIMESTAMP,               -- Fecha de borrado lógico (NULL si activo)
+            tarifa_id INT,                      -- Tarifa de precios asignada (NULL si precio de catálogo)
+            FOREIGN KEY (tarifa_id) REFERENCES tarifas(id) ON DELETE SET NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS clientes_historial (
+            id SERIAL PRIMARY KEY,              -- Identificador único del cambio
+            cliente_id INT NOT NULL,            -- ID del cliente modificado
+            fecha TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha del cambio
+            cambios JSONB NOT NULL,             -- Columnas cambiadas con su valor antes y después
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS clientes_fusiones (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la fusión
+            cliente_id INT NOT NULL,            -- ID del cliente que sobrevive
+            fusionado_id INT NOT NULL,          -- ID del duplicado (queda borrado lógicamente)
+            fusionado JSONB NOT NULL,           -- Fila del duplicado antes de la fusión
+            movidos JSONB NOT NULL,             -- Filas movidas por tabla
+            user_id INT NOT NULL,               -- user_id de quien fusiona (auth)
+            fecha TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de la fusión
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS rgpd_solicitudes (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la solicitud
+            cliente_id INT NOT NULL,            -- ID del cliente
+            tipo VARCHAR(20) NOT NULL,          -- exportacion o supresion
+            user_id INT NOT NULL,               -- user_id de quien la atiende (auth)
+            fecha TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha en que se atendió
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS corp_usuarios (
+            user_id INT PRIMARY KEY,            -- user_id de auth
+            datos JSONB NOT NULL,               -- Copia de UserData del directorio corporativo
+            last_synced_at TIMESTAMP NOT NULL   -- Última vez que se leyó del directorio
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS corp_conflictos (
+            id SERIAL PRIMARY KEY,              -- Identificador único del conflicto
+            cliente_id INT NOT NULL,            -- ID del cliente
+            user_id INT NOT NULL,               -- user_id de auth del cliente
+            campo VARCHAR(20) NOT NULL,         -- nombre, email o telefono
+            valor_local TEXT,                   -- Valor del cliente antes de sincronizar
+            valor_corp TEXT NOT NULL,           -- Valor del directorio corporativo
+            precedencia VARCHAR(10) NOT NULL,   -- corp o local, según la política
+            aplicado BOOLEAN NOT NULL,          -- TRUE si el valor corporativo sustituyó al local
+            fecha TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de la sincronización
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS consentimientos (
+            id SERIAL PRIMARY KEY,              -- Identificador único del registro
+            cliente_id INT NOT NULL,            -- ID del cliente
+            canal VARCHAR(20) NOT NULL,         -- email, telefono o sms
+            otorgado BOOLEAN NOT NULL,          -- TRUE si da el consentimiento, FALSE si lo retira
+            origen VARCHAR(20) NOT NULL,        -- Dónde se recogió (web, formulario, llamada...)
+            version_texto VARCHAR(20) NOT NULL, -- Versión del texto legal aceptado
+            user_id INT NOT NULL,               -- user_id de quien lo registra (auth)
+            fecha TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- Momento del cambio
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE RESTRICT
+        );
         "#,
     )
     .execute(&pool)
     .await
     .unwrap();
 
+    // el historial es la prueba del consentimiento: solo se añaden filas
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS issue_request_clientes;
+        CREATE OR REPLACE FUNCTION consentimientos_solo_insercion() RETURNS TRIGGER AS $$
+        BEGIN
+            RAISE EXCEPTION 'el historial de consentimientos no se puede modificar';
+        END;
+        $$ LANGUAGE plpgsql;
         "#,
     )
     .execute(&pool)
@@ -19,7 +514,9 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
 
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS issue_request_pedidos;
+        CREATE TRIGGER consentimientos_solo_insercion
+        BEFORE UPDATE OR DELETE ON consentimientos
+        FOR EACH ROW EXECUTE FUNCTION consentimientos_solo_insercion();
         "#,
     )
     .execute(&pool)
@@ -28,7 +525,18 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
 
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS issue_request;
+        CREATE TABLE IF NOT EXISTS interacciones (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la interacción
+            cliente_id INT NOT NULL,            -- ID del cliente
+            autor_user_id INT NOT NULL,         -- user_id de quien la registra (auth)
+            tipo VARCHAR(20) NOT NULL,          -- llamada, reunion, email o nota
+            cuerpo TEXT NOT NULL,               -- Contenido de la interacción
+            fecha TIMESTAMP NOT NULL,           -- Cuándo ocurrió
+            fecha_seguimiento DATE,             -- Fecha para volver a contactar (opcional)
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de registro
+            fecha_modificacion TIMESTAMP,       -- Última edición por el autor
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
         "#,
     )
     .execute(&pool)
@@ -37,16 +545,30 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
 
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS pedidos_detalles;
+        CREATE TABLE IF NOT EXISTS direcciones (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la dirección
+            cliente_id INT NOT NULL,            -- ID del cliente
+            tipo VARCHAR(20) NOT NULL,          -- facturacion o envio
+            calle VARCHAR(200) NOT NULL,        -- Calle, número, piso
+            codigo_postal VARCHAR(10) NOT NULL, -- Código postal
+            ciudad VARCHAR(100) NOT NULL,       -- Ciudad
+            provincia VARCHAR(100),             -- Provincia o región (opcional)
+            pais VARCHAR(2) NOT NULL DEFAULT 'ES', -- País (ISO 3166-1 alfa-2)
+            predeterminada BOOLEAN NOT NULL DEFAULT FALSE, -- La que se usa si no se indica otra
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de alta
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
         "#,
     )
     .execute(&pool)
     .await
     .unwrap();
 
+    // una sola dirección predeterminada por cliente y tipo
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS pedidos;
+        CREATE UNIQUE INDEX IF NOT EXISTS direcciones_predeterminada
+        ON direcciones (cliente_id, tipo) WHERE predeterminada;
         "#,
     )
     .execute(&pool)
@@ -55,7 +577,16 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
 
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS clientes;
+        CREATE TABLE IF NOT EXISTS contactos (
+            id SERIAL PRIMARY KEY,              -- Identificador único del contacto
+            cliente_id INT NOT NULL,            -- ID del cliente
+            nombre VARCHAR(100) NOT NULL,       -- Nombre de la persona de contacto
+            cargo VARCHAR(100),                 -- Cargo en la empresa (opcional)
+            email VARCHAR(100),                 -- Email (opcional)
+            telefono VARCHAR(20),               -- Teléfono (opcional)
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de alta
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
         "#,
     )
     .execute(&pool)
@@ -64,24 +595,56 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
 
     sqlx::query(
         r#"        
-        DROP TABLE IF EXISTS articulos;
+        CREATE TABLE IF NOT EXISTS clientes_etiquetas (
+            cliente_id INT NOT NULL,            -- ID del cliente
+            etiqueta VARCHAR(50) NOT NULL,      -- Etiqueta libre en minúsculas
+            PRIMARY KEY (cliente_id, etiqueta),
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
         "#,
     )
     .execute(&pool)
     .await
     .unwrap();
 
+    // para buscar clientes por etiqueta en los segmentos
     sqlx::query(
         r#"        
-        CREATE TABLE IF NOT EXISTS clientes (
-            id SERIAL PRIMARY KEY,              -- Identificador único del cliente
-            user_id INT NOT NULL UNIQUE,        -- Identificador único del usuario, integra con auth
-            nombre VARCHAR(100) NOT NULL,       -- Nombre del cliente
-            email VARCHAR(100) UNIQUE,          -- Email del cliente (único)
-            telefono VARCHAR(20),               -- Teléfono del cliente
-            direccion TEXT,                     -- Dirección del cliente
-            fecha_registro TIMESTAMP DEFAULT CURRENT_TIMESTAMP -- Fecha de registro
-        );
+        CREATE INDEX IF NOT EXISTS clientes_etiquetas_etiqueta
+        ON clientes_etiquetas (etiqueta);
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // cada UPDATE de clientes deja en el historial solo las columnas que cambian
+    sqlx::query(
+        r#"        
+        CREATE OR REPLACE FUNCTION clientes_historial() RETURNS TRIGGER AS $$
+        BEGIN
+            INSERT INTO clientes_historial (cliente_id, cambios)
+            SELECT NEW.id, jsonb_object_agg(
+                nuevo.key, jsonb_build_object('antes', viejo.value, 'despues', nuevo.value)
+            )
+            FROM jsonb_each(to_jsonb(NEW)) nuevo
+            JOIN jsonb_each(to_jsonb(OLD)) viejo ON viejo.key = nuevo.key
+            WHERE nuevo.value IS DISTINCT FROM viejo.value
+            HAVING COUNT(*) > 0;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TRIGGER clientes_historial
+        AFTER UPDATE ON clientes
+        FOR EACH ROW EXECUTE FUNCTION clientes_historial();
         "#,
     )
     .execute(&pool)
@@ -94,9 +657,51 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
             id SERIAL PRIMARY KEY,              -- Identificador único del artículo
             nombre VARCHAR(100) NOT NULL,       -- Nombre del artículo
             descripcion TEXT,                   -- Descripción del artículo
-            precio INT NOT NULL,     -- Precio del artículo
+            precio BIGINT NOT NULL,             -- Precio del artículo en céntimos
+            moneda VARCHAR(3) NOT NULL DEFAULT 'EUR', -- Moneda del precio (ISO 4217)
             stock INT NOT NULL DEFAULT 0,       -- Cantidad en stock
-            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP -- Fecha de creación
+            tipo_iva VARCHAR(20) NOT NULL DEFAULT 'general', -- Tipo de IVA del artículo
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            deleted_at TIMESTAMP,               -- Fecha de borrado lógico (NULL si activo)
+            FOREIGN KEY (tipo_iva) REFERENCES tipos_iva(codigo)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tarifas_precios (
+            tarifa_id INT NOT NULL,             -- ID de la tarifa
+            articulo_id INT NOT NULL,           -- ID del artículo
+            precio BIGINT NOT NULL,             -- Precio del artículo en esta tarifa en céntimos
+            moneda VARCHAR(3) NOT NULL,         -- Moneda del precio (ISO 4217)
+            PRIMARY KEY (tarifa_id, articulo_id),
+            FOREIGN KEY (tarifa_id) REFERENCES tarifas(id) ON DELETE CASCADE,
+            FOREIGN KEY (articulo_id) REFERENCES articulos(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS promociones (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la promoción
+            nombre VARCHAR(100) NOT NULL,       -- Nombre de la promoción
+            articulo_id INT,                    -- Artículo promocionado (NULL para todo el catálogo)
+            porcentaje INT,                     -- Descuento en puntos básicos (1000 = 10%)
+            importe BIGINT,                     -- Descuento fijo por unidad en céntimos
+            moneda VARCHAR(3),                  -- Moneda del descuento fijo (ISO 4217)
+            cantidad_minima INT NOT NULL DEFAULT 1, -- Cantidad mínima de la línea para aplicarla
+            fecha_inicio DATE NOT NULL,         -- Primer día de vigencia
+            fecha_fin DATE NOT NULL,            -- Último día de vigencia
+            CHECK ((porcentaje IS NULL) <> (importe IS NULL)),
+            FOREIGN KEY (articulo_id) REFERENCES articulos(id) ON DELETE CASCADE
         );
         "#,
     )
@@ -111,8 +716,30 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
             cliente_id INT NOT NULL,             -- ID del cliente que realiza el pedido
             fecha_pedido TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha del pedido
             estado VARCHAR(50) NOT NULL DEFAULT 'Pendiente', -- Estado del pedido (Pendiente, Enviado, Entregado, etc.)
-            total INT NOT NULL,      -- Total del pedido
-            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+            base_imponible BIGINT NOT NULL,     -- Suma de las bases de las líneas en céntimos
+            cuota_iva BIGINT NOT NULL,          -- Suma de las cuotas de IVA de las líneas en céntimos
+            total BIGINT NOT NULL,              -- Total del pedido con IVA en céntimos
+            moneda VARCHAR(3) NOT NULL DEFAULT 'EUR', -- Moneda del pedido y sus detalles (ISO 4217)
+            direccion_envio_id INT,             -- Dirección de envío del cliente (NULL si no tiene)
+            direccion_envio TEXT,               -- Dirección de envío tal como era al hacer el pedido
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE,
+            FOREIGN KEY (direccion_envio_id) REFERENCES direcciones(id) ON DELETE SET NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS pedidos_historial (
+            id SERIAL PRIMARY KEY,              -- Identificador único del cambio
+            pedido_id INT NOT NULL,             -- ID del pedido
+            fecha TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha del cambio de estado
+            estado_anterior VARCHAR(50) NOT NULL, -- Estado antes del cambio
+            estado VARCHAR(50) NOT NULL,        -- Estado nuevo
+            FOREIGN KEY (pedido_id) REFERENCES pedidos(id) ON DELETE CASCADE
         );
         "#,
     )
@@ -120,6 +747,35 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
     .await
     .unwrap();
 
+    // los cambios de estado se registran en la base de datos, vengan de donde vengan
+    sqlx::query(
+        r#"        
+        CREATE OR REPLACE FUNCTION pedidos_historial() RETURNS TRIGGER AS $$
+        BEGIN
+            INSERT INTO pedidos_historial (pedido_id, estado_anterior, estado)
+            VALUES (NEW.id, OLD.estado, NEW.estado);
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TRIGGER pedidos_historial
+        AFTER UPDATE OF estado ON pedidos
+        FOR EACH ROW
+        WHEN (OLD.estado IS DISTINCT FROM NEW.estado)
+        EXECUTE FUNCTION pedidos_historial();
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
     sqlx::query(
         r#"        
         CREATE TABLE IF NOT EXISTS pedidos_detalles (
@@ -127,8 +783,15 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
             pedido_id INT NOT NULL,             -- ID del pedido
             articulo_id INT NOT NULL,           -- ID del artículo
             cantidad INT NOT NULL,              -- Cantidad del artículo en el pedido
-            precio_unitario INT NOT NULL, -- Precio unitario del artículo en el momento del pedido
-            subtotal INT NOT NULL,   -- Subtotal (cantidad * precio_unitario)
+            precio_unitario BIGINT NOT NULL,    -- Precio unitario en céntimos en el momento del pedido
+            regla_precio VARCHAR(20) NOT NULL DEFAULT 'base', -- Origen del precio: base, tarifa o promocion
+            regla_id INT,                       -- ID de la tarifa o promoción aplicada
+            descuento INT NOT NULL DEFAULT 0,   -- Descuento de la línea en puntos básicos (1000 = 10%)
+            subtotal BIGINT NOT NULL,           -- Base imponible en céntimos (cantidad * precio_unitario - descuento)
+            tipo_iva VARCHAR(20) NOT NULL,      -- Tipo de IVA aplicado
+            porcentaje_iva INT NOT NULL,        -- Porcentaje aplicado en puntos básicos (2100 = 21%)
+            cuota_iva BIGINT NOT NULL,          -- Cuota de IVA en céntimos
+            total BIGINT NOT NULL,              -- Total de la línea con IVA en céntimos
             FOREIGN KEY (pedido_id) REFERENCES pedidos(id) ON DELETE CASCADE,
             FOREIGN KEY (articulo_id) REFERENCES articulos(id) ON DELETE CASCADE
         );
@@ -138,6 +801,53 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
     .await
     .unwrap();
 
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS presupuestos (
+            id SERIAL PRIMARY KEY,              -- Identificador único del presupuesto
+            cliente_id INT NOT NULL,            -- ID del cliente al que se ofrece
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            fecha_validez DATE NOT NULL,        -- Último día en que se puede aceptar
+            estado VARCHAR(20) NOT NULL DEFAULT 'borrador', -- borrador, enviado, aceptado, rechazado o caducado
+            pedido_id INT UNIQUE,               -- Pedido generado al convertirlo
+            base_imponible BIGINT NOT NULL,     -- Suma de las bases de las líneas en céntimos
+            cuota_iva BIGINT NOT NULL,          -- Suma de las cuotas de IVA en céntimos
+            total BIGINT NOT NULL,              -- Total con IVA en céntimos
+            moneda VARCHAR(3) NOT NULL DEFAULT 'EUR', -- Moneda del presupuesto y sus líneas (ISO 4217)
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE,
+            FOREIGN KEY (pedido_id) REFERENCES pedidos(id) ON DELETE SET NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS presupuestos_lineas (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la línea
+            presupuesto_id INT NOT NULL,        -- ID del presupuesto
+            articulo_id INT NOT NULL,           -- ID del artículo
+            cantidad INT NOT NULL,              -- Cantidad ofrecida
+            precio_unitario BIGINT NOT NULL,    -- Precio unitario en céntimos al presupuestar
+            regla_precio VARCHAR(20) NOT NULL DEFAULT 'base', -- Origen del precio: base, tarifa o promocion
+            regla_id INT,                       -- ID de la tarifa o promoción aplicada
+            descuento INT NOT NULL DEFAULT 0,   -- Descuento de la línea en puntos básicos (1000 = 10%)
+            subtotal BIGINT NOT NULL,           -- Base imponible en céntimos ya descontada
+            tipo_iva VARCHAR(20) NOT NULL,      -- Tipo de IVA aplicado
+            porcentaje_iva INT NOT NULL,        -- Porcentaje en puntos básicos (2100 = 21%)
+            cuota_iva BIGINT NOT NULL,          -- Cuota de IVA en céntimos
+            total BIGINT NOT NULL,              -- Total de la línea con IVA en céntimos
+            FOREIGN KEY (presupuesto_id) REFERENCES presupuestos(id) ON DELETE CASCADE,
+            FOREIGN KEY (articulo_id) REFERENCES articulos(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
     sqlx::query(
         r#"        
         CREATE TABLE IF NOT EXISTS issue_request (
@@ -200,6 +910,278 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
     .await
     .unwrap();
 
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tareas (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la tarea
+            titulo VARCHAR(200) NOT NULL,       -- Título de la tarea
+            descripcion TEXT,                   -- Detalle (opcional)
+            asignado_user_id INT NOT NULL,      -- user_id responsable (auth)
+            creador_user_id INT NOT NULL,       -- user_id de quien la crea (auth)
+            prioridad VARCHAR(10) NOT NULL DEFAULT 'media', -- baja, media o alta
+            estado VARCHAR(20) NOT NULL DEFAULT 'pendiente', -- pendiente, en_curso, completada o cancelada
+            fecha_vencimiento TIMESTAMP,        -- Fecha límite (opcional)
+            vencida_en TIMESTAMP,               -- Cuándo la marcó vencida el job
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            fecha_modificacion TIMESTAMP        -- Última edición
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tareas_clientes (
+            tarea_id INT PRIMARY KEY,           -- ID de la tarea (un vínculo por tipo)
+            cliente_id INT NOT NULL,                  -- ID del cliente
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            FOREIGN KEY (tarea_id) REFERENCES tareas(id) ON DELETE CASCADE,
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tareas_articulos (
+            tarea_id INT PRIMARY KEY,           -- ID de la tarea (un vínculo por tipo)
+            articulo_id INT NOT NULL,                  -- ID del artículo
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            FOREIGN KEY (tarea_id) REFERENCES tareas(id) ON DELETE CASCADE,
+            FOREIGN KEY (articulo_id) REFERENCES articulos(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS tareas_pedidos (
+            tarea_id INT PRIMARY KEY,           -- ID de la tarea (un vínculo por tipo)
+            pedido_id INT NOT NULL,                  -- ID del pedido
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            FOREIGN KEY (tarea_id) REFERENCES tareas(id) ON DELETE CASCADE,
+            FOREIGN KEY (pedido_id) REFERENCES pedidos(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS notificaciones (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la notificación
+            user_id INT NOT NULL,               -- Destinatario (auth)
+            tipo VARCHAR(30) NOT NULL,          -- Origen de la notificación (tarea_vencida...)
+            mensaje TEXT NOT NULL,              -- Texto para el usuario
+            tarea_id INT,                       -- Tarea relacionada (opcional)
+            leida BOOLEAN NOT NULL DEFAULT FALSE, -- Marcada como leída por el usuario
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            FOREIGN KEY (tarea_id) REFERENCES tareas(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS leads (
+            id SERIAL PRIMARY KEY,              -- Identificador único del lead
+            nombre VARCHAR(100) NOT NULL,       -- Nombre del contacto
+            email VARCHAR(100),                 -- Email (obligatorio para convertirlo en cliente)
+            telefono VARCHAR(20),               -- Teléfono
+            empresa VARCHAR(100),               -- Empresa del contacto
+            origen VARCHAR(20) NOT NULL,        -- web, referido, feria, llamada u otro
+            estado VARCHAR(20) NOT NULL DEFAULT 'nuevo', -- nuevo, contactado, cualificado, descartado o convertido
+            notas TEXT,                         -- Notas del comercial
+            propietario_user_id INT NOT NULL,   -- user_id del comercial que lo lleva (auth)
+            cliente_id INT,                     -- Cliente creado al convertir el lead
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            fecha_modificacion TIMESTAMP,       -- Última edición
+            fecha_conversion TIMESTAMP,         -- Cuándo se convirtió en cliente
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE SET NULL
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS oportunidades (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la oportunidad
+            titulo VARCHAR(200) NOT NULL,       -- Descripción corta
+            lead_id INT,                        -- Lead de origen (si no es de un cliente)
+            cliente_id INT,                     -- Cliente (directo o tras convertir el lead)
+            etapa VARCHAR(20) NOT NULL DEFAULT 'prospeccion', -- Etapa del pipeline
+            valor_esperado BIGINT NOT NULL,     -- Valor esperado en céntimos
+            moneda VARCHAR(3) NOT NULL,         -- Moneda del valor esperado (ISO 4217)
+            fecha_cierre_prevista DATE,         -- Fecha prevista de cierre
+            fecha_cierre TIMESTAMP,             -- Cuándo pasó a ganada o perdida
+            propietario_user_id INT NOT NULL,   -- user_id del comercial (auth)
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de creación
+            fecha_modificacion TIMESTAMP,       -- Última edición
+            FOREIGN KEY (lead_id) REFERENCES leads(id) ON DELETE CASCADE,
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE CASCADE,
+            CHECK (lead_id IS NOT NULL OR cliente_id IS NOT NULL)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS segmentos (
+            id SERIAL PRIMARY KEY,              -- Identificador único del segmento
+            nombre VARCHAR(100) NOT NULL UNIQUE, -- Nombre del segmento
+            descripcion TEXT,                   -- Descripción (opcional)
+            reglas JSONB NOT NULL,              -- Reglas que deben cumplir los clientes (todas)
+            creador_user_id INT NOT NULL,       -- user_id de quien lo crea (auth)
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de alta
+            fecha_modificacion TIMESTAMP        -- Última edición
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS facturas_contadores (
+            serie VARCHAR(10) NOT NULL,         -- Serie de facturación
+            anio INT NOT NULL,                  -- Año de la numeración
+            ultimo_numero INT NOT NULL,         -- Último número emitido en la serie y año
+            PRIMARY KEY (serie, anio)
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS facturas (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la factura
+            serie VARCHAR(10) NOT NULL,         -- Serie (F ordinarias, R rectificativas)
+            anio INT NOT NULL,                  -- Año de la numeración
+            numero INT NOT NULL,                -- Número correlativo dentro de serie y año
+            codigo VARCHAR(30) NOT NULL UNIQUE, -- Código legible, p.ej. F2026-000001
+            tipo VARCHAR(20) NOT NULL,          -- ordinaria o rectificativa
+            fecha_emision TIMESTAMP NOT NULL,   -- Fecha de emisión
+            pedido_id INT NOT NULL,             -- Pedido facturado
+            factura_rectificada_id INT UNIQUE,  -- Factura que rectifica (solo rectificativas)
+            motivo TEXT,                        -- Motivo de la rectificación
+            cliente_id INT NOT NULL,            -- Cliente facturado
+            cliente_nombre VARCHAR(100) NOT NULL, -- Copia del nombre del cliente al emitir
+            cliente_email VARCHAR(100),         -- Copia del email del cliente al emitir
+            cliente_telefono VARCHAR(20),       -- Copia del teléfono del cliente al emitir
+            cliente_direccion TEXT,             -- Copia de la dirección del cliente al emitir
+            base_imponible BIGINT NOT NULL,     -- Base imponible en céntimos
+            cuota_iva BIGINT NOT NULL,          -- Cuota de IVA en céntimos
+            total BIGINT NOT NULL,              -- Total con IVA en céntimos
+            moneda VARCHAR(3) NOT NULL,         -- Moneda (ISO 4217)
+            UNIQUE (serie, anio, numero),
+            FOREIGN KEY (pedido_id) REFERENCES pedidos(id) ON DELETE RESTRICT,
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE RESTRICT,
+            FOREIGN KEY (factura_rectificada_id) REFERENCES facturas(id) ON DELETE RESTRICT
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS facturas_lineas (
+            id SERIAL PRIMARY KEY,              -- Identificador único de la línea
+            factura_id INT NOT NULL,            -- ID de la factura
+            articulo_id INT NOT NULL,           -- ID del artículo
+            descripcion VARCHAR(100) NOT NULL,  -- Copia del nombre del artículo al emitir
+            cantidad INT NOT NULL,              -- Cantidad (negativa en rectificativas)
+            precio_unitario BIGINT NOT NULL,    -- Precio unitario en céntimos
+            descuento INT NOT NULL DEFAULT 0,   -- Descuento de la línea en puntos básicos
+            subtotal BIGINT NOT NULL,           -- Base imponible en céntimos
+            tipo_iva VARCHAR(20) NOT NULL,      -- Tipo de IVA aplicado
+            porcentaje_iva INT NOT NULL,        -- Porcentaje en puntos básicos (2100 = 21%)
+            cuota_iva BIGINT NOT NULL,          -- Cuota de IVA en céntimos
+            total BIGINT NOT NULL,              -- Total de la línea con IVA en céntimos
+            moneda VARCHAR(3) NOT NULL,         -- Moneda (ISO 4217)
+            FOREIGN KEY (factura_id) REFERENCES facturas(id) ON DELETE RESTRICT,
+            FOREIGN KEY (articulo_id) REFERENCES articulos(id) ON DELETE RESTRICT
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"        
+        CREATE TABLE IF NOT EXISTS pagos (
+            id SERIAL PRIMARY KEY,              -- Identificador único del pago
+            cliente_id INT NOT NULL,            -- Cliente que paga
+            pedido_id INT NOT NULL,             -- Pedido al que se imputa
+            factura_id INT,                     -- Factura a la que se imputa (si se pagó contra factura)
+            metodo VARCHAR(20) NOT NULL,        -- transferencia, tarjeta, efectivo o domiciliacion
+            importe BIGINT NOT NULL,            -- Importe pagado en céntimos
+            moneda VARCHAR(3) NOT NULL,         -- Moneda del pago (ISO 4217)
+            fecha DATE NOT NULL,                -- Fecha del pago
+            referencia VARCHAR(100),            -- Referencia del banco, TPV, etc.
+            fecha_creacion TIMESTAMP DEFAULT CURRENT_TIMESTAMP, -- Fecha de registro
+            FOREIGN KEY (cliente_id) REFERENCES clientes(id) ON DELETE RESTRICT,
+            FOREIGN KEY (pedido_id) REFERENCES pedidos(id) ON DELETE RESTRICT,
+            FOREIGN KEY (factura_id) REFERENCES facturas(id) ON DELETE RESTRICT
+        );
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // las facturas emitidas no se modifican ni se borran, se rectifican
+    sqlx::query(
+        r#"        
+        CREATE OR REPLACE FUNCTION facturas_inmutables() RETURNS TRIGGER AS $$
+        BEGIN
+            RAISE EXCEPTION 'las facturas emitidas no se pueden modificar ni borrar';
+        END;
+        $$ LANGUAGE plpgsql;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    for tabla in ["facturas", "facturas_lineas"] {
+        sqlx::query(&format!(
+            r#"        
+            CREATE TRIGGER {tabla}_inmutables
+            BEFORE UPDATE OR DELETE ON {tabla}
+            FOR EACH ROW EXECUTE FUNCTION facturas_inmutables();
+            "#
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
     sqlx::query(
         r#"        
         INSERT INTO clientes (user_id,nombre, email, telefono, direccion)
@@ -210,33 +1192,79 @@ pub async fn initialization(pool: sqlx::Pool<sqlx::Postgres>) {
     .await
     .unwrap();
 
+    // importes en céntimos: 2 laptops a 1200.00 cada una con IVA general
+    let precio_laptop = Money::from_minor(120000, Currency::Eur);
+    let (tipo_iva, _, porcentaje_iva) = TIPOS_IVA[0];
+    let linea = DesgloseIva::calcular(
+        tipo_iva,
+        porcentaje_iva,
+        precio_laptop.checked_mul(2).unwrap(),
+    )
+    .unwrap();
+
     sqlx::query(
         r#"        
-        INSERT INTO articulos (nombre, descripcion, precio, stock)
-        VALUES ('Laptop', 'Laptop de 15 pulgadas', 120000, 10);
+        INSERT INTO articulos (nombre, descripcion, precio, moneda, stock, tipo_iva)
+        VALUES ('Laptop', 'Laptop de 15 pulgadas', $1, $2, 10, $3);
         "#,
     )
+    .bind(precio_laptop.amount_minor())
+    .bind(precio_laptop.currency().code())
+    .bind(tipo_iva)
     .execute(&pool)
     .await
     .unwrap();
 
     sqlx::query(
         r#"        
-        INSERT INTO pedidos (cliente_id, total)
-        VALUES (1, 240000); -- Suponiendo que el cliente con ID 1 compra 2 laptops
+        INSERT INTO pedidos (cliente_id, base_imponible, cuota_iva, total, moneda)
+        VALUES (1, $1, $2, $3, $4); -- Suponiendo que el cliente con ID 1 compra 2 laptops
     "#,
     )
+    .bind(linea.base.amount_minor())
+    .bind(linea.cuota.amount_minor())
+    .bind(linea.total.amount_minor())
+    .bind(linea.total.currency().code())
     .execute(&pool)
     .await
     .unwrap();
 
     sqlx::query(
         r#"        
-        INSERT INTO pedidos_detalles (pedido_id, articulo_id, cantidad, precio_unitario, subtotal)
-        VALUES (1, 1, 2, 120000, 240000); -- 2 laptops a 1200.00 cada una
+        INSERT INTO pedidos_detalles (
+            pedido_id, articulo_id, cantidad, precio_unitario, subtotal,
+            tipo_iva, porcentaje_iva, cuota_iva, total
+        )
+        VALUES (1, 1, 2, $1, $2, $3, $4, $5, $6);
         "#,
     )
+    .bind(precio_laptop.amount_minor())
+    .bind(linea.base.amount_minor())
+    .bind(tipo_iva)
+    .bind(porcentaje_iva)
+    .bind(linea.cuota.amount_minor())
+    .bind(linea.total.amount_minor())
     .execute(&pool)
     .await
     .unwrap();
 }
+
+// Base de datos para los tests que necesitan Postgres, tomada de
+// TEST_DATABASE_URL y recreada con initialization como al arrancar. Esos tests
+// van con #[ignore] y se lanzan con --ignored; sin la variable fallan. El guard
+// serializa los tests: todos recrean las mismas tablas.
+#[cfg(test)]
+pub mod pruebas {
+    use rocket::tokio::sync::{Mutex, MutexGuard};
+
+    static BASE_DE_DATOS: Mutex<()> = Mutex::const_new(());
+
+    pub async fn pool_de_pruebas() -> (MutexGuard<'static, ()>, sqlx::Pool<sqlx::Postgres>) {
+        let url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL is required for database tests");
+        let guard = BASE_DE_DATOS.lock().await;
+        let pool = sqlx::postgres::PgPool::connect(&url).await.unwrap();
+        super::initialization(pool.clone()).await;
+        (guard, pool)
+    }
+}
@@ -0,0 +1,537 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::{Validate, ValidationError};
+
+use crate::money::{Money, money_from_row};
+
+// origen del precio aplicado en una línea de pedido o presupuesto
+pub const REGLA_BASE: &str = "base";
+pub const REGLA_TARIFA: &str = "tarifa";
+pub const REGLA_PROMOCION: &str = "promocion";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct TarifaRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    pub descripcion: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Tarifa {
+    pub id: i32,
+    pub nombre: String,
+    pub descripcion: Option<String>,
+    pub fecha_creacion: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct TarifaPrecioRequest {
+    #[validate(custom(function = "crate::validacion::validate_non_negative_money"))]
+    pub precio: Money,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TarifaPrecio {
+    pub tarifa_id: i32,
+    pub articulo_id: i32,
+    pub articulo_nombre: String,
+    pub precio: Money,
+}
+
+impl<'r> FromRow<'r, PgRow> for TarifaPrecio {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(TarifaPrecio {
+            tarifa_id: row.try_get("tarifa_id")?,
+            articulo_id: row.try_get("articulo_id")?,
+            articulo_nombre: row.try_get("articulo_nombre")?,
+            precio: money_from_row(row, "precio", "moneda")?,
+        })
+    }
+}
+
+fn cantidad_minima_por_defecto() -> i32 {
+    1
+}
+
+// descuento en porcentaje (puntos básicos) o importe fijo por unidad, nunca ambos
+fn validate_promocion(promocion: &PromocionRequest) -> Result<(), ValidationError> {
+    if promocion.porcentaje.is_some() == promocion.importe.is_some() {
+        return Err(ValidationError::new("descuento")
+            .with_message("indica porcentaje o importe, solo uno de los dos".into()));
+    }
+    if promocion.fecha_fin < promocion.fecha_inicio {
+        return Err(ValidationError::new("fechas")
+            .with_message("fecha_fin no puede ser anterior a fecha_inicio".into()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+#[validate(schema(function = "validate_promocion"))]
+pub struct PromocionRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: String,
+    // sin artículo la promoción aplica a todo el catálogo
+    pub articulo_id: Option<i32>,
+    #[validate(range(min = 1, max = 10000, message = "debe estar entre 1 y 10000"))]
+    pub porcentaje: Option<i32>,
+    #[validate(custom(function = "crate::validacion::validate_non_negative_money"))]
+    pub importe: Option<Money>,
+    #[serde(default = "cantidad_minima_por_defecto")]
+    #[validate(range(min = 1, message = "debe ser al menos 1"))]
+    pub cantidad_minima: i32,
+    pub fecha_inicio: chrono::NaiveDate,
+    pub fecha_fin: chrono::NaiveDate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Promocion {
+    pub id: i32,
+    pub nombre: String,
+    pub articulo_id: Option<i32>,
+    pub porcentaje: Option<i32>,
+    pub importe: Option<Money>,
+    pub cantidad_minima: i32,
+    pub fecha_inicio: chrono::NaiveDate,
+    pub fecha_fin: chrono::NaiveDate,
+}
+
+impl Promocion {
+    // precio unitario tras la promoción, None si no se puede aplicar a ese precio
+    pub fn aplicar(&self, precio: Money) -> Option<Money> {
+        if let Some(porcentaje) = self.porcentaje {
+            return precio.checked_sub(precio.percentage(porcentaje).ok()?).ok();
+        }
+        let importe = self.importe?;
+        if importe.currency() != precio.currency() {
+            return None;
+        }
+        let rebajado = precio.checked_sub(importe).ok()?;
+        if rebajado.is_negative() {
+            return Some(Money::zero(precio.currency()));
+        }
+        Some(rebajado)
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for Promocion {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        let importe: Option<i64> = row.try_get("importe")?;
+        let importe = match importe {
+            Some(_) => Some(money_from_row(row, "importe", "moneda")?),
+            None => None,
+        };
+        Ok(Promocion {
+            id: row.try_get("id")?,
+            nombre: row.try_get("nombre")?,
+            articulo_id: row.try_get("articulo_id")?,
+            porcentaje: row.try_get("porcentaje")?,
+            importe,
+            cantidad_minima: row.try_get("cantidad_minima")?,
+            fecha_inicio: row.try_get("fecha_inicio")?,
+            fecha_fin: row.try_get("fecha_fin")?,
+        })
+    }
+}
+
+const PROMOCION_COLUMNS: &str = "id, nombre, articulo_id, porcentaje, importe, moneda, cantidad_minima, fecha_inicio, fecha_fin";
+
+// precio unitario elegido por el motor y la regla que lo produjo
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrecioAplicado {
+    pub precio_unitario: Money,
+    pub regla_precio: String,
+    pub regla_id: Option<i32>,
+}
+
+impl PrecioAplicado {
+    pub fn base(precio_unitario: Money) -> Self {
+        PrecioAplicado {
+            precio_unitario,
+            regla_precio: REGLA_BASE.to_string(),
+            regla_id: None,
+        }
+    }
+
+    // se queda con el candidato solo si es más barato y en la misma moneda
+    fn considerar(&mut self, precio_unitario: Money, regla_precio: &str, regla_id: i32) {
+        if precio_unitario.currency() == self.precio_unitario.currency()
+            && precio_unitario.amount_minor() < self.precio_unitario.amount_minor()
+        {
+            *self = PrecioAplicado {
+                precio_unitario,
+                regla_precio: regla_precio.to_string(),
+                regla_id: Some(regla_id),
+            };
+        }
+    }
+}
+
+// Motor de precios: parte del precio del artículo y elige el más bajo entre
+// la tarifa del cliente y las promociones vigentes para esa cantidad. Las
+// promociones se aplican sobre el precio del artículo, no se acumulan.
+pub async fn mejor_precio(
+    conn: &mut sqlx::PgConnection,
+    cliente_id: i32,
+    articulo_id: i32,
+    cantidad: i32,
+    precio_articulo: Money,
+) -> Result<PrecioAplicado, sqlx::Error> {
+    let mut mejor = PrecioAplicado::base(precio_articulo);
+
+    let tarifa = sqlx::query(
+        "
+        SELECT tp.tarifa_id, tp.precio, tp.moneda
+        FROM clientes c
+        JOIN tarifas_precios tp ON tp.tarifa_id = c.tarifa_id
+        WHERE c.id = $1 AND tp.articulo_id = $2",
+    )
+    .bind(cliente_id)
+    .bind(articulo_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(row) = tarifa {
+        let precio = money_from_row(&row, "precio", "moneda")?;
+        mejor.considerar(precio, REGLA_TARIFA, row.try_get("tarifa_id")?);
+    }
+
+    let promociones = sqlx::query_as::<_, Promocion>(&format!(
+        "
+        SELECT {}
+        FROM promociones
+        WHERE (articulo_id = $1 OR articulo_id IS NULL)
+        AND cantidad_minima <= $2
+        AND CURRENT_DATE BETWEEN fecha_inicio AND fecha_fin
+        ORDER BY id",
+        PROMOCION_COLUMNS
+    ))
+    .bind(articulo_id)
+    .bind(cantidad)
+    .fetch_all(&mut *conn)
+    .await?;
+    for promocion in promociones {
+        if let Some(precio) = promocion.aplicar(precio_articulo) {
+            mejor.considerar(precio, REGLA_PROMOCION, promocion.id);
+        }
+    }
+
+    Ok(mejor)
+}
+
+pub async fn postgres_get_tarifas(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<Tarifa>, sqlx::Error> {
+    let tarifas = sqlx::query_as::<_, Tarifa>(
+        "SELECT id, nombre, descripcion, fecha_creacion FROM tarifas ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tarifas)
+}
+
+pub async fn postgres_get_tarifa_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Tarifa>, sqlx::Error> {
+    let tarifa = sqlx::query_as::<_, Tarifa>(
+        "SELECT id, nombre, descripcion, fecha_creacion FROM tarifas WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(tarifa)
+}
+
+pub async fn postgres_get_tarifa_precios(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tarifa_id: i32,
+) -> Result<Vec<TarifaPrecio>, sqlx::Error> {
+    let precios = sqlx::query_as::<_, TarifaPrecio>(
+        "
+        SELECT tp.tarifa_id, tp.articulo_id, a.nombre AS articulo_nombre, tp.precio, tp.moneda
+        FROM tarifas_precios tp
+        JOIN articulos a ON a.id = tp.articulo_id
+        WHERE tp.tarifa_id = $1
+        ORDER BY tp.articulo_id",
+    )
+    .bind(tarifa_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(precios)
+}
+
+pub async fn postgres_create_tarifa(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tarifa: TarifaRequest,
+) -> Result<Tarifa, sqlx::Error> {
+    let new_tarifa = sqlx::query_as::<_, Tarifa>(
+        "INSERT INTO tarifas (nombre, descripcion)
+        VALUES ($1, $2)
+        RETURNING id, nombre, descripcion, fecha_creacion",
+    )
+    .bind(tarifa.nombre)
+    .bind(tarifa.descripcion)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(new_tarifa)
+}
+
+// alta o cambio del precio de un artículo en la tarifa, None si alguno no existe
+pub async fn postgres_set_tarifa_precio(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tarifa_id: i32,
+    articulo_id: i32,
+    precio: Money,
+) -> Result<Option<TarifaPrecio>, sqlx::Error> {
+    let tarifa_precio = sqlx::query_as::<_, TarifaPrecio>(
+        "
+        WITH guardado AS (
+            INSERT INTO tarifas_precios (tarifa_id, articulo_id, precio, moneda)
+            SELECT t.id, a.id, $3, $4
+            FROM tarifas t, articulos a
+            WHERE t.id = $1 AND a.id = $2 AND a.deleted_at IS NULL
+            ON CONFLICT (tarifa_id, articulo_id)
+            DO UPDATE SET precio = EXCLUDED.precio, moneda = EXCLUDED.moneda
+            RETURNING tarifa_id, articulo_id, precio, moneda
+        )
+        SELECT g.tarifa_id, g.articulo_id, a.nombre AS articulo_nombre, g.precio, g.moneda
+        FROM guardado g
+        JOIN articulos a ON a.id = g.articulo_id",
+    )
+    .bind(tarifa_id)
+    .bind(articulo_id)
+    .bind(precio.amount_minor())
+    .bind(precio.currency().code())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(tarifa_precio)
+}
+
+pub async fn postgres_delete_tarifa_precio(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    tarifa_id: i32,
+    articulo_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result =
+        sqlx::query("DELETE FROM tarifas_precios WHERE tarifa_id = $1 AND articulo_id = $2")
+            .bind(tarifa_id)
+            .bind(articulo_id)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn postgres_get_promociones(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    solo_vigentes: bool,
+) -> Result<Vec<Promocion>, sqlx::Error> {
+    let promociones = sqlx::query_as::<_, Promocion>(&format!(
+        "
+        SELECT {}
+        FROM promociones
+        WHERE NOT $1 OR CURRENT_DATE BETWEEN fecha_inicio AND fecha_fin
+        ORDER BY id",
+        PROMOCION_COLUMNS
+    ))
+    .bind(solo_vigentes)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(promociones)
+}
+
+// None si la promoción es de un artículo que no existe
+pub async fn postgres_create_promocion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    promocion: PromocionRequest,
+) -> Result<Option<Promocion>, sqlx::Error> {
+    let new_promocion = sqlx::query_as::<_, Promocion>(&format!(
+        "
+        INSERT INTO promociones (
+            nombre, articulo_id, porcentaje, importe, moneda,
+            cantidad_minima, fecha_inicio, fecha_fin
+        )
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8
+        WHERE $2::INT IS NULL
+        OR EXISTS (SELECT 1 FROM articulos WHERE id = $2 AND deleted_at IS NULL)
+        RETURNING {}",
+        PROMOCION_COLUMNS
+    ))
+    .bind(promocion.nombre)
+    .bind(promocion.articulo_id)
+    .bind(promocion.porcentaje)
+    .bind(promocion.importe.map(|i| i.amount_minor()))
+    .bind(promocion.importe.map(|i| i.currency().code()))
+    .bind(promocion.cantidad_minima)
+    .bind(promocion.fecha_inicio)
+    .bind(promocion.fecha_fin)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(new_promocion)
+}
+
+pub async fn postgres_delete_promocion(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM promociones WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+    use crate::postgresini::pruebas::pool_de_pruebas;
+
+    fn eur(amount: i64) -> Money {
+        Money::from_minor(amount, Currency::Eur)
+    }
+
+    fn promocion(porcentaje: Option<i32>, importe: Option<Money>) -> Promocion {
+        let hoy = chrono::Utc::now().date_naive();
+        Promocion {
+            id: 1,
+            nombre: "promo".to_string(),
+            articulo_id: None,
+            porcentaje,
+            importe,
+            cantidad_minima: 1,
+            fecha_inicio: hoy,
+            fecha_fin: hoy,
+        }
+    }
+
+    #[test]
+    fn aplica_porcentajes_redondeando_la_rebaja() {
+        assert_eq!(
+            promocion(Some(1000), None).aplicar(eur(1_000)),
+            Some(eur(900))
+        );
+        // 12.5% de 0.99 = 0.12375 -> 0.12
+        assert_eq!(promocion(Some(1250), None).aplicar(eur(99)), Some(eur(87)));
+        assert_eq!(promocion(Some(10_000), None).aplicar(eur(99)), Some(eur(0)));
+    }
+
+    #[test]
+    fn aplica_importes_fijos_sin_bajar_de_cero() {
+        assert_eq!(
+            promocion(None, Some(eur(250))).aplicar(eur(1_000)),
+            Some(eur(750))
+        );
+        assert_eq!(
+            promocion(None, Some(eur(2_000))).aplicar(eur(1_000)),
+            Some(eur(0))
+        );
+        let dolares = Some(Money::from_minor(100, Currency::Usd));
+        assert_eq!(promocion(None, dolares).aplicar(eur(1_000)), None);
+        assert_eq!(promocion(None, None).aplicar(eur(1_000)), None);
+    }
+
+    #[test]
+    fn solo_considera_precios_mas_baratos_en_la_misma_moneda() {
+        let mut precio = PrecioAplicado::base(eur(1_000));
+        precio.considerar(eur(1_000), REGLA_TARIFA, 1);
+        assert_eq!(precio.regla_precio, REGLA_BASE);
+        precio.considerar(Money::from_minor(1, Currency::Usd), REGLA_TARIFA, 1);
+        assert_eq!(precio.regla_precio, REGLA_BASE);
+        precio.considerar(eur(900), REGLA_TARIFA, 1);
+        precio.considerar(eur(950), REGLA_PROMOCION, 2);
+        assert_eq!(precio.precio_unitario, eur(900));
+        assert_eq!(precio.regla_precio, REGLA_TARIFA);
+        assert_eq!(precio.regla_id, Some(1));
+    }
+
+    #[rocket::async_test]
+    #[ignore = "necesita TEST_DATABASE_URL"]
+    async fn mejor_precio_entre_tarifa_y_promociones() {
+        let (_guard, pool) = pool_de_pruebas().await;
+        let cliente_id: i32 =
+            sqlx::query_scalar("INSERT INTO clientes (nombre) VALUES ('Tienda') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let tarifa = postgres_create_tarifa(
+            &pool,
+            TarifaRequest {
+                nombre: "mayorista".to_string(),
+                descripcion: None,
+            },
+        )
+        .await
+        .unwrap();
+        postgres_set_tarifa_precio(&pool, tarifa.id, 1, eur(800))
+            .await
+            .unwrap()
+            .unwrap();
+        sqlx::query("UPDATE clientes SET tarifa_id = $1 WHERE id = $2")
+            .bind(tarifa.id)
+            .bind(cliente_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let hoy = chrono::Utc::now().date_naive();
+        let por_volumen = postgres_create_promocion(
+            &pool,
+            PromocionRequest {
+                nombre: "por volumen".to_string(),
+                articulo_id: Some(1),
+                porcentaje: Some(3000),
+                importe: None,
+                cantidad_minima: 10,
+                fecha_inicio: hoy,
+                fecha_fin: hoy,
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        postgres_create_promocion(
+            &pool,
+            PromocionRequest {
+                nombre: "caducada".to_string(),
+                articulo_id: None,
+                porcentaje: Some(9000),
+                importe: None,
+                cantidad_minima: 1,
+                fecha_inicio: hoy - chrono::Duration::days(10),
+                fecha_fin: hoy - chrono::Duration::days(1),
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        let precio = mejor_precio(&mut conn, cliente_id, 1, 1, eur(1_000))
+            .await
+            .unwrap();
+        assert_eq!(precio.precio_unitario, eur(800));
+        assert_eq!(precio.regla_precio, REGLA_TARIFA);
+        assert_eq!(precio.regla_id, Some(tarifa.id));
+
+        // la promoción se calcula sobre el precio del artículo, no sobre la tarifa
+        let precio = mejor_precio(&mut conn, cliente_id, 1, 10, eur(1_000))
+            .await
+            .unwrap();
+        assert_eq!(precio.precio_unitario, eur(700));
+        assert_eq!(precio.regla_precio, REGLA_PROMOCION);
+        assert_eq!(precio.regla_id, Some(por_volumen.id));
+    }
+}
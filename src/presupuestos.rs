@@ -0,0 +1,497 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
+use validator::Validate;
+
+use crate::iva::DesgloseIva;
+use crate::money::{Money, MoneyError, money_from_row};
+use crate::pedidos::{self, LineaPedido, Pedido, PedidoError};
+use crate::precios::{self, PrecioAplicado};
+
+pub const ESTADO_BORRADOR: &str = "borrador";
+pub const ESTADO_ENVIADO: &str = "enviado";
+pub const ESTADO_ACEPTADO: &str = "aceptado";
+pub const ESTADO_RECHAZADO: &str = "rechazado";
+pub const ESTADO_CADUCADO: &str = "caducado";
+
+// días de validez si el presupuesto no indica fecha
+const DIAS_VALIDEZ_POR_DEFECTO: i32 = 30;
+
+// borrador -> enviado -> aceptado | rechazado | caducado; un borrador también se puede descartar
+pub fn transicion_valida(desde: &str, hasta: &str) -> bool {
+    matches!(
+        (desde, hasta),
+        (ESTADO_BORRADOR, ESTADO_ENVIADO)
+            | (ESTADO_BORRADOR, ESTADO_RECHAZADO)
+            | (ESTADO_ENVIADO, ESTADO_ACEPTADO)
+            | (ESTADO_ENVIADO, ESTADO_RECHAZADO)
+            | (ESTADO_ENVIADO, ESTADO_CADUCADO)
+    )
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PresupuestoLineaRequest {
+    pub articulo_id: i32,
+    #[validate(range(min = 1, message = "debe ser al menos 1"))]
+    pub cantidad: i32,
+    // en puntos básicos, 1000 = 10%
+    #[serde(default)]
+    #[validate(range(min = 0, max = 10000, message = "debe estar entre 0 y 10000"))]
+    pub descuento: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PresupuestoRequest {
+    pub cliente_id: i32,
+    pub fecha_validez: Option<chrono::NaiveDate>,
+    #[validate(
+        length(min = 1, message = "el presupuesto necesita al menos una línea"),
+        nested
+    )]
+    pub lineas: Vec<PresupuestoLineaRequest>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PresupuestoEstadoRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 20, message = "entre 1 y 20 caracteres"))]
+    pub estado: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Presupuesto {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub fecha_creacion: chrono::NaiveDateTime,
+    pub fecha_validez: chrono::NaiveDate,
+    pub estado: String,
+    pub pedido_id: Option<i32>,
+    pub base_imponible: Money,
+    pub cuota_iva: Money,
+    pub total: Money,
+}
+
+impl<'r> FromRow<'r, PgRow> for Presupuesto {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Presupuesto {
+            id: row.try_get("id")?,
+            cliente_id: row.try_get("cliente_id")?,
+            fecha_creacion: row.try_get("fecha_creacion")?,
+            fecha_validez: row.try_get("fecha_validez")?,
+            estado: row.try_get("estado")?,
+            pedido_id: row.try_get("pedido_id")?,
+            base_imponible: money_from_row(row, "base_imponible", "moneda")?,
+            cuota_iva: money_from_row(row, "cuota_iva", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+        })
+    }
+}
+
+// precio, tipo de IVA y descuento quedan fijados al crear el presupuesto
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PresupuestoLinea {
+    pub id: i32,
+    pub presupuesto_id: i32,
+    pub articulo_id: i32,
+    pub articulo_nombre: String,
+    pub cantidad: i32,
+    pub precio_unitario: Money,
+    pub regla_precio: String,
+    pub regla_id: Option<i32>,
+    pub descuento: i32,
+    pub subtotal: Money,
+    pub tipo_iva: String,
+    pub porcentaje_iva: i32,
+    pub cuota_iva: Money,
+    pub total: Money,
+}
+
+impl PresupuestoLinea {
+    pub fn desglose_iva(&self) -> DesgloseIva {
+        DesgloseIva {
+            tipo_iva: self.tipo_iva.clone(),
+            porcentaje_iva: self.porcentaje_iva,
+            base: self.subtotal,
+            cuota: self.cuota_iva,
+            total: self.total,
+        }
+    }
+}
+
+impl<'r> FromRow<'r, PgRow> for PresupuestoLinea {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(PresupuestoLinea {
+            id: row.try_get("id")?,
+            presupuesto_id: row.try_get("presupuesto_id")?,
+            articulo_id: row.try_get("articulo_id")?,
+            articulo_nombre: row.try_get("articulo_nombre")?,
+            cantidad: row.try_get("cantidad")?,
+            precio_unitario: money_from_row(row, "precio_unitario", "moneda")?,
+            regla_precio: row.try_get("regla_precio")?,
+            regla_id: row.try_get("regla_id")?,
+            descuento: row.try_get("descuento")?,
+            subtotal: money_from_row(row, "subtotal", "moneda")?,
+            tipo_iva: row.try_get("tipo_iva")?,
+            porcentaje_iva: row.try_get("porcentaje_iva")?,
+            cuota_iva: money_from_row(row, "cuota_iva", "moneda")?,
+            total: money_from_row(row, "total", "moneda")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PresupuestoError {
+    Sqlx(sqlx::Error),
+    Money(MoneyError),
+    Pedido(PedidoError),
+    ClienteNoEncontrado(i32),
+    ArticuloNoEncontrado(i32),
+    PresupuestoNoEncontrado(i32),
+    TransicionNoValida(String, String),
+    Caducado(i32),
+    NoAceptado(i32),
+    YaConvertido(i32),
+}
+
+impl fmt::Display for PresupuestoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresupuestoError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            PresupuestoError::Money(e) => write!(f, "{}", e),
+            PresupuestoError::Pedido(e) => write!(f, "{}", e),
+            PresupuestoError::ClienteNoEncontrado(id) => {
+                write!(f, "cliente {} no encontrado", id)
+            }
+            PresupuestoError::ArticuloNoEncontrado(id) => {
+                write!(f, "artículo {} no encontrado", id)
+            }
+            PresupuestoError::PresupuestoNoEncontrado(id) => {
+                write!(f, "presupuesto {} no encontrado", id)
+            }
+            PresupuestoError::TransicionNoValida(desde, hasta) => {
+                write!(f, "no se puede pasar de {} a {}", desde, hasta)
+            }
+            PresupuestoError::Caducado(id) => write!(f, "el presupuesto {} ha caducado", id),
+            PresupuestoError::NoAceptado(id) => {
+                write!(f, "el presupuesto {} no está aceptado", id)
+            }
+            PresupuestoError::YaConvertido(id) => {
+                write!(f, "el presupuesto {} ya se convirtió en pedido", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PresupuestoError {}
+
+impl From<sqlx::Error> for PresupuestoError {
+    fn from(e: sqlx::Error) -> Self {
+        PresupuestoError::Sqlx(e)
+    }
+}
+
+impl From<MoneyError> for PresupuestoError {
+    fn from(e: MoneyError) -> Self {
+        PresupuestoError::Money(e)
+    }
+}
+
+impl From<PedidoError> for PresupuestoError {
+    fn from(e: PedidoError) -> Self {
+        PresupuestoError::Pedido(e)
+    }
+}
+
+const PRESUPUESTO_COLUMNS: &str = "id, cliente_id, fecha_creacion, fecha_validez, estado, pedido_id, base_imponible, cuota_iva, total, moneda";
+
+pub async fn postgres_get_presupuesto_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Presupuesto>, sqlx::Error> {
+    let presupuesto = sqlx::query_as::<_, Presupuesto>(&format!(
+        "SELECT {} FROM presupuestos WHERE id = $1",
+        PRESUPUESTO_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(presupuesto)
+}
+
+pub async fn postgres_get_presupuesto_lineas(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    presupuesto_id: i32,
+) -> Result<Vec<PresupuestoLinea>, sqlx::Error> {
+    let lineas = sqlx::query_as::<_, PresupuestoLinea>(
+        "
+        SELECT
+            pl.id, pl.presupuesto_id, pl.articulo_id, a.nombre AS articulo_nombre, pl.cantidad,
+            pl.precio_unitario, pl.regla_precio, pl.regla_id, pl.descuento, pl.subtotal,
+            pl.tipo_iva, pl.porcentaje_iva, pl.cuota_iva, pl.total, p.moneda
+        FROM presupuestos_lineas pl
+        JOIN presupuestos p ON p.id = pl.presupuesto_id
+        JOIN articulos a ON a.id = pl.articulo_id
+        WHERE pl.presupuesto_id = $1
+        ORDER BY pl.id",
+    )
+    .bind(presupuesto_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(lineas)
+}
+
+// Crea el presupuesto en borrador con el mejor precio para el cliente y el IVA
+// vigente de cada artículo. No reserva stock: eso ocurre al convertirlo en pedido.
+pub async fn postgres_create_presupuesto(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    presupuesto: PresupuestoRequest,
+) -> Result<Presupuesto, PresupuestoError> {
+    let mut tx = pool.begin().await?;
+
+    let cliente_exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM clientes WHERE id = $1 AND deleted_at IS NULL)",
+    )
+    .bind(presupuesto.cliente_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !cliente_exists {
+        return Err(PresupuestoError::ClienteNoEncontrado(
+            presupuesto.cliente_id,
+        ));
+    }
+
+    let mut lineas = Vec::new();
+    for linea in &presupuesto.lineas {
+        let row = sqlx::query(
+            "
+            SELECT
+                a.precio, a.moneda, a.tipo_iva, t.porcentaje AS porcentaje_iva
+            FROM articulos a
+            JOIN tipos_iva t ON t.codigo = a.tipo_iva
+            WHERE a.id = $1 AND a.deleted_at IS NULL",
+        )
+        .bind(linea.articulo_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(PresupuestoError::ArticuloNoEncontrado(linea.articulo_id))?;
+
+        let precio = precios::mejor_precio(
+            &mut tx,
+            presupuesto.cliente_id,
+            linea.articulo_id,
+            linea.cantidad,
+            money_from_row(&row, "precio", "moneda")?,
+        )
+        .await?;
+        let tipo_iva: String = row.try_get("tipo_iva")?;
+        lineas.push(LineaPedido::calcular(
+            linea.articulo_id,
+            linea.cantidad,
+            precio,
+            linea.descuento,
+            &tipo_iva,
+            row.try_get("porcentaje_iva")?,
+        )?);
+    }
+
+    let moneda = lineas[0].precio.precio_unitario.currency();
+    let base_imponible = Money::sum(moneda, lineas.iter().map(|l| l.desglose.base))?;
+    let cuota_iva = Money::sum(moneda, lineas.iter().map(|l| l.desglose.cuota))?;
+    let total = Money::sum(moneda, lineas.iter().map(|l| l.desglose.total))?;
+
+    let new_presupuesto = sqlx::query_as::<_, Presupuesto>(&format!(
+        "
+        INSERT INTO presupuestos (
+            cliente_id, fecha_validez, base_imponible, cuota_iva, total, moneda
+        )
+        VALUES ($1, COALESCE($2, CURRENT_DATE + $3::INT), $4, $5, $6, $7)
+        RETURNING {}",
+        PRESUPUESTO_COLUMNS
+    ))
+    .bind(presupuesto.cliente_id)
+    .bind(presupuesto.fecha_validez)
+    .bind(DIAS_VALIDEZ_POR_DEFECTO)
+    .bind(base_imponible.amount_minor())
+    .bind(cuota_iva.amount_minor())
+    .bind(total.amount_minor())
+    .bind(moneda.code())
+    .fetch_one(&mut *tx)
+    .await?;
+
+    for linea in lineas {
+        sqlx::query(
+            "
+            INSERT INTO presupuestos_lineas (
+                presupuesto_id, articulo_id, cantidad, precio_unitario, regla_precio,
+                regla_id, descuento, subtotal, tipo_iva, porcentaje_iva, cuota_iva, total
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+        )
+        .bind(new_presupuesto.id)
+        .bind(linea.articulo_id)
+        .bind(linea.cantidad)
+        .bind(linea.precio.precio_unitario.amount_minor())
+        .bind(&linea.precio.regla_precio)
+        .bind(linea.precio.regla_id)
+        .bind(linea.descuento)
+        .bind(linea.desglose.base.amount_minor())
+        .bind(&linea.desglose.tipo_iva)
+        .bind(linea.desglose.porcentaje_iva)
+        .bind(linea.desglose.cuota.amount_minor())
+        .bind(linea.desglose.total.amount_minor())
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(new_presupuesto)
+}
+
+// cambia el estado; no se acepta un presupuesto pasada su fecha de validez
+pub async fn postgres_update_presupuesto_estado(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    estado: &str,
+) -> Result<Presupuesto, PresupuestoError> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query(
+        "
+        SELECT estado, fecha_validez < CURRENT_DATE AS caducado
+        FROM presupuestos
+        WHERE id = $1
+        FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(PresupuestoError::PresupuestoNoEncontrado(id))?;
+
+    let estado_actual: String = row.try_get("estado")?;
+    if !transicion_valida(&estado_actual, estado) {
+        return Err(PresupuestoError::TransicionNoValida(
+            estado_actual,
+            estado.to_string(),
+        ));
+    }
+
+    let caducado: bool = row.try_get("caducado")?;
+    if estado == ESTADO_ACEPTADO && caducado {
+        return Err(PresupuestoError::Caducado(id));
+    }
+
+    let presupuesto = sqlx::query_as::<_, Presupuesto>(&format!(
+        "UPDATE presupuestos SET estado = $1 WHERE id = $2 RETURNING {}",
+        PRESUPUESTO_COLUMNS
+    ))
+    .bind(estado)
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(presupuesto)
+}
+
+// Convierte un presupuesto aceptado en pedido con las mismas líneas y los
+// precios del presupuesto. Descuenta stock igual que un pedido nuevo.
+pub async fn postgres_convert_presupuesto(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Pedido, PresupuestoError> {
+    let mut tx = pool.begin().await?;
+
+    let presupuesto = sqlx::query_as::<_, Presupuesto>(&format!(
+        "SELECT {} FROM presupuestos WHERE id = $1 FOR UPDATE",
+        PRESUPUESTO_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(PresupuestoError::PresupuestoNoEncontrado(id))?;
+
+    if presupuesto.pedido_id.is_some() {
+        return Err(PresupuestoError::YaConvertido(id));
+    }
+    if presupuesto.estado != ESTADO_ACEPTADO {
+        return Err(PresupuestoError::NoAceptado(id));
+    }
+
+    let cliente_exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM clientes WHERE id = $1 AND deleted_at IS NULL)",
+    )
+    .bind(presupuesto.cliente_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !cliente_exists {
+        return Err(PresupuestoError::ClienteNoEncontrado(
+            presupuesto.cliente_id,
+        ));
+    }
+
+    let lineas = sqlx::query(
+        "
+        SELECT
+            articulo_id, cantidad, precio_unitario, regla_precio, regla_id, descuento,
+            subtotal, tipo_iva, porcentaje_iva, cuota_iva, total
+        FROM presupuestos_lineas
+        WHERE presupuesto_id = $1
+        ORDER BY id",
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let moneda = presupuesto.total.currency().code();
+    let mut lineas_pedido = Vec::new();
+    for linea in &lineas {
+        let articulo_id: i32 = linea.try_get("articulo_id")?;
+        let cantidad: i32 = linea.try_get("cantidad")?;
+        pedidos::descontar_stock(&mut tx, articulo_id, cantidad).await?;
+
+        // importes copiados tal cual, sin recalcular con los precios actuales
+        lineas_pedido.push(LineaPedido {
+            articulo_id,
+            cantidad,
+            precio: PrecioAplicado {
+                precio_unitario: Money::from_db(linea.try_get("precio_unitario")?, moneda)?,
+                regla_precio: linea.try_get("regla_precio")?,
+                regla_id: linea.try_get("regla_id")?,
+            },
+            descuento: linea.try_get("descuento")?,
+            desglose: DesgloseIva {
+                tipo_iva: linea.try_get("tipo_iva")?,
+                porcentaje_iva: linea.try_get("porcentaje_iva")?,
+                base: Money::from_db(linea.try_get("subtotal")?, moneda)?,
+                cuota: Money::from_db(linea.try_get("cuota_iva")?, moneda)?,
+                total: Money::from_db(linea.try_get("total")?, moneda)?,
+            },
+        });
+    }
+
+    // el pedido sale con la dirección de envío predeterminada del cliente
+    let direccion_envio = pedidos::direccion_envio(&mut tx, presupuesto.cliente_id, None).await?;
+    let pedido = pedidos::insertar_pedido(
+        &mut tx,
+        presupuesto.cliente_id,
+        direccion_envio,
+        lineas_pedido,
+    )
+    .await?;
+
+    sqlx::query("UPDATE presupuestos SET pedido_id = $1 WHERE id = $2")
+        .bind(pedido.id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(pedido)
+}
@@ -0,0 +1,399 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::clientes::{CLIENTE_COLUMNS, Cliente};
+use crate::duplicados::sql_ids_fusionados;
+
+pub const SOLICITUD_EXPORTACION: &str = "exportacion";
+pub const SOLICITUD_SUPRESION: &str = "supresion";
+
+// Todo lo que el CRM guarda de un cliente, tabla a tabla, con las filas tal
+// cual están en la base de datos.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Exportacion {
+    pub generado: chrono::NaiveDateTime,
+    pub cliente: serde_json::Value,
+    pub datos: serde_json::Map<String, serde_json::Value>,
+}
+
+// registro de las solicitudes atendidas, para poder demostrarlo
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct SolicitudRgpd {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub tipo: String,
+    pub user_id: i32,
+    pub fecha: chrono::NaiveDateTime,
+}
+
+// (clave en la exportación, filas como jsonb). $1 son los ids del cliente: el
+// suyo primero y después los de los clientes que se fusionaron en él, cuyas
+// filas (facturas, consentimientos, historial...) siguen siendo suyas.
+const CONSULTAS_EXPORTACION: [(&str, &str); 22] = [
+    (
+        "direcciones",
+        "SELECT to_jsonb(t) FROM direcciones t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "contactos",
+        "SELECT to_jsonb(t) FROM contactos t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "etiquetas",
+        "SELECT to_jsonb(t) FROM clientes_etiquetas t WHERE cliente_id = ANY($1) ORDER BY etiqueta",
+    ),
+    (
+        "consentimientos",
+        "SELECT to_jsonb(t) FROM consentimientos t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "pedidos",
+        "SELECT to_jsonb(t) FROM pedidos t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "pedidos_detalles",
+        "SELECT to_jsonb(t) FROM pedidos_detalles t
+        JOIN pedidos p ON p.id = t.pedido_id WHERE p.cliente_id = ANY($1) ORDER BY t.id",
+    ),
+    (
+        "pedidos_historial",
+        "SELECT to_jsonb(t) FROM pedidos_historial t
+        JOIN pedidos p ON p.id = t.pedido_id WHERE p.cliente_id = ANY($1) ORDER BY t.id",
+    ),
+    (
+        "presupuestos",
+        "SELECT to_jsonb(t) FROM presupuestos t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "presupuestos_lineas",
+        "SELECT to_jsonb(t) FROM presupuestos_lineas t
+        JOIN presupuestos p ON p.id = t.presupuesto_id WHERE p.cliente_id = ANY($1) ORDER BY t.id",
+    ),
+    (
+        "facturas",
+        "SELECT to_jsonb(t) FROM facturas t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "facturas_lineas",
+        "SELECT to_jsonb(t) FROM facturas_lineas t
+        JOIN facturas f ON f.id = t.factura_id WHERE f.cliente_id = ANY($1) ORDER BY t.id",
+    ),
+    (
+        "pagos",
+        "SELECT to_jsonb(t) FROM pagos t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "issue_requests",
+        "SELECT to_jsonb(t) FROM issue_request t
+        JOIN issue_request_clientes irc ON irc.issue_request_id = t.id
+        WHERE irc.cliente_id = ANY($1) ORDER BY t.id",
+    ),
+    (
+        "interacciones",
+        "SELECT to_jsonb(t) FROM interacciones t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "tareas",
+        "SELECT to_jsonb(t) FROM tareas t
+        JOIN tareas_clientes tc ON tc.tarea_id = t.id WHERE tc.cliente_id = ANY($1) ORDER BY t.id",
+    ),
+    (
+        "leads",
+        "SELECT to_jsonb(t) FROM leads t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "oportunidades",
+        "SELECT to_jsonb(t) FROM oportunidades t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "historial",
+        "SELECT to_jsonb(t) FROM clientes_historial t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "corp",
+        "SELECT to_jsonb(t) FROM corp_usuarios t
+        JOIN clientes c ON c.user_id = t.user_id WHERE c.id = ANY($1)",
+    ),
+    (
+        "corp_conflictos",
+        "SELECT to_jsonb(t) FROM corp_conflictos t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+    (
+        "fusionados",
+        "SELECT to_jsonb(t) FROM clientes t WHERE id = ANY($1[2:]) ORDER BY id",
+    ),
+    (
+        "solicitudes_rgpd",
+        "SELECT to_jsonb(t) FROM rgpd_solicitudes t WHERE cliente_id = ANY($1) ORDER BY id",
+    ),
+];
+
+// el cliente y los fusionados en él, también en fusiones encadenadas; el suyo primero
+async fn ids_cliente(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<i32>, sqlx::Error> {
+    let fusionados: Vec<i32> = sqlx::query_scalar(&format!(
+        "SELECT id FROM ({}) ids WHERE id <> $1 ORDER BY id",
+        sql_ids_fusionados("$1")
+    ))
+    .bind(cliente_id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(std::iter::once(cliente_id).chain(fusionados).collect())
+}
+
+async fn registrar_solicitud(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    cliente_id: i32,
+    tipo: &str,
+    user_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO rgpd_solicitudes (cliente_id, tipo, user_id) VALUES ($1, $2, $3)")
+        .bind(cliente_id)
+        .bind(tipo)
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn postgres_get_solicitudes_rgpd(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+) -> Result<Vec<SolicitudRgpd>, sqlx::Error> {
+    let solicitudes = sqlx::query_as::<_, SolicitudRgpd>(
+        "SELECT id, cliente_id, tipo, user_id, fecha
+        FROM rgpd_solicitudes
+        WHERE cliente_id = $1
+        ORDER BY fecha DESC, id DESC",
+    )
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(solicitudes)
+}
+
+// La exportación queda registrada; se lee en la misma transacción para que
+// todas las tablas se vean en el mismo instante.
+pub async fn postgres_exportar_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    user_id: i32,
+) -> Result<Option<Exportacion>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut *tx)
+        .await?;
+
+    let cliente: Option<serde_json::Value> =
+        sqlx::query_scalar("SELECT to_jsonb(c) FROM clientes c WHERE id = $1")
+            .bind(cliente_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let Some(cliente) = cliente else {
+        return Ok(None);
+    };
+
+    registrar_solicitud(&mut tx, cliente_id, SOLICITUD_EXPORTACION, user_id).await?;
+
+    let ids = ids_cliente(&mut tx, cliente_id).await?;
+    let mut datos = serde_json::Map::new();
+    for (clave, consulta) in CONSULTAS_EXPORTACION {
+        let filas: Vec<serde_json::Value> = sqlx::query_scalar(consulta)
+            .bind(&ids)
+            .fetch_all(&mut *tx)
+            .await?;
+        datos.insert(clave.to_string(), serde_json::Value::Array(filas));
+    }
+
+    tx.commit().await?;
+
+    Ok(Some(Exportacion {
+        generado: chrono::Utc::now().naive_utc(),
+        cliente,
+        datos,
+    }))
+}
+
+// Derecho de supresión: borra o vacía los datos personales y deja el cliente
+// borrado, junto con los clientes que se fusionaron en él. Facturas, pedidos,
+// presupuestos y pagos se conservan porque hay obligación legal de guardarlos;
+// las facturas mantienen los datos fiscales con los que se emitieron. None si
+// el cliente no existe.
+pub async fn postgres_anonimizar_cliente(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    user_id: i32,
+) -> Result<Option<Cliente>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // copia del directorio corporativo, antes de desvincular la cuenta de auth
+    sqlx::query(
+        "DELETE FROM corp_usuarios WHERE user_id = (SELECT user_id FROM clientes WHERE id = $1)",
+    )
+    .bind(cliente_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let ids = ids_cliente(&mut tx, cliente_id).await?;
+
+    // el email es único y obligatorio: se sustituye por uno que no existe
+    let clientes = sqlx::query_as::<_, Cliente>(&format!(
+        "
+        UPDATE clientes
+        SET nombre = 'Anonimizado', email = 'anonimizado-' || id || '@invalid',
+            telefono = NULL, direccion = NULL, user_id = NULL,
+            deleted_at = COALESCE(deleted_at, CURRENT_TIMESTAMP)
+        WHERE id = ANY($1)
+        RETURNING {}",
+        CLIENTE_COLUMNS
+    ))
+    .bind(&ids)
+    .fetch_all(&mut *tx)
+    .await?;
+    let Some(cliente) = clientes.into_iter().find(|c| c.id == cliente_id) else {
+        return Ok(None);
+    };
+
+    for tabla in [
+        "direcciones",
+        "contactos",
+        "interacciones",
+        "corp_conflictos",
+    ] {
+        sqlx::query(&format!("DELETE FROM {} WHERE cliente_id = ANY($1)", tabla))
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    // los pedidos se conservan, pero la dirección de envío copiada es un dato
+    // personal y no fiscal
+    sqlx::query("UPDATE pedidos SET direccion_envio = NULL WHERE cliente_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+
+    // el historial guarda los valores anteriores, también los de este UPDATE
+    sqlx::query("DELETE FROM clientes_historial WHERE cliente_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "
+        UPDATE leads
+        SET nombre = 'Anonimizado', email = NULL, telefono = NULL, empresa = NULL, notas = NULL
+        WHERE cliente_id = ANY($1)",
+    )
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+
+    // las incidencias pueden estar vinculadas también a artículos y pedidos: se
+    // conservan sin su contenido
+    sqlx::query(
+        "
+        UPDATE issue_request
+        SET data = jsonb_build_object('anonimizado', TRUE)
+        WHERE id IN (
+            SELECT issue_request_id FROM issue_request_clientes WHERE cliente_id = ANY($1)
+        )",
+    )
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+
+    // copia de la fila del duplicado guardada al fusionar
+    sqlx::query(
+        "
+        UPDATE clientes_fusiones
+        SET fusionado = jsonb_build_object('id', fusionado_id)
+        WHERE cliente_id = ANY($1) OR fusionado_id = ANY($1)",
+    )
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+
+    registrar_solicitud(&mut tx, cliente_id, SOLICITUD_SUPRESION, user_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Some(cliente))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duplicados::postgres_fusionar_clientes;
+    use crate::postgresini::pruebas::pool_de_pruebas;
+
+    async fn crear_cliente(
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        nombre: &str,
+        email: &str,
+        telefono: &str,
+    ) -> i32 {
+        sqlx::query_scalar(
+            "INSERT INTO clientes (nombre, email, telefono) VALUES ($1, $2, $3) RETURNING id",
+        )
+        .bind(nombre)
+        .bind(email)
+        .bind(telefono)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[rocket::async_test]
+    #[ignore = "necesita TEST_DATABASE_URL"]
+    async fn supresion_alcanza_a_los_clientes_fusionados() {
+        let (_guard, pool) = pool_de_pruebas().await;
+        let cliente = crear_cliente(&pool, "Luis Martín", "luis@example.com", "600000001").await;
+        let duplicado =
+            crear_cliente(&pool, "Luis Martin", "lmartin@example.com", "600000002").await;
+        let encadenado =
+            crear_cliente(&pool, "L. Martín", "l.martin@example.com", "600000003").await;
+        postgres_fusionar_clientes(&pool, duplicado, encadenado, 1)
+            .await
+            .unwrap();
+        postgres_fusionar_clientes(&pool, cliente, duplicado, 1)
+            .await
+            .unwrap();
+
+        let exportacion = postgres_exportar_cliente(&pool, cliente, 1)
+            .await
+            .unwrap()
+            .unwrap();
+        let fusionados = exportacion.datos["fusionados"].as_array().unwrap();
+        assert_eq!(fusionados.len(), 2);
+        assert_eq!(fusionados[0]["email"], "lmartin@example.com");
+
+        postgres_anonimizar_cliente(&pool, cliente, 1)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let restos: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM clientes
+            WHERE id = ANY($1) AND (nombre <> 'Anonimizado' OR telefono IS NOT NULL)",
+        )
+        .bind(vec![cliente, duplicado, encadenado])
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(restos, 0);
+        let historial: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM clientes_historial WHERE cliente_id = ANY($1)",
+        )
+        .bind(vec![cliente, duplicado, encadenado])
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(historial, 0);
+    }
+}
@@ -0,0 +1,302 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::{FromRow, Row};
+use validator::{Validate, ValidationError};
+
+use crate::clientes::{CLIENTE_COLUMNS, Cliente};
+use crate::duplicados::sql_ids_fusionados;
+use crate::money::Money;
+use crate::paginacion::Paginacion;
+use crate::pedidos::ESTADO_CANCELADO;
+
+pub const POR_PAGINA_POR_DEFECTO: i64 = 50;
+pub const POR_PAGINA_MAXIMO: i64 = 200;
+
+// Condiciones que tiene que cumplir el cliente; un segmento exige todas (AND).
+// En JSON: {"tipo": "gasto_minimo", "importe": {...}, "dias": 90}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "tipo", rename_all = "snake_case")]
+pub enum ReglaSegmento {
+    // gasto en pedidos no cancelados de esa moneda en los últimos `dias` días mayor que `importe`
+    GastoMinimo {
+        importe: Money,
+        dias: i32,
+    },
+    // issue_request no guarda estado: cuenta toda solicitud vinculada al cliente
+    IssueAbierta,
+    Etiqueta {
+        #[serde(deserialize_with = "crate::etiquetas::normalizada")]
+        etiqueta: String,
+    },
+    RegistradoAntes {
+        fecha: chrono::NaiveDate,
+    },
+    // último consentimiento del canal otorgado; sin registros no hay consentimiento
+    Consentimiento {
+        canal: String,
+    },
+}
+
+impl ReglaSegmento {
+    fn validar(&self) -> Result<(), ValidationError> {
+        match self {
+            ReglaSegmento::GastoMinimo { importe, dias } => {
+                crate::validacion::validate_non_negative_money(importe)?;
+                if !(1..=3650).contains(dias) {
+                    return Err(
+                        ValidationError::new("dias").with_message("dias entre 1 y 3650".into())
+                    );
+                }
+                Ok(())
+            }
+            ReglaSegmento::Etiqueta { etiqueta } => crate::etiquetas::validate_etiqueta(etiqueta),
+            ReglaSegmento::Consentimiento { canal } => {
+                crate::consentimientos::validate_canal(canal)
+            }
+            ReglaSegmento::IssueAbierta | ReglaSegmento::RegistradoAntes { .. } => Ok(()),
+        }
+    }
+
+    // condición sobre la fila de clientes de la consulta exterior
+    fn push_condicion(&self, query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>) {
+        match self {
+            ReglaSegmento::GastoMinimo { importe, dias } => {
+                query.push(
+                    "(SELECT COALESCE(SUM(p.total), 0) FROM pedidos p
+                    WHERE p.cliente_id = clientes.id AND p.estado <> ",
+                );
+                query.push_bind(ESTADO_CANCELADO);
+                query.push(" AND p.moneda = ");
+                query.push_bind(importe.currency().code());
+                query.push(" AND p.fecha_pedido >= CURRENT_TIMESTAMP - make_interval(days => ");
+                query.push_bind(*dias);
+                query.push(")) > ");
+                query.push_bind(importe.amount_minor());
+            }
+            ReglaSegmento::IssueAbierta => {
+                query.push(
+                    "EXISTS (SELECT 1 FROM issue_request_clientes irc
+                    WHERE irc.cliente_id = clientes.id)",
+                );
+            }
+            ReglaSegmento::Etiqueta { etiqueta } => {
+                query.push(
+                    "EXISTS (SELECT 1 FROM clientes_etiquetas ce
+                    WHERE ce.cliente_id = clientes.id AND ce.etiqueta = ",
+                );
+                query.push_bind(etiqueta.clone());
+                query.push(")");
+            }
+            ReglaSegmento::RegistradoAntes { fecha } => {
+                query.push("clientes.fecha_registro < ");
+                query.push_bind(*fecha);
+            }
+            // el último registro del canal entre el cliente y los fusionados en él
+            ReglaSegmento::Consentimiento { canal } => {
+                query.push(format!(
+                    "COALESCE((SELECT co.otorgado FROM consentimientos co
+                    WHERE co.cliente_id IN ({}) AND co.canal = ",
+                    sql_ids_fusionados("clientes.id")
+                ));
+                query.push_bind(canal.clone());
+                query.push(" ORDER BY co.fecha DESC, co.id DESC LIMIT 1), FALSE)");
+            }
+        }
+    }
+}
+
+fn validate_reglas(reglas: &[ReglaSegmento]) -> Result<(), ValidationError> {
+    if reglas.is_empty() {
+        return Err(ValidationError::new("reglas")
+            .with_message("el segmento necesita al menos una regla".into()));
+    }
+    reglas.iter().try_for_each(ReglaSegmento::validar)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct SegmentoRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 100, message = "entre 1 y 100 caracteres"))]
+    pub nombre: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 1000, message = "máximo 1000 caracteres"))]
+    pub descripcion: Option<String>,
+    #[validate(custom(function = "validate_reglas"))]
+    pub reglas: Vec<ReglaSegmento>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Segmento {
+    pub id: i32,
+    pub nombre: String,
+    pub descripcion: Option<String>,
+    pub reglas: Vec<ReglaSegmento>,
+    pub creador_user_id: i32,
+    pub fecha_creacion: chrono::NaiveDateTime,
+    pub fecha_modificacion: Option<chrono::NaiveDateTime>,
+}
+
+impl<'r> FromRow<'r, PgRow> for Segmento {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Segmento {
+            id: row.try_get("id")?,
+            nombre: row.try_get("nombre")?,
+            descripcion: row.try_get("descripcion")?,
+            reglas: row.try_get::<Json<Vec<ReglaSegmento>>, _>("reglas")?.0,
+            creador_user_id: row.try_get("creador_user_id")?,
+            fecha_creacion: row.try_get("fecha_creacion")?,
+            fecha_modificacion: row.try_get("fecha_modificacion")?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ClientesSegmento {
+    pub segmento_id: i32,
+    pub clientes: Vec<Cliente>,
+    pub pagina: i64,
+    pub por_pagina: i64,
+    pub total: i64,
+}
+
+const SEGMENTO_COLUMNS: &str =
+    "id, nombre, descripcion, reglas, creador_user_id, fecha_creacion, fecha_modificacion";
+
+pub async fn postgres_get_segmentos(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<Segmento>, sqlx::Error> {
+    let segmentos = sqlx::query_as::<_, Segmento>(&format!(
+        "SELECT {} FROM segmentos ORDER BY nombre",
+        SEGMENTO_COLUMNS
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(segmentos)
+}
+
+pub async fn postgres_get_segmento_by_id(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<Option<Segmento>, sqlx::Error> {
+    let segmento = sqlx::query_as::<_, Segmento>(&format!(
+        "SELECT {} FROM segmentos WHERE id = $1",
+        SEGMENTO_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(segmento)
+}
+
+// el nombre es único: un duplicado falla con unique violation
+pub async fn postgres_create_segmento(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    creador_user_id: i32,
+    segmento: SegmentoRequest,
+) -> Result<Segmento, sqlx::Error> {
+    let new_segmento = sqlx::query_as::<_, Segmento>(&format!(
+        "
+        INSERT INTO segmentos (nombre, descripcion, reglas, creador_user_id)
+        VALUES ($1, $2, $3, $4)
+        RETURNING {}",
+        SEGMENTO_COLUMNS
+    ))
+    .bind(segmento.nombre)
+    .bind(segmento.descripcion)
+    .bind(Json(segmento.reglas))
+    .bind(creador_user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(new_segmento)
+}
+
+pub async fn postgres_update_segmento(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    segmento: SegmentoRequest,
+) -> Result<Option<Segmento>, sqlx::Error> {
+    let updated_segmento = sqlx::query_as::<_, Segmento>(&format!(
+        "
+        UPDATE segmentos
+        SET nombre = $1, descripcion = $2, reglas = $3, fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $4
+        RETURNING {}",
+        SEGMENTO_COLUMNS
+    ))
+    .bind(segmento.nombre)
+    .bind(segmento.descripcion)
+    .bind(Json(segmento.reglas))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(updated_segmento)
+}
+
+pub async fn postgres_delete_segmento(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM segmentos WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// SELECT sobre los clientes activos que cumplen todas las reglas
+fn consulta_segmento<'a>(
+    select: &str,
+    reglas: &'a [ReglaSegmento],
+) -> sqlx::QueryBuilder<'a, sqlx::Postgres> {
+    let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new(format!(
+        "SELECT {} FROM clientes WHERE clientes.deleted_at IS NULL",
+        select
+    ));
+    for regla in reglas {
+        query.push(" AND ");
+        regla.push_condicion(&mut query);
+    }
+    query
+}
+
+// Clientes del segmento evaluado ahora, por id; pagina empieza en 1. Con canal
+// solo se devuelven los que tienen el consentimiento de ese canal.
+pub async fn postgres_get_clientes_segmento(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    segmento: &Segmento,
+    canal: Option<&str>,
+    paginacion: &Paginacion,
+) -> Result<ClientesSegmento, sqlx::Error> {
+    let mut reglas = segmento.reglas.clone();
+    if let Some(canal) = canal {
+        reglas.push(ReglaSegmento::Consentimiento {
+            canal: canal.to_string(),
+        });
+    }
+
+    let total: i64 = consulta_segmento("COUNT(*)", &reglas)
+        .build_query_scalar()
+        .fetch_one(pool)
+        .await?;
+
+    let mut query = consulta_segmento(CLIENTE_COLUMNS, &reglas);
+    query.push(" ORDER BY clientes.id LIMIT ");
+    query.push_bind(paginacion.por_pagina());
+    query.push(" OFFSET ");
+    query.push_bind(paginacion.offset());
+    let clientes = query.build_query_as::<Cliente>().fetch_all(pool).await?;
+
+    Ok(ClientesSegmento {
+        segmento_id: segmento.id,
+        clientes,
+        pagina: paginacion.pagina(),
+        por_pagina: paginacion.por_pagina(),
+        total,
+    })
+}
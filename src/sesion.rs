@@ -1,7 +1,10 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use redis::AsyncCommands;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
 use rocket::serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthProfile {
@@ -12,31 +15,100 @@ pub struct AuthProfile {
 }
 
 const SESSION_TOKEN_KEY: &str = "session-token:";
+const REVOKED_TOKEN_KEY: &str = "revoked-token:";
+
+// el token no se guarda en claro: quien lea redis no puede usarlo
+fn token_key(prefijo: &str, token: &str) -> String {
+    format!(
+        "{}{}",
+        prefijo,
+        hex::encode(Sha256::digest(token.as_bytes()))
+    )
+}
+
+// Conexión compartida por toda la aplicación; se reconecta sola si redis cae.
+// Con redis caído cada comando falla en menos de un segundo en vez de quedarse
+// esperando: factor y max_delay acotan la espera entre reintentos.
+pub async fn redis_connection_manager(
+    redis_connection_string: &str,
+) -> redis::RedisResult<ConnectionManager> {
+    let client = redis::Client::open(redis_connection_string)?;
+    let config = ConnectionManagerConfig::new()
+        .set_number_of_retries(1)
+        .set_factor(2)
+        .set_max_delay(500)
+        .set_connection_timeout(Duration::from_secs(1))
+        .set_response_timeout(Duration::from_secs(1));
+    ConnectionManager::new_with_config(client, config).await
+}
+
+// una entrada corrupta se borra y cuenta como sesión inexistente
 pub async fn redis_get_session_by_token(
-    client: &redis::Client,
+    con: &ConnectionManager,
     token: &str,
 ) -> redis::RedisResult<Option<AuthProfile>> {
-    let key = format!("{}:{}", SESSION_TOKEN_KEY, token);
+    let key = token_key(SESSION_TOKEN_KEY, token);
+    let mut con = con.clone();
 
-    let mut con = client.get_multiplexed_async_connection().await.unwrap();
     let session_json: Option<String> = con.get(&key).await?;
-    let session: Option<AuthProfile> = match session_json {
-        Some(session_json) => Some(serde_json::from_str(&session_json).unwrap()),
-        None => None,
+    let Some(session_json) = session_json else {
+        return Ok(None);
     };
-    Ok(session)
+
+    match serde_json::from_str(&session_json) {
+        Ok(session) => Ok(Some(session)),
+        Err(e) => {
+            eprintln!("Discarding corrupt session entry: {:?}", e);
+            let _: () = con.del(&key).await?;
+            Ok(None)
+        }
+    }
 }
 
 pub async fn redis_set_session_by_token(
-    client: &redis::Client,
+    con: &ConnectionManager,
     token: &str,
     session: &AuthProfile,
-    auth_redis_ttl: i64,
+    auth_redis_ttl: u64,
 ) -> redis::RedisResult<()> {
-    let key = format!("{}:{}", SESSION_TOKEN_KEY, token);
-    let mut con = client.get_multiplexed_async_connection().await.unwrap();
-    let session_json = serde_json::to_string(session).unwrap();
-    let _: () = con.set(&key, session_json).await?;
-    let _: () = con.expire(&key, auth_redis_ttl).await?;
+    let key = token_key(SESSION_TOKEN_KEY, token);
+    let mut con = con.clone();
+
+    let session_json = serde_json::to_string(session).map_err(|e| {
+        redis::RedisError::from((
+            redis::ErrorKind::TypeError,
+            "Error serializing session",
+            e.to_string(),
+        ))
+    })?;
+    // SET ... EX: la sesión nunca queda guardada sin caducidad
+    let _: () = con.set_ex(&key, session_json, auth_redis_ttl).await?;
     Ok(())
 }
+
+// borra la sesión cacheada y apunta el token como revocado en una sola operación;
+// revoked_ttl debe cubrir la vida de los tokens del servidor de auth
+pub async fn redis_revoke_token(
+    con: &ConnectionManager,
+    token: &str,
+    revoked_ttl: u64,
+) -> redis::RedisResult<()> {
+    let mut con = con.clone();
+    let _: () = redis::pipe()
+        .atomic()
+        .del(token_key(SESSION_TOKEN_KEY, token))
+        .ignore()
+        .set_ex(token_key(REVOKED_TOKEN_KEY, token), 1, revoked_ttl)
+        .ignore()
+        .query_async(&mut con)
+        .await?;
+    Ok(())
+}
+
+pub async fn redis_is_token_revoked(
+    con: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<bool> {
+    let mut con = con.clone();
+    con.exists(token_key(REVOKED_TOKEN_KEY, token)).await
+}
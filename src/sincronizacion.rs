@@ -0,0 +1,474 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::Json;
+
+use crate::clientes::{CLIENTE_COLUMNS, Cliente};
+use crate::corpcache::CorpCache;
+use crate::corpservice::UserData;
+
+pub const CAMPO_NOMBRE: &str = "nombre";
+pub const CAMPO_EMAIL: &str = "email";
+pub const CAMPO_TELEFONO: &str = "telefono";
+
+// quién gana cuando el cliente y el directorio corporativo no coinciden
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Precedencia {
+    Corp,
+    Local,
+}
+
+impl Precedencia {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Precedencia::Corp => "corp",
+            Precedencia::Local => "local",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct PoliticaSincronizacion {
+    pub nombre: Precedencia,
+    pub email: Precedencia,
+    pub telefono: Precedencia,
+}
+
+impl Default for PoliticaSincronizacion {
+    fn default() -> Self {
+        PoliticaSincronizacion {
+            nombre: Precedencia::Corp,
+            email: Precedencia::Corp,
+            telefono: Precedencia::Corp,
+        }
+    }
+}
+
+impl PoliticaSincronizacion {
+    // "nombre=corp,email=local,telefono=corp"; los campos que no aparecen son corp
+    pub fn parse(texto: &str) -> Result<Self, String> {
+        let mut politica = PoliticaSincronizacion::default();
+        for par in texto.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (campo, valor) = par
+                .split_once('=')
+                .ok_or_else(|| format!("se esperaba campo=corp|local: {}", par))?;
+            let precedencia = match valor.trim() {
+                "corp" => Precedencia::Corp,
+                "local" => Precedencia::Local,
+                otro => return Err(format!("precedencia no válida: {}", otro)),
+            };
+            match campo.trim() {
+                CAMPO_NOMBRE => politica.nombre = precedencia,
+                CAMPO_EMAIL => politica.email = precedencia,
+                CAMPO_TELEFONO => politica.telefono = precedencia,
+                otro => return Err(format!("campo no válido: {}", otro)),
+            }
+        }
+        Ok(politica)
+    }
+}
+
+// diferencia encontrada en la última sincronización del cliente; aplicado
+// indica si el valor corporativo sustituyó al local
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Conflicto {
+    pub id: i32,
+    pub cliente_id: i32,
+    pub user_id: i32,
+    pub campo: String,
+    pub valor_local: Option<String>,
+    pub valor_corp: String,
+    pub precedencia: String,
+    pub aplicado: bool,
+    pub fecha: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Sincronizacion {
+    pub user_id: i32,
+    pub cliente_id: Option<i32>,
+    pub last_synced_at: chrono::NaiveDateTime,
+    pub conflictos: Vec<Conflicto>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ResumenSincronizacion {
+    pub usuarios: i64,
+    pub sincronizados: i64,
+    pub no_encontrados: i64,
+    pub errores: i64,
+    pub conflictos: i64,
+    pub aplicados: i64,
+}
+
+// copia local de los datos corporativos de un usuario
+pub struct CopiaCorp {
+    pub datos: UserData,
+    pub last_synced_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub enum SincronizacionError {
+    Sqlx(sqlx::Error),
+    Corp(String),
+}
+
+impl fmt::Display for SincronizacionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SincronizacionError::Sqlx(e) => write!(f, "error de base de datos: {}", e),
+            SincronizacionError::Corp(e) => write!(f, "error del servicio corporativo: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SincronizacionError {}
+
+impl From<sqlx::Error> for SincronizacionError {
+    fn from(e: sqlx::Error) -> Self {
+        SincronizacionError::Sqlx(e)
+    }
+}
+
+const CONFLICTO_COLUMNS: &str =
+    "id, cliente_id, user_id, campo, valor_local, valor_corp, precedencia, aplicado, fecha";
+
+pub async fn postgres_get_copia_corp(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> Result<Option<CopiaCorp>, sqlx::Error> {
+    let copia: Option<(Json<UserData>, chrono::NaiveDateTime)> =
+        sqlx::query_as("SELECT datos, last_synced_at FROM corp_usuarios WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(copia.map(|(datos, last_synced_at)| CopiaCorp {
+        datos: datos.0,
+        last_synced_at,
+    }))
+}
+
+async fn guardar_copia<'e, E>(
+    executor: E,
+    user_id: i32,
+    datos: &UserData,
+) -> Result<chrono::NaiveDateTime, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_scalar(
+        "
+        INSERT INTO corp_usuarios (user_id, datos, last_synced_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP)
+        ON CONFLICT (user_id) DO UPDATE
+        SET datos = EXCLUDED.datos, last_synced_at = EXCLUDED.last_synced_at
+        RETURNING last_synced_at",
+    )
+    .bind(user_id)
+    .bind(Json(datos))
+    .fetch_one(executor)
+    .await
+}
+
+// guarda la copia sin tocar el cliente; la reconciliación la hace la sincronización
+pub async fn postgres_guardar_copia_corp(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    datos: &UserData,
+) -> Result<chrono::NaiveDateTime, sqlx::Error> {
+    guardar_copia(pool, user_id, datos).await
+}
+
+// valor corporativo de cada campo; vacío es que el directorio no lo tiene
+fn valores_corp(datos: &UserData) -> [(&'static str, String); 3] {
+    let persona = &datos.person;
+    [
+        (
+            CAMPO_NOMBRE,
+            format!("{} {}", persona.nombre.trim(), persona.apellidos.trim())
+                .trim()
+                .to_string(),
+        ),
+        (CAMPO_EMAIL, persona.email.trim().to_lowercase()),
+        (CAMPO_TELEFONO, persona.telefono.trim().to_string()),
+    ]
+}
+
+// Guarda la copia de los datos corporativos y reconcilia nombre, email y
+// teléfono del cliente vinculado según la política. El informe de conflictos
+// del cliente se sustituye por las diferencias de esta pasada.
+pub async fn postgres_sincronizar_usuario(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+    datos: &UserData,
+    politica: &PoliticaSincronizacion,
+) -> Result<Sincronizacion, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let last_synced_at = guardar_copia(&mut *tx, user_id, datos).await?;
+
+    let cliente = sqlx::query_as::<_, Cliente>(&format!(
+        "SELECT {} FROM clientes WHERE user_id = $1 AND deleted_at IS NULL FOR UPDATE",
+        CLIENTE_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(cliente) = cliente else {
+        tx.commit().await?;
+        return Ok(Sincronizacion {
+            user_id,
+            cliente_id: None,
+            last_synced_at,
+            conflictos: Vec::new(),
+        });
+    };
+
+    let mut nombre = cliente.nombre.clone();
+    let mut email = cliente.email.clone();
+    let mut telefono = cliente.telefono.clone();
+    let mut diferencias: Vec<(&str, Option<String>, String, Precedencia, bool)> = Vec::new();
+
+    for (campo, valor_corp) in valores_corp(datos) {
+        let (local, precedencia, maximo) = match campo {
+            CAMPO_NOMBRE => (Some(cliente.nombre.clone()), politica.nombre, 100),
+            CAMPO_EMAIL => (Some(cliente.email.clone()), politica.email, 100),
+            _ => (cliente.telefono.clone(), politica.telefono, 20),
+        };
+        let igual = match (campo, &local) {
+            (CAMPO_EMAIL, Some(local)) => local.trim().eq_ignore_ascii_case(&valor_corp),
+            (_, Some(local)) => local.trim() == valor_corp,
+            (_, None) => false,
+        };
+        if valor_corp.is_empty() || igual {
+            continue;
+        }
+
+        // un valor que no cabe o un email de otro cliente se queda en el informe sin aplicar
+        let mut aplicable = valor_corp.chars().count() <= maximo;
+        if campo == CAMPO_EMAIL && aplicable {
+            let ocupado: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM clientes WHERE lower(email) = $1 AND id <> $2)",
+            )
+            .bind(&valor_corp)
+            .bind(cliente.id)
+            .fetch_one(&mut *tx)
+            .await?;
+            aplicable = !ocupado;
+        }
+        let aplicado = precedencia == Precedencia::Corp && aplicable;
+        if aplicado {
+            match campo {
+                CAMPO_NOMBRE => nombre = valor_corp.clone(),
+                CAMPO_EMAIL => email = valor_corp.clone(),
+                _ => telefono = Some(valor_corp.clone()),
+            }
+        }
+        diferencias.push((campo, local, valor_corp, precedencia, aplicado));
+    }
+
+    if diferencias.iter().any(|d| d.4) {
+        sqlx::query("UPDATE clientes SET nombre = $1, email = $2, telefono = $3 WHERE id = $4")
+            .bind(&nombre)
+            .bind(&email)
+            .bind(&telefono)
+            .bind(cliente.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query("DELETE FROM corp_conflictos WHERE cliente_id = $1")
+        .bind(cliente.id)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut conflictos = Vec::new();
+    for (campo, valor_local, valor_corp, precedencia, aplicado) in diferencias {
+        let conflicto = sqlx::query_as::<_, Conflicto>(&format!(
+            "
+            INSERT INTO corp_conflictos
+                (cliente_id, user_id, campo, valor_local, valor_corp, precedencia, aplicado)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING {}",
+            CONFLICTO_COLUMNS
+        ))
+        .bind(cliente.id)
+        .bind(user_id)
+        .bind(campo)
+        .bind(valor_local)
+        .bind(valor_corp)
+        .bind(precedencia.as_str())
+        .bind(aplicado)
+        .fetch_one(&mut *tx)
+        .await?;
+        conflictos.push(conflicto);
+    }
+
+    tx.commit().await?;
+
+    Ok(Sincronizacion {
+        user_id,
+        cliente_id: Some(cliente.id),
+        last_synced_at,
+        conflictos,
+    })
+}
+
+// el usuario ya no está en el directorio: se descarta la copia local
+pub async fn postgres_borrar_copia_corp(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    user_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM corp_usuarios WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// informe de conflictos de la última sincronización, de todos los clientes o de uno
+pub async fn postgres_get_conflictos(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: Option<i32>,
+) -> Result<Vec<Conflicto>, sqlx::Error> {
+    let conflictos = sqlx::query_as::<_, Conflicto>(&format!(
+        "SELECT {}
+        FROM corp_conflictos
+        WHERE $1::INT IS NULL OR cliente_id = $1
+        ORDER BY cliente_id, campo",
+        CONFLICTO_COLUMNS
+    ))
+    .bind(cliente_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(conflictos)
+}
+
+// None si el usuario no existe en el directorio corporativo. Siempre consulta
+// el directorio: sincronizar con datos de la caché podría usar datos caducados.
+pub async fn sincronizar_usuario(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cache: &CorpCache,
+    user_id: i32,
+    politica: &PoliticaSincronizacion,
+) -> Result<Option<Sincronizacion>, SincronizacionError> {
+    let datos = cache
+        .refrescar(user_id)
+        .await
+        .map_err(|e| SincronizacionError::Corp(e.to_string()))?;
+
+    match datos {
+        Some(datos) => Ok(Some(
+            postgres_sincronizar_usuario(pool, user_id, &datos, politica).await?,
+        )),
+        None => {
+            postgres_borrar_copia_corp(pool, user_id).await?;
+            Ok(None)
+        }
+    }
+}
+
+// sincroniza todos los clientes activos con cuenta de auth; un fallo con un
+// usuario se cuenta y se sigue con el siguiente
+pub async fn sincronizar_todos(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cache: &CorpCache,
+    politica: &PoliticaSincronizacion,
+) -> Result<ResumenSincronizacion, sqlx::Error> {
+    let user_ids: Vec<i32> = sqlx::query_scalar(
+        "SELECT user_id FROM clientes
+        WHERE user_id IS NOT NULL AND deleted_at IS NULL
+        ORDER BY user_id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut resumen = ResumenSincronizacion {
+        usuarios: user_ids.len() as i64,
+        ..Default::default()
+    };
+    for user_id in user_ids {
+        match sincronizar_usuario(pool, cache, user_id, politica).await {
+            Ok(Some(sincronizacion)) => {
+                resumen.sincronizados += 1;
+                resumen.conflictos += sincronizacion.conflictos.len() as i64;
+                resumen.aplicados += sincronizacion
+                    .conflictos
+                    .iter()
+                    .filter(|c| c.aplicado)
+                    .count() as i64;
+            }
+            Ok(None) => resumen.no_encontrados += 1,
+            Err(e) => {
+                eprintln!("Error synchronizing user {}: {:?}", user_id, e);
+                resumen.errores += 1;
+            }
+        }
+    }
+
+    Ok(resumen)
+}
+
+// job en segundo plano que sincroniza con el directorio corporativo cada `intervalo`
+pub fn lanzar_job_sincronizacion(
+    pool: sqlx::Pool<sqlx::Postgres>,
+    cache: CorpCache,
+    politica: PoliticaSincronizacion,
+    intervalo: std::time::Duration,
+) {
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(intervalo);
+        loop {
+            ticker.tick().await;
+            match sincronizar_todos(&pool, &cache, &politica).await {
+                Ok(resumen) => println!(
+                    "Corp sync: {} synchronized, {} not found, {} errors, {} conflicts",
+                    resumen.sincronizados,
+                    resumen.no_encontrados,
+                    resumen.errores,
+                    resumen.conflictos
+                ),
+                Err(e) => eprintln!("Error synchronizing with corp directory: {:?}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn politica_vacia_es_toda_corp() {
+        for texto in ["", " ", ",,"] {
+            let politica = PoliticaSincronizacion::parse(texto).unwrap();
+            assert_eq!(politica.nombre, Precedencia::Corp);
+            assert_eq!(politica.email, Precedencia::Corp);
+            assert_eq!(politica.telefono, Precedencia::Corp);
+        }
+    }
+
+    #[test]
+    fn parsea_la_precedencia_de_cada_campo() {
+        let politica = PoliticaSincronizacion::parse(" email = local , telefono=corp").unwrap();
+        assert_eq!(politica.nombre, Precedencia::Corp);
+        assert_eq!(politica.email, Precedencia::Local);
+        assert_eq!(politica.telefono, Precedencia::Corp);
+
+        let politica = PoliticaSincronizacion::parse("nombre=local,nombre=corp").unwrap();
+        assert_eq!(politica.nombre, Precedencia::Corp);
+    }
+
+    #[test]
+    fn rechaza_politicas_mal_formadas() {
+        assert!(PoliticaSincronizacion::parse("email").is_err());
+        assert!(PoliticaSincronizacion::parse("email=remota").is_err());
+        assert!(PoliticaSincronizacion::parse("email=Local").is_err());
+        assert!(PoliticaSincronizacion::parse("direccion=corp").is_err());
+    }
+}
@@ -0,0 +1,481 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+use crate::notificaciones::NOTIFICACION_TAREA_VENCIDA;
+
+pub const ESTADO_PENDIENTE: &str = "pendiente";
+pub const ESTADO_EN_CURSO: &str = "en_curso";
+pub const ESTADO_COMPLETADA: &str = "completada";
+pub const ESTADO_CANCELADA: &str = "cancelada";
+
+pub const ESTADOS_TAREA: [&str; 4] = [
+    ESTADO_PENDIENTE,
+    ESTADO_EN_CURSO,
+    ESTADO_COMPLETADA,
+    ESTADO_CANCELADA,
+];
+
+// solo las tareas abiertas pueden vencer
+pub const ESTADOS_ABIERTOS: [&str; 2] = [ESTADO_PENDIENTE, ESTADO_EN_CURSO];
+
+pub const PRIORIDADES: [&str; 3] = ["baja", "media", "alta"];
+
+pub fn validate_estado_tarea(estado: &str) -> Result<(), ValidationError> {
+    if !ESTADOS_TAREA.contains(&estado) {
+        return Err(ValidationError::new("estado")
+            .with_message("debe ser pendiente, en_curso, completada o cancelada".into()));
+    }
+    Ok(())
+}
+
+pub fn validate_prioridad(prioridad: &str) -> Result<(), ValidationError> {
+    if !PRIORIDADES.contains(&prioridad) {
+        return Err(
+            ValidationError::new("prioridad").with_message("debe ser baja, media o alta".into())
+        );
+    }
+    Ok(())
+}
+
+fn prioridad_por_defecto() -> String {
+    "media".to_string()
+}
+
+fn estado_por_defecto() -> String {
+    ESTADO_PENDIENTE.to_string()
+}
+
+// los vínculos son opcionales, como en issue_request_*; al editar se reemplazan
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct TareaRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(min = 1, max = 200, message = "entre 1 y 200 caracteres"))]
+    pub titulo: String,
+    #[serde(default, deserialize_with = "crate::validacion::trimmed")]
+    #[validate(length(max = 10000, message = "máximo 10000 caracteres"))]
+    pub descripcion: Option<String>,
+    // por defecto quien crea la tarea
+    pub asignado_user_id: Option<i32>,
+    pub fecha_vencimiento: Option<chrono::NaiveDateTime>,
+    #[serde(
+        default = "prioridad_por_defecto",
+        deserialize_with = "crate::validacion::trimmed"
+    )]
+    #[validate(custom(function = "validate_prioridad"))]
+    pub prioridad: String,
+    #[serde(
+        default = "estado_por_defecto",
+        deserialize_with = "crate::validacion::trimmed"
+    )]
+    #[validate(custom(function = "validate_estado_tarea"))]
+    pub estado: String,
+    pub cliente_id: Option<i32>,
+    pub articulo_id: Option<i32>,
+    pub pedido_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct TareaEstadoRequest {
+    #[serde(deserialize_with = "crate::validacion::trimmed")]
+    #[validate(custom(function = "validate_estado_tarea"))]
+    pub estado: String,
+}
+
+// vencida_en lo marca el job de tareas vencidas al notificar al asignado
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Tarea {
+    pub id: i32,
+    pub titulo: String,
+    pub descripcion: Option<String>,
+    pub asignado_user_id: i32,
+    pub creador_user_id: i32,
+    pub prioridad: String,
+    pub estado: String,
+    pub fecha_vencimiento: Option<chrono::NaiveDateTime>,
+    pub vencida_en: Option<chrono::NaiveDateTime>,
+    pub cliente_id: Option<i32>,
+    pub articulo_id: Option<i32>,
+    pub pedido_id: Option<i32>,
+    pub fecha_creacion: chrono::NaiveDateTime,
+    pub fecha_modificacion: Option<chrono::NaiveDateTime>,
+}
+
+impl Tarea {
+    pub fn visible_para(&self, user_id: i32) -> bool {
+        self.asignado_user_id == user_id || self.creador_user_id == user_id
+    }
+}
+
+// filtro ?due= del listado
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vencimiento {
+    Overdue,
+    Today,
+    Week,
+}
+
+impl Vencimiento {
+    pub fn from_param(due: &str) -> Option<Self> {
+        match due {
+            "overdue" => Some(Vencimiento::Overdue),
+            "today" => Some(Vencimiento::Today),
+            "week" => Some(Vencimiento::Week),
+            _ => None,
+        }
+    }
+
+    // condición sobre la tabla t; las abiertas se pasan en $1
+    fn condicion(self) -> &'static str {
+        match self {
+            Vencimiento::Overdue => {
+                "t.estado = ANY($1) AND t.fecha_vencimiento < CURRENT_TIMESTAMP"
+            }
+            Vencimiento::Today => "t.estado = ANY($1) AND t.fecha_vencimiento::DATE = CURRENT_DATE",
+            Vencimiento::Week => {
+                "t.estado = ANY($1) AND t.fecha_vencimiento >= CURRENT_TIMESTAMP
+                AND t.fecha_vencimiento < CURRENT_TIMESTAMP + INTERVAL '7 days'"
+            }
+        }
+    }
+}
+
+const TAREA_SELECT: &str = "
+    SELECT
+        t.id, t.titulo, t.descripcion, t.asignado_user_id, t.creador_user_id, t.prioridad,
+        t.estado, t.fecha_vencimiento, t.vencida_en, tc.cliente_id, ta.articulo_id,
+        tp.pedido_id, t.fecha_creacion, t.fecha_modificacion
+    FROM tareas t
+    LEFT JOIN tareas_clientes tc ON tc.tarea_id = t.id
+    LEFT JOIN tareas_articulos ta ON ta.tarea_id = t.id
+    LEFT JOIN tareas_pedidos tp ON tp.tarea_id = t.id";
+
+// primero las que vencen antes y, a igual vencimiento, las de más prioridad
+const TAREA_ORDER: &str = "
+    ORDER BY t.fecha_vencimiento ASC NULLS LAST,
+        CASE t.prioridad WHEN 'alta' THEN 0 WHEN 'media' THEN 1 ELSE 2 END, t.id";
+
+// asignado_user_id None lista las de todos los usuarios
+pub async fn postgres_get_tareas<'e, E>(
+    executor: E,
+    asignado_user_id: Option<i32>,
+    vencimiento: Option<Vencimiento>,
+    estado: Option<&str>,
+) -> Result<Vec<Tarea>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let tareas = sqlx::query_as::<_, Tarea>(&format!(
+        "{}
+        WHERE ($2::INT IS NULL OR t.asignado_user_id = $2)
+        AND ($3::TEXT IS NULL OR t.estado = $3)
+        AND {}
+        {}",
+        TAREA_SELECT,
+        vencimiento.map_or("TRUE", Vencimiento::condicion),
+        TAREA_ORDER
+    ))
+    .bind(&ESTADOS_ABIERTOS[..])
+    .bind(asignado_user_id)
+    .bind(estado)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(tareas)
+}
+
+pub async fn postgres_get_tarea_by_id<'e, E>(
+    executor: E,
+    id: i32,
+) -> Result<Option<Tarea>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let tarea = sqlx::query_as::<_, Tarea>(&format!("{} WHERE t.id = $1", TAREA_SELECT))
+        .bind(id)
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(tarea)
+}
+
+async fn insertar_vinculos(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    tarea_id: i32,
+    tarea: &TareaRequest,
+) -> Result<(), sqlx::Error> {
+    if let Some(cliente_id) = tarea.cliente_id {
+        sqlx::query("INSERT INTO tareas_clientes (tarea_id, cliente_id) VALUES ($1, $2)")
+            .bind(tarea_id)
+            .bind(cliente_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+    if let Some(articulo_id) = tarea.articulo_id {
+        sqlx::query("INSERT INTO tareas_articulos (tarea_id, articulo_id) VALUES ($1, $2)")
+            .bind(tarea_id)
+            .bind(articulo_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+    if let Some(pedido_id) = tarea.pedido_id {
+        sqlx::query("INSERT INTO tareas_pedidos (tarea_id, pedido_id) VALUES ($1, $2)")
+            .bind(tarea_id)
+            .bind(pedido_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+// un vínculo a un cliente, artículo o pedido inexistente falla con foreign key violation
+pub async fn postgres_create_tarea(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    creador_user_id: i32,
+    tarea: TareaRequest,
+) -> Result<Tarea, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let tarea_id: i32 = sqlx::query_scalar(
+        "
+        INSERT INTO tareas (
+            titulo, descripcion, asignado_user_id, creador_user_id, prioridad, estado,
+            fecha_vencimiento
+        )
+        VALUES ($1, $2, COALESCE($3, $4), $4, $5, $6, $7)
+        RETURNING id",
+    )
+    .bind(&tarea.titulo)
+    .bind(&tarea.descripcion)
+    .bind(tarea.asignado_user_id)
+    .bind(creador_user_id)
+    .bind(&tarea.prioridad)
+    .bind(&tarea.estado)
+    .bind(tarea.fecha_vencimiento)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    insertar_vinculos(&mut tx, tarea_id, &tarea).await?;
+
+    let new_tarea = postgres_get_tarea_by_id(&mut *tx, tarea_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+    tx.commit().await?;
+
+    Ok(new_tarea)
+}
+
+// Reemplaza la tarea y sus vínculos. Si la nueva fecha de vencimiento ya no
+// ha pasado se limpia vencida_en para que el job vuelva a avisar.
+pub async fn postgres_update_tarea(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    tarea: TareaRequest,
+) -> Result<Option<Tarea>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let updated = sqlx::query(
+        "
+        UPDATE tareas
+        SET titulo = $1, descripcion = $2, asignado_user_id = COALESCE($3, asignado_user_id),
+            prioridad = $4, estado = $5, fecha_vencimiento = $6,
+            vencida_en = CASE WHEN $6 < CURRENT_TIMESTAMP THEN vencida_en END,
+            fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $7",
+    )
+    .bind(&tarea.titulo)
+    .bind(&tarea.descripcion)
+    .bind(tarea.asignado_user_id)
+    .bind(&tarea.prioridad)
+    .bind(&tarea.estado)
+    .bind(tarea.fecha_vencimiento)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    for tabla in ["tareas_clientes", "tareas_articulos", "tareas_pedidos"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE tarea_id = $1", tabla))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    insertar_vinculos(&mut tx, id, &tarea).await?;
+
+    let updated_tarea = postgres_get_tarea_by_id(&mut *tx, id).await?;
+
+    tx.commit().await?;
+
+    Ok(updated_tarea)
+}
+
+pub async fn postgres_update_tarea_estado(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+    estado: &str,
+) -> Result<Option<Tarea>, sqlx::Error> {
+    let updated = sqlx::query(
+        "
+        UPDATE tareas
+        SET estado = $1, fecha_modificacion = CURRENT_TIMESTAMP
+        WHERE id = $2",
+    )
+    .bind(estado)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    postgres_get_tarea_by_id(pool, id).await
+}
+
+pub async fn postgres_delete_tarea(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM tareas WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Marca las tareas abiertas cuyo vencimiento ya pasó y deja una notificación
+// al asignado en la misma sentencia, así cada tarea se notifica una sola vez.
+pub async fn postgres_marcar_tareas_vencidas(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "
+        WITH vencidas AS (
+            UPDATE tareas
+            SET vencida_en = CURRENT_TIMESTAMP
+            WHERE vencida_en IS NULL
+            AND estado = ANY($1)
+            AND fecha_vencimiento < CURRENT_TIMESTAMP
+            RETURNING id, asignado_user_id, titulo
+        )
+        INSERT INTO notificaciones (user_id, tipo, mensaje, tarea_id)
+        SELECT asignado_user_id, $2, 'La tarea \"' || titulo || '\" ha vencido', id
+        FROM vencidas",
+    )
+    .bind(&ESTADOS_ABIERTOS[..])
+    .bind(NOTIFICACION_TAREA_VENCIDA)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// job en segundo plano que revisa las tareas vencidas cada `intervalo`
+pub fn lanzar_job_vencidas(pool: sqlx::Pool<sqlx::Postgres>, intervalo: std::time::Duration) {
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(intervalo);
+        loop {
+            ticker.tick().await;
+            match postgres_marcar_tareas_vencidas(&pool).await {
+                Ok(0) => {}
+                Ok(vencidas) => println!("Marked {} overdue tasks", vencidas),
+                Err(e) => eprintln!("Error marking overdue tasks: {:?}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgresini::pruebas::pool_de_pruebas;
+
+    #[test]
+    fn vencimiento_desde_el_parametro() {
+        assert_eq!(
+            Vencimiento::from_param("overdue"),
+            Some(Vencimiento::Overdue)
+        );
+        assert_eq!(Vencimiento::from_param("today"), Some(Vencimiento::Today));
+        assert_eq!(Vencimiento::from_param("week"), Some(Vencimiento::Week));
+        assert_eq!(Vencimiento::from_param("Today"), None);
+        assert_eq!(Vencimiento::from_param(""), None);
+        assert_eq!(Vencimiento::from_param("month"), None);
+    }
+
+    async fn crear_tarea(
+        conn: &mut sqlx::PgConnection,
+        titulo: &str,
+        estado: &str,
+        vencimiento: &str,
+    ) -> i32 {
+        sqlx::query_scalar(&format!(
+            "INSERT INTO tareas (titulo, asignado_user_id, creador_user_id, estado, fecha_vencimiento)
+            VALUES ($1, 1, 1, $2, {})
+            RETURNING id",
+            vencimiento
+        ))
+        .bind(titulo)
+        .bind(estado)
+        .fetch_one(conn)
+        .await
+        .unwrap()
+    }
+
+    async fn ids(conn: &mut sqlx::PgConnection, vencimiento: Vencimiento) -> Vec<i32> {
+        postgres_get_tareas(conn, Some(1), Some(vencimiento), None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    // todo en una transacción: CURRENT_TIMESTAMP es el mismo en los datos y en
+    // los filtros, así que el resultado no depende de la hora a la que se ejecute
+    #[rocket::async_test]
+    #[ignore = "necesita TEST_DATABASE_URL"]
+    async fn filtra_por_vencimiento() {
+        let (_guard, pool) = pool_de_pruebas().await;
+        let mut tx = pool.begin().await.unwrap();
+        let vencida = crear_tarea(
+            &mut tx,
+            "vencida",
+            ESTADO_PENDIENTE,
+            "CURRENT_TIMESTAMP - INTERVAL '1 day'",
+        )
+        .await;
+        crear_tarea(
+            &mut tx,
+            "completada",
+            ESTADO_COMPLETADA,
+            "CURRENT_TIMESTAMP - INTERVAL '1 day'",
+        )
+        .await;
+        let hoy = crear_tarea(&mut tx, "hoy", ESTADO_EN_CURSO, "CURRENT_TIMESTAMP").await;
+        let semana = crear_tarea(
+            &mut tx,
+            "semana",
+            ESTADO_PENDIENTE,
+            "CURRENT_TIMESTAMP + INTERVAL '3 days'",
+        )
+        .await;
+        crear_tarea(
+            &mut tx,
+            "mes",
+            ESTADO_PENDIENTE,
+            "CURRENT_TIMESTAMP + INTERVAL '10 days'",
+        )
+        .await;
+        crear_tarea(&mut tx, "sin fecha", ESTADO_PENDIENTE, "NULL").await;
+
+        assert_eq!(ids(&mut tx, Vencimiento::Overdue).await, vec![vencida]);
+        assert_eq!(ids(&mut tx, Vencimiento::Today).await, vec![hoy]);
+        assert_eq!(ids(&mut tx, Vencimiento::Week).await, vec![hoy, semana]);
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::duplicados::sql_ids_fusionados;
+use crate::money::Money;
+use crate::paginacion::Paginacion;
+
+pub const EVENTO_PEDIDO_CREADO: &str = "pedido_creado";
+pub const EVENTO_PEDIDO_ESTADO: &str = "pedido_estado";
+pub const EVENTO_FACTURA_EMITIDA: &str = "factura_emitida";
+pub const EVENTO_INCIDENCIA: &str = "incidencia";
+pub const EVENTO_PAGO: &str = "pago";
+pub const EVENTO_PERFIL_EDITADO: &str = "perfil_editado";
+pub const EVENTO_INTERACCION: &str = "interaccion";
+
+pub const TIPOS_EVENTO: [&str; 7] = [
+    EVENTO_PEDIDO_CREADO,
+    EVENTO_PEDIDO_ESTADO,
+    EVENTO_FACTURA_EMITIDA,
+    EVENTO_INCIDENCIA,
+    EVENTO_PAGO,
+    EVENTO_PERFIL_EDITADO,
+    EVENTO_INTERACCION,
+];
+
+pub const POR_PAGINA_POR_DEFECTO: i64 = 50;
+pub const POR_PAGINA_MAXIMO: i64 = 200;
+
+// referencia_id apunta a la entidad del evento (pedido, factura, pago...)
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct Evento {
+    pub tipo: String,
+    pub fecha: chrono::NaiveDateTime,
+    pub referencia_id: i32,
+    pub datos: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Timeline {
+    pub eventos: Vec<Evento>,
+    pub pagina: i64,
+    pub por_pagina: i64,
+    pub total: i64,
+}
+
+// importe en unidades menores con su moneda; se formatea con Money al leerlo
+fn importe_json(columna: &str, moneda: &str) -> String {
+    format!(
+        "jsonb_build_object('amount', {}, 'currency', {})",
+        columna, moneda
+    )
+}
+
+// campo de datos que lleva un importe, según el tipo de evento
+fn campo_importe(tipo: &str) -> Option<&'static str> {
+    match tipo {
+        EVENTO_PEDIDO_CREADO | EVENTO_FACTURA_EMITIDA => Some("total"),
+        EVENTO_PAGO => Some("importe"),
+        _ => None,
+    }
+}
+
+// {"amount": 120000, "currency": "EUR"} -> {"amount": "1200.00", "currency": "EUR"}
+fn formatear_importe(evento: &mut Evento) -> Result<(), serde_json::Error> {
+    if let Some(campo) = campo_importe(&evento.tipo)
+        && let Some(valor) = evento.datos.get_mut(campo)
+    {
+        let importe: Money = serde_json::from_value(valor.take())?;
+        *valor = serde_json::to_value(importe)?;
+    }
+    Ok(())
+}
+
+// Todos los eventos del cliente $1 como (tipo, fecha, referencia_id, datos). Las
+// interacciones son notas internas y solo se incluyen si $2 es true. Las
+// facturas de los clientes fusionados en él también son suyas.
+fn eventos_cliente() -> String {
+    format!(
+        "
+        SELECT '{pedido_creado}' AS tipo, p.fecha_pedido AS fecha, p.id AS referencia_id,
+            jsonb_build_object('total', {total_pedido}) AS datos
+        FROM pedidos p
+        WHERE p.cliente_id = $1
+        UNION ALL
+        SELECT '{pedido_estado}', h.fecha, h.pedido_id,
+            jsonb_build_object('estado_anterior', h.estado_anterior, 'estado', h.estado)
+        FROM pedidos_historial h
+        JOIN pedidos p ON p.id = h.pedido_id
+        WHERE p.cliente_id = $1
+        UNION ALL
+        SELECT '{factura_emitida}', f.fecha_emision, f.id,
+            jsonb_build_object(
+                'codigo', f.codigo, 'tipo', f.tipo, 'pedido_id', f.pedido_id,
+                'total', {total_factura}
+            )
+        FROM facturas f
+        WHERE f.cliente_id IN ({ids_cliente})
+        UNION ALL
+        SELECT '{incidencia}', ir.fecha_creacion, ir.id, ir.data
+        FROM issue_request ir
+        WHERE ir.id IN (
+            SELECT issue_request_id FROM issue_request_clientes WHERE cliente_id = $1
+            UNION
+            SELECT irp.issue_request_id
+            FROM issue_request_pedidos irp
+            JOIN pedidos p ON p.id = irp.pedido_id
+            WHERE p.cliente_id = $1
+        )
+        UNION ALL
+        SELECT '{pago}', pg.fecha_creacion, pg.id,
+            jsonb_build_object(
+                'pedido_id', pg.pedido_id, 'factura_id', pg.factura_id, 'metodo', pg.metodo,
+                'importe', {importe_pago}, 'fecha_pago', pg.fecha, 'referencia', pg.referencia
+            )
+        FROM pagos pg
+        WHERE pg.cliente_id = $1
+        UNION ALL
+        SELECT '{perfil_editado}', ch.fecha, ch.id, ch.cambios
+        FROM clientes_historial ch
+        WHERE ch.cliente_id = $1
+        UNION ALL
+        SELECT '{interaccion}', i.fecha, i.id,
+            jsonb_build_object(
+                'tipo', i.tipo, 'autor_user_id', i.autor_user_id, 'cuerpo', i.cuerpo,
+                'fecha_seguimiento', i.fecha_seguimiento
+            )
+        FROM interacciones i
+        WHERE i.cliente_id = $1 AND $2::BOOLEAN",
+        pedido_creado = EVENTO_PEDIDO_CREADO,
+        pedido_estado = EVENTO_PEDIDO_ESTADO,
+        factura_emitida = EVENTO_FACTURA_EMITIDA,
+        incidencia = EVENTO_INCIDENCIA,
+        pago = EVENTO_PAGO,
+        perfil_editado = EVENTO_PERFIL_EDITADO,
+        interaccion = EVENTO_INTERACCION,
+        total_pedido = importe_json("p.total", "p.moneda"),
+        total_factura = importe_json("f.total", "f.moneda"),
+        importe_pago = importe_json("pg.importe", "pg.moneda"),
+        ids_cliente = sql_ids_fusionados("$1"),
+    )
+}
+
+// página de eventos del cliente, del más reciente al más antiguo, opcionalmente de un solo tipo
+pub async fn postgres_get_timeline(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cliente_id: i32,
+    incluir_interacciones: bool,
+    tipo: Option<&str>,
+    paginacion: &Paginacion,
+) -> Result<Timeline, sqlx::Error> {
+    let eventos_cliente = eventos_cliente();
+
+    let total: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM ({}) e WHERE $3::TEXT IS NULL OR e.tipo = $3",
+        eventos_cliente
+    ))
+    .bind(cliente_id)
+    .bind(incluir_interacciones)
+    .bind(tipo)
+    .fetch_one(pool)
+    .await?;
+
+    let mut eventos = sqlx::query_as::<_, Evento>(&format!(
+        "
+        SELECT e.tipo, e.fecha, e.referencia_id, e.datos
+        FROM ({}) e
+        WHERE $3::TEXT IS NULL OR e.tipo = $3
+        ORDER BY e.fecha DESC, e.tipo, e.referencia_id DESC
+        LIMIT $4 OFFSET $5",
+        eventos_cliente
+    ))
+    .bind(cliente_id)
+    .bind(incluir_interacciones)
+    .bind(tipo)
+    .bind(paginacion.por_pagina())
+    .bind(paginacion.offset())
+    .fetch_all(pool)
+    .await?;
+
+    for evento in &mut eventos {
+        formatear_importe(evento).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+    }
+
+    Ok(Timeline {
+        eventos,
+        pagina: paginacion.pagina(),
+        por_pagina: paginacion.por_pagina(),
+        total,
+    })
+}
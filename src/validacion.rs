@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use rocket::data::{self, Data, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::Request;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Deserializer, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::money::Money;
+
+// errores por campo que se devuelven con el 422
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct FieldErrors(pub BTreeMap<String, Vec<String>>);
+
+impl From<&ValidationErrors> for FieldErrors {
+    fn from(errors: &ValidationErrors) -> Self {
+        let mut fields = BTreeMap::new();
+        aplanar_errores("", errors, &mut fields);
+        FieldErrors(fields)
+    }
+}
+
+// los errores de DTOs anidados se devuelven con la ruta completa, p.ej. lineas[0].cantidad
+fn aplanar_errores(
+    prefijo: &str,
+    errors: &ValidationErrors,
+    fields: &mut BTreeMap<String, Vec<String>>,
+) {
+    for (field, kind) in errors.errors() {
+        let ruta = if prefijo.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefijo, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                let messages = errors
+                    .iter()
+                    .map(|e| {
+                        e.message
+                            .as_ref()
+                            .map(|m| m.to_string())
+                            .unwrap_or_else(|| e.code.to_string())
+                    })
+                    .collect();
+                fields.insert(ruta, messages);
+            }
+            ValidationErrorsKind::Struct(errors) => aplanar_errores(&ruta, errors, fields),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    aplanar_errores(&format!("{}[{}]", ruta, index), errors, fields);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ValidationErrorResponse {
+    pub status: String,
+    pub errors: FieldErrors,
+}
+
+// Data guard: parsea el JSON y valida el DTO. Si falla deja los errores en la
+// caché de la petición para que el catcher 422 los devuelva.
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T> FromData<'r> for Validated<T>
+where
+    T: Deserialize<'r> + Validate,
+{
+    type Error = FieldErrors;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        match Json::<T>::from_data(req, data).await {
+            Outcome::Success(value) => {
+                let value = value.into_inner();
+                match value.validate() {
+                    Ok(()) => Outcome::Success(Validated(value)),
+                    Err(errors) => {
+                        let errors = req.local_cache(|| FieldErrors::from(&errors)).clone();
+                        Outcome::Error((Status::UnprocessableEntity, errors))
+                    }
+                }
+            }
+            Outcome::Error((status, e)) => {
+                let mut fields = BTreeMap::new();
+                fields.insert("body".to_string(), vec![e.to_string()]);
+                let errors = req.local_cache(|| FieldErrors(fields)).clone();
+                Outcome::Error((status, errors))
+            }
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+pub trait Trim {
+    fn trim(self) -> Self;
+}
+
+impl Trim for String {
+    fn trim(self) -> Self {
+        let trimmed = str::trim(&self);
+        if trimmed.len() == self.len() {
+            self
+        } else {
+            trimmed.to_string()
+        }
+    }
+}
+
+impl<T: Trim> Trim for Option<T> {
+    fn trim(self) -> Self {
+        self.map(Trim::trim)
+    }
+}
+
+// #[serde(deserialize_with = "validacion::trimmed")] quita los espacios de los extremos
+pub fn trimmed<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de> + Trim,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Trim::trim)
+}
+
+// dígitos con +, espacios, guiones, puntos y paréntesis
+pub fn validate_telefono(telefono: &str) -> Result<(), ValidationError> {
+    let valid_chars = telefono
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '+' | ' ' | '-' | '.' | '(' | ')'));
+    let digits = telefono.chars().filter(|c| c.is_ascii_digit()).count();
+    if !valid_chars || !(6..=15).contains(&digits) {
+        return Err(
+            ValidationError::new("telefono").with_message("formato de teléfono no válido".into())
+        );
+    }
+    Ok(())
+}
+
+pub fn validate_non_negative_money(importe: &Money) -> Result<(), ValidationError> {
+    if importe.is_negative() {
+        return Err(ValidationError::new("range").with_message("no puede ser negativo".into()));
+    }
+    Ok(())
+}